@@ -0,0 +1,50 @@
+//! Compiles `shaders/*.slang` to SPIR-V in `OUT_DIR` so the renderer can embed
+//! them with `include_bytes!`.
+//!
+//! Needs the Slang compiler, `slangc`, on the `PATH` or at `$SLANGC`.
+
+use std::path::PathBuf;
+use std::process::Command;
+
+fn main() {
+    println!("cargo::rerun-if-changed=shaders");
+    println!("cargo::rerun-if-env-changed=SLANGC");
+
+    let out_dir = PathBuf::from(std::env::var_os("OUT_DIR").expect("OUT_DIR is set by cargo"));
+    let slangc = std::env::var_os("SLANGC").unwrap_or_else(|| "slangc".into());
+
+    for entry in std::fs::read_dir("shaders").expect("shaders directory") {
+        let source = entry.expect("shaders directory entry").path();
+        if source.extension().is_none_or(|ext| ext != "slang") {
+            continue;
+        }
+        println!("cargo::rerun-if-changed={}", source.display());
+
+        let output = out_dir
+            .join(source.file_stem().expect("shader file name"))
+            .with_extension("spv");
+
+        let status = Command::new(&slangc)
+            .arg(&source)
+            .args(["-target", "spirv", "-fvk-use-entrypoint-name", "-o"])
+            .arg(&output)
+            .status();
+        match status {
+            Ok(status) if status.success() => {}
+            Ok(_) => fail(&format!("slangc failed on {}", source.display())),
+            Err(e) => fail(&format!(
+                "could not run {}: {e}. Shaders are compiled with the Slang compiler; \
+                 install it from https://github.com/shader-slang/slang/releases and put \
+                 slangc on the PATH or point SLANGC at it, or use `nix develop`",
+                slangc.display()
+            )),
+        }
+    }
+}
+
+/// Stop the build with `message` shown as a warning, rather than a panic and
+/// its backtrace.
+fn fail(message: &str) -> ! {
+    println!("cargo::warning={message}");
+    std::process::exit(1);
+}
@@ -0,0 +1,509 @@
+//! Power budget simulation for the robot and its base.
+//!
+//! Everything here is plain data advanced by [`EnergySystem::tick`]. There is no
+//! randomness and every loop walks its vectors in a fixed order, so the same
+//! inputs always produce the same outputs.
+
+/// Watts.
+pub type Power = f32;
+/// Joules.
+pub type Energy = f32;
+
+pub mod grid;
+
+/// Temperature solar panels are rated at, in degrees Celsius.
+const SOLAR_RATED_TEMPERATURE: f32 = 25.;
+/// Share of a solar panel's output lost for each degree above its rated
+/// temperature, and gained for each degree below.
+const SOLAR_TEMPERATURE_COEFFICIENT: f32 = 0.004;
+
+/// Outside conditions that affect generator output.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Environment {
+    /// Sunlight reaching the panels, `0.0` at night and `1.0` at clear noon.
+    pub sun_intensity: f32,
+    /// Wind speed in metres per second.
+    pub wind_speed: f32,
+    /// Air temperature in degrees Celsius.
+    pub temperature: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Generator {
+    /// Constant output until its fuel runs out.
+    NuclearBattery { output: Power, remaining: Energy },
+    /// Output scales linearly with sunlight, at `peak` for clear noon at the
+    /// rated temperature. Cold panels give a little more.
+    Solar { peak: Power },
+    /// Cubic ramp between `cut_in` and `rated_speed`, shut down above `cut_out`.
+    Wind {
+        rated: Power,
+        cut_in: f32,
+        rated_speed: f32,
+        cut_out: f32,
+    },
+}
+
+impl Generator {
+    /// The robot's starting battery: it lasts for years but only trickles out power.
+    pub const fn nuclear_battery() -> Self {
+        Self::NuclearBattery {
+            output: 40.,
+            remaining: 40. * 60. * 60. * 24. * 365. * 10.,
+        }
+    }
+
+    pub fn output(&self, env: &Environment) -> Power {
+        match *self {
+            Self::NuclearBattery { output, remaining } => {
+                if remaining > 0. {
+                    output
+                } else {
+                    0.
+                }
+            }
+            Self::Solar { peak } => {
+                let derating = 1.
+                    - SOLAR_TEMPERATURE_COEFFICIENT * (env.temperature - SOLAR_RATED_TEMPERATURE);
+                peak * env.sun_intensity.clamp(0., 1.) * derating.max(0.)
+            }
+            Self::Wind {
+                rated,
+                cut_in,
+                rated_speed,
+                cut_out,
+            } => {
+                let speed = env.wind_speed;
+                if speed < cut_in || speed >= cut_out {
+                    0.
+                } else if speed >= rated_speed {
+                    rated
+                } else {
+                    let t = (speed - cut_in) / (rated_speed - cut_in);
+                    rated * t * t * t
+                }
+            }
+        }
+    }
+
+    /// Produce power for `dt` seconds, returning the energy generated.
+    fn run(&mut self, env: &Environment, dt: f32) -> Energy {
+        let generated = self.output(env) * dt;
+        if let Self::NuclearBattery { remaining, .. } = self {
+            let generated = generated.min(*remaining);
+            *remaining -= generated;
+            return generated;
+        }
+        generated
+    }
+}
+
+/// Something that can buffer energy, like a capacitor bank or the robot's cells.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Storage {
+    pub capacity: Energy,
+    pub charge: Energy,
+    pub max_charge_rate: Power,
+    pub max_discharge_rate: Power,
+}
+
+impl Storage {
+    pub const fn new(capacity: Energy, max_charge_rate: Power, max_discharge_rate: Power) -> Self {
+        Self {
+            capacity,
+            charge: 0.,
+            max_charge_rate,
+            max_discharge_rate,
+        }
+    }
+
+    #[cfg(feature = "neuro")]
+    pub fn fraction(&self) -> f32 {
+        if self.capacity > 0. {
+            self.charge / self.capacity
+        } else {
+            0.
+        }
+    }
+
+    /// Energy that could be pulled out during the next `dt` seconds.
+    fn available(&self, dt: f32) -> Energy {
+        (self.max_discharge_rate * dt).min(self.charge)
+    }
+
+    /// Energy that could be pushed in during the next `dt` seconds.
+    fn headroom(&self, dt: f32) -> Energy {
+        (self.max_charge_rate * dt).min(self.capacity - self.charge)
+    }
+}
+
+/// Higher priorities are served first during a brownout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Priority(pub u8);
+
+impl Priority {
+    pub const LOW: Self = Self(0);
+    pub const NORMAL: Self = Self(128);
+    pub const CRITICAL: Self = Self(255);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Consumer {
+    pub demand: Power,
+    pub priority: Priority,
+    /// Fraction of `demand` delivered on the last tick.
+    pub satisfaction: f32,
+}
+
+impl Consumer {
+    pub const fn new(demand: Power, priority: Priority) -> Self {
+        Self {
+            demand,
+            priority,
+            satisfaction: 0.,
+        }
+    }
+
+    pub fn is_powered(&self) -> bool {
+        self.satisfaction >= 1.
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TickReport {
+    pub generated: Energy,
+    pub consumed: Energy,
+    pub demanded: Energy,
+    pub stored: Energy,
+    pub drawn: Energy,
+    /// Surplus that could not be stored anywhere.
+    pub wasted: Energy,
+}
+
+impl TickReport {
+    pub fn unmet(&self) -> Energy {
+        self.demanded - self.consumed
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GeneratorId(usize);
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StorageId(usize);
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ConsumerId(usize);
+
+#[derive(Debug, Clone, Default)]
+pub struct EnergySystem {
+    generators: Vec<Generator>,
+    storages: Vec<Storage>,
+    consumers: Vec<Consumer>,
+}
+
+impl EnergySystem {
+    pub const fn new() -> Self {
+        Self {
+            generators: vec![],
+            storages: vec![],
+            consumers: vec![],
+        }
+    }
+
+    pub fn add_generator(&mut self, generator: Generator) -> GeneratorId {
+        self.generators.push(generator);
+        GeneratorId(self.generators.len() - 1)
+    }
+
+    pub fn add_storage(&mut self, storage: Storage) -> StorageId {
+        self.storages.push(storage);
+        StorageId(self.storages.len() - 1)
+    }
+
+    pub fn add_consumer(&mut self, consumer: Consumer) -> ConsumerId {
+        self.consumers.push(consumer);
+        ConsumerId(self.consumers.len() - 1)
+    }
+
+    pub fn generator(&self, id: GeneratorId) -> &Generator {
+        &self.generators[id.0]
+    }
+
+    pub fn storage(&self, id: StorageId) -> &Storage {
+        &self.storages[id.0]
+    }
+
+    pub fn consumer(&self, id: ConsumerId) -> &Consumer {
+        &self.consumers[id.0]
+    }
+
+    /// Advance the simulation by `dt` seconds.
+    pub fn tick(&mut self, dt: f32, env: &Environment) -> TickReport {
+        balance(
+            &mut self.generators.iter_mut().collect::<Vec<_>>(),
+            &mut self.storages.iter_mut().collect::<Vec<_>>(),
+            &mut self.consumers.iter_mut().collect::<Vec<_>>(),
+            dt,
+            env,
+        )
+    }
+}
+
+/// Run one tick of supply and demand over a single connected set of machines.
+///
+/// Generators run first, then consumers are fed in priority order from
+/// generation topped up by storage. Whatever is left over charges storage.
+pub fn balance(
+    generators: &mut [&mut Generator],
+    storages: &mut [&mut Storage],
+    consumers: &mut [&mut Consumer],
+    dt: f32,
+    env: &Environment,
+) -> TickReport {
+    let mut report = TickReport::default();
+
+    for generator in generators.iter_mut() {
+        report.generated += generator.run(env, dt);
+    }
+
+    let from_storage: Energy = storages.iter().map(|s| s.available(dt)).sum();
+    let mut budget = report.generated + from_storage;
+
+    // Stable sort so consumers sharing a priority keep insertion order.
+    let mut order: Vec<usize> = (0..consumers.len()).collect();
+    order.sort_by(|&a, &b| consumers[b].priority.cmp(&consumers[a].priority));
+
+    let mut start = 0;
+    while start < order.len() {
+        let priority = consumers[order[start]].priority;
+        let end = order[start..]
+            .iter()
+            .position(|&i| consumers[i].priority != priority)
+            .map_or(order.len(), |len| start + len);
+        let group = &order[start..end];
+
+        let wanted: Energy = group.iter().map(|&i| consumers[i].demand * dt).sum();
+        report.demanded += wanted;
+
+        // A priority level that cannot be fully fed shares what is left evenly.
+        let satisfaction = if wanted <= 0. {
+            1.
+        } else {
+            (budget / wanted).min(1.)
+        };
+        for &i in group {
+            consumers[i].satisfaction = satisfaction;
+        }
+
+        let used = wanted * satisfaction;
+        report.consumed += used;
+        budget -= used;
+        start = end;
+    }
+
+    let net = report.generated - report.consumed;
+    if net >= 0. {
+        let mut surplus = net;
+        for storage in storages.iter_mut() {
+            let accepted = storage.headroom(dt).min(surplus);
+            storage.charge += accepted;
+            surplus -= accepted;
+            report.stored += accepted;
+        }
+        report.wasted = surplus;
+    } else {
+        // Drain every storage in proportion to what it can give so none is
+        // emptied ahead of the others.
+        let deficit = -net;
+        let share = if from_storage > 0. {
+            deficit / from_storage
+        } else {
+            0.
+        };
+        for storage in storages.iter_mut() {
+            let drawn = storage.available(dt) * share;
+            storage.charge = (storage.charge - drawn).max(0.);
+            report.drawn += drawn;
+        }
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NIGHT: Environment = Environment {
+        sun_intensity: 0.,
+        wind_speed: 0.,
+        temperature: 0.,
+    };
+
+    fn with_sun(sun_intensity: f32) -> Environment {
+        Environment {
+            sun_intensity,
+            ..NIGHT
+        }
+    }
+
+    fn with_wind(wind_speed: f32) -> Environment {
+        Environment {
+            wind_speed,
+            ..NIGHT
+        }
+    }
+
+    /// A generator with a steady `output` that never runs dry.
+    fn steady(output: Power) -> Generator {
+        Generator::NuclearBattery {
+            output,
+            remaining: Energy::MAX,
+        }
+    }
+
+    #[test]
+    fn nuclear_battery_runs_until_depleted() {
+        let mut system = EnergySystem::new();
+        let battery = system.add_generator(Generator::NuclearBattery {
+            output: 10.,
+            remaining: 25.,
+        });
+
+        let generated: Vec<Energy> = (0..4).map(|_| system.tick(1., &NIGHT).generated).collect();
+        assert_eq!(generated, [10., 10., 5., 0.]);
+        assert_eq!(system.generator(battery).output(&NIGHT), 0.);
+    }
+
+    #[test]
+    fn solar_follows_clamped_sunlight() {
+        let solar = Generator::Solar { peak: 100. };
+        let rated = |sun_intensity| Environment {
+            temperature: SOLAR_RATED_TEMPERATURE,
+            ..with_sun(sun_intensity)
+        };
+        assert_eq!(solar.output(&rated(0.5)), 50.);
+        assert_eq!(solar.output(&rated(2.)), 100.);
+        assert_eq!(solar.output(&rated(-1.)), 0.);
+    }
+
+    #[test]
+    fn solar_derates_with_temperature() {
+        let solar = Generator::Solar { peak: 100. };
+        let at = |temperature| Environment {
+            temperature,
+            ..with_sun(1.)
+        };
+        assert!((solar.output(&at(-25.)) - 120.).abs() < 1e-3);
+        assert!((solar.output(&at(75.)) - 80.).abs() < 1e-3);
+        // Far past any real temperature the panel gives nothing rather than
+        // drawing power.
+        assert_eq!(solar.output(&at(1000.)), 0.);
+    }
+
+    #[test]
+    fn wind_ramps_between_cut_in_and_rated_speed() {
+        let wind = Generator::Wind {
+            rated: 1000.,
+            cut_in: 3.,
+            rated_speed: 12.,
+            cut_out: 25.,
+        };
+        assert_eq!(wind.output(&with_wind(2.)), 0.);
+        assert_eq!(wind.output(&with_wind(3.)), 0.);
+        assert_eq!(wind.output(&with_wind(7.5)), 125.);
+        assert_eq!(wind.output(&with_wind(12.)), 1000.);
+        assert_eq!(wind.output(&with_wind(20.)), 1000.);
+        assert_eq!(wind.output(&with_wind(25.)), 0.);
+    }
+
+    #[test]
+    fn brownout_shares_evenly_within_a_priority() {
+        let mut system = EnergySystem::new();
+        system.add_generator(steady(30.));
+        let a = system.add_consumer(Consumer::new(20., Priority::NORMAL));
+        let b = system.add_consumer(Consumer::new(20., Priority::NORMAL));
+
+        let report = system.tick(1., &NIGHT);
+        assert_eq!(system.consumer(a).satisfaction, 0.75);
+        assert_eq!(system.consumer(b).satisfaction, 0.75);
+        assert_eq!(report.unmet(), 10.);
+    }
+
+    #[test]
+    fn brownout_feeds_higher_priorities_first() {
+        let mut system = EnergySystem::new();
+        system.add_generator(steady(30.));
+        let low = system.add_consumer(Consumer::new(20., Priority::LOW));
+        let critical = system.add_consumer(Consumer::new(20., Priority::CRITICAL));
+        let normal = system.add_consumer(Consumer::new(20., Priority::NORMAL));
+
+        system.tick(1., &NIGHT);
+        assert!(system.consumer(critical).is_powered());
+        assert_eq!(system.consumer(normal).satisfaction, 0.5);
+        assert_eq!(system.consumer(low).satisfaction, 0.);
+    }
+
+    #[test]
+    fn storage_charges_no_faster_than_its_rate() {
+        let mut system = EnergySystem::new();
+        system.add_generator(steady(50.));
+        let storage = system.add_storage(Storage::new(100., 10., 5.));
+
+        let report = system.tick(1., &NIGHT);
+        assert_eq!(system.storage(storage).charge, 10.);
+        assert_eq!(report.stored, 10.);
+        assert_eq!(report.wasted, 40.);
+    }
+
+    #[test]
+    fn storage_discharges_no_faster_than_its_rate() {
+        let mut system = EnergySystem::new();
+        let storage = system.add_storage(Storage {
+            charge: 100.,
+            ..Storage::new(100., 10., 5.)
+        });
+        let consumer = system.add_consumer(Consumer::new(20., Priority::NORMAL));
+
+        let report = system.tick(1., &NIGHT);
+        assert_eq!(system.storage(storage).charge, 95.);
+        assert_eq!(report.drawn, 5.);
+        assert_eq!(system.consumer(consumer).satisfaction, 0.25);
+    }
+
+    #[test]
+    fn identical_inputs_give_identical_results() {
+        let build = || {
+            let mut system = EnergySystem::new();
+            system.add_generator(Generator::nuclear_battery());
+            system.add_generator(Generator::Solar { peak: 120. });
+            system.add_generator(Generator::Wind {
+                rated: 300.,
+                cut_in: 3.,
+                rated_speed: 12.,
+                cut_out: 25.,
+            });
+            system.add_storage(Storage::new(5_000., 150., 200.));
+            system.add_storage(Storage::new(800., 40., 60.));
+            system.add_consumer(Consumer::new(90., Priority::CRITICAL));
+            system.add_consumer(Consumer::new(250., Priority::NORMAL));
+            system.add_consumer(Consumer::new(120., Priority::NORMAL));
+            system.add_consumer(Consumer::new(400., Priority::LOW));
+            system
+        };
+        let run = |mut system: EnergySystem| {
+            let reports: Vec<TickReport> = (0..200)
+                .map(|i| {
+                    let env = Environment {
+                        sun_intensity: (i as f32 * 0.05).sin().max(0.),
+                        wind_speed: (i % 30) as f32,
+                        temperature: -20.,
+                    };
+                    system.tick(0.05, &env)
+                })
+                .collect();
+            (reports, format!("{system:?}"))
+        };
+
+        assert_eq!(run(build()), run(build()));
+    }
+}
@@ -0,0 +1,524 @@
+//! Cable networks connecting the machines of the base.
+//!
+//! Cables and machines are blocks in the voxel world. Every face-adjacent pair
+//! of grid blocks is connected, and each connected set forms a network that
+//! balances its own supply and demand. Placing a block unions it with its
+//! neighbours. Breaking one can split a network, so only the members of that
+//! network are flooded again.
+
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::io::{self, Read, Write};
+
+use glam::IVec3;
+
+use super::{Consumer, Environment, Generator, Priority, Storage, balance};
+
+const NEIGHBOURS: [IVec3; 6] = [
+    IVec3::X,
+    IVec3::NEG_X,
+    IVec3::Y,
+    IVec3::NEG_Y,
+    IVec3::Z,
+    IVec3::NEG_Z,
+];
+
+const SAVE_MAGIC: [u8; 4] = *b"GRID";
+const SAVE_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Machine {
+    Generator(Generator),
+    Storage(Storage),
+    Consumer(Consumer),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Block {
+    Cable,
+    Machine(Machine),
+}
+
+/// Identifies a network until the next time the grid's topology changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NetworkId(usize);
+
+#[derive(Debug, Clone)]
+struct Node {
+    pos: IVec3,
+    block: Block,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PowerGrid {
+    index: HashMap<IVec3, usize>,
+    nodes: Vec<Option<Node>>,
+    parent: Vec<usize>,
+    size: Vec<u32>,
+    free: Vec<usize>,
+}
+
+impl PowerGrid {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn block(&self, pos: IVec3) -> Option<&Block> {
+        self.index
+            .get(&pos)
+            .and_then(|&slot| self.nodes[slot].as_ref())
+            .map(|node| &node.block)
+    }
+
+    pub fn machine_mut(&mut self, pos: IVec3) -> Option<&mut Machine> {
+        let slot = *self.index.get(&pos)?;
+        match self.nodes[slot].as_mut() {
+            Some(Node {
+                block: Block::Machine(machine),
+                ..
+            }) => Some(machine),
+            _ => None,
+        }
+    }
+
+    /// Add a cable or machine. Returns `false` if the position is already taken.
+    pub fn place(&mut self, pos: IVec3, block: Block) -> bool {
+        if self.index.contains_key(&pos) {
+            return false;
+        }
+
+        let node = Some(Node { pos, block });
+        let slot = if let Some(slot) = self.free.pop() {
+            self.nodes[slot] = node;
+            self.parent[slot] = slot;
+            self.size[slot] = 1;
+            slot
+        } else {
+            self.nodes.push(node);
+            self.parent.push(self.nodes.len() - 1);
+            self.size.push(1);
+            self.nodes.len() - 1
+        };
+        self.index.insert(pos, slot);
+
+        for offset in NEIGHBOURS {
+            if let Some(&neighbour) = self.index.get(&(pos + offset)) {
+                self.union(slot, neighbour);
+            }
+        }
+        true
+    }
+
+    /// Remove whatever grid block is at `pos`, splitting its network if needed.
+    pub fn remove(&mut self, pos: IVec3) -> Option<Block> {
+        let slot = self.index.remove(&pos)?;
+        let node = self.nodes[slot].take()?;
+        self.free.push(slot);
+
+        // Union-find cannot delete, so rebuild the sets for every piece the old
+        // network may have broken into. Each flood starts from a neighbour that
+        // has not already been reached by an earlier one.
+        let mut visited = HashSet::new();
+        for offset in NEIGHBOURS {
+            let Some(&start) = self.index.get(&(pos + offset)) else {
+                continue;
+            };
+            if visited.contains(&start) {
+                continue;
+            }
+
+            let mut members = vec![];
+            let mut queue = VecDeque::from([start]);
+            visited.insert(start);
+            while let Some(current) = queue.pop_front() {
+                members.push(current);
+                let Some(current_pos) = self.nodes[current].as_ref().map(|n| n.pos) else {
+                    continue;
+                };
+                for offset in NEIGHBOURS {
+                    if let Some(&next) = self.index.get(&(current_pos + offset))
+                        && visited.insert(next)
+                    {
+                        queue.push_back(next);
+                    }
+                }
+            }
+
+            for &member in &members {
+                self.parent[member] = start;
+                self.size[member] = 1;
+            }
+            self.size[start] = members.len() as u32;
+        }
+
+        Some(node.block)
+    }
+
+    pub fn network_at(&mut self, pos: IVec3) -> Option<NetworkId> {
+        let slot = *self.index.get(&pos)?;
+        Some(NetworkId(self.find(slot)))
+    }
+
+    pub fn network_count(&mut self) -> usize {
+        (0..self.nodes.len())
+            .filter(|&slot| self.nodes[slot].is_some() && self.find(slot) == slot)
+            .count()
+    }
+
+    /// Balance every network for `dt` seconds.
+    pub fn tick(&mut self, dt: f32, env: &Environment) {
+        let roots: Vec<usize> = (0..self.nodes.len()).map(|slot| self.find(slot)).collect();
+
+        #[derive(Default)]
+        struct Members<'a> {
+            generators: Vec<&'a mut Generator>,
+            storages: Vec<&'a mut Storage>,
+            consumers: Vec<&'a mut Consumer>,
+        }
+
+        // Keyed by root slot and filled in slot order so ticks are repeatable.
+        let mut networks: BTreeMap<usize, Members> = BTreeMap::new();
+        for (slot, node) in self.nodes.iter_mut().enumerate() {
+            let Some(node) = node else {
+                continue;
+            };
+            let members = networks.entry(roots[slot]).or_default();
+            match &mut node.block {
+                Block::Cable => {}
+                Block::Machine(Machine::Generator(g)) => members.generators.push(g),
+                Block::Machine(Machine::Storage(s)) => members.storages.push(s),
+                Block::Machine(Machine::Consumer(c)) => members.consumers.push(c),
+            }
+        }
+
+        for mut members in networks.into_values() {
+            balance(
+                &mut members.generators,
+                &mut members.storages,
+                &mut members.consumers,
+                dt,
+                env,
+            );
+        }
+    }
+
+    /// Write every grid block and its machine state. Networks are not stored;
+    /// they are rebuilt from adjacency on load.
+    pub fn save(&self, w: &mut impl Write) -> io::Result<()> {
+        w.write_all(&SAVE_MAGIC)?;
+        w.write_all(&SAVE_VERSION.to_le_bytes())?;
+
+        let mut nodes: Vec<&Node> = self.nodes.iter().flatten().collect();
+        nodes.sort_by_key(|node| node.pos.to_array());
+        w.write_all(&(nodes.len() as u32).to_le_bytes())?;
+
+        for node in nodes {
+            for c in node.pos.to_array() {
+                w.write_all(&c.to_le_bytes())?;
+            }
+            match node.block {
+                Block::Cable => w.write_all(&[0])?,
+                Block::Machine(Machine::Generator(Generator::NuclearBattery {
+                    output,
+                    remaining,
+                })) => {
+                    w.write_all(&[1])?;
+                    write_f32s(w, &[output, remaining])?;
+                }
+                Block::Machine(Machine::Generator(Generator::Solar { peak })) => {
+                    w.write_all(&[2])?;
+                    write_f32s(w, &[peak])?;
+                }
+                Block::Machine(Machine::Generator(Generator::Wind {
+                    rated,
+                    cut_in,
+                    rated_speed,
+                    cut_out,
+                })) => {
+                    w.write_all(&[3])?;
+                    write_f32s(w, &[rated, cut_in, rated_speed, cut_out])?;
+                }
+                Block::Machine(Machine::Storage(s)) => {
+                    w.write_all(&[4])?;
+                    write_f32s(
+                        w,
+                        &[
+                            s.capacity,
+                            s.charge,
+                            s.max_charge_rate,
+                            s.max_discharge_rate,
+                        ],
+                    )?;
+                }
+                Block::Machine(Machine::Consumer(c)) => {
+                    w.write_all(&[5, c.priority.0])?;
+                    write_f32s(w, &[c.demand])?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn load(r: &mut impl Read) -> io::Result<Self> {
+        if read_array::<4>(r)? != SAVE_MAGIC {
+            return Err(invalid_data("not a power grid save"));
+        }
+        let version = u32::from_le_bytes(read_array(r)?);
+        if version != SAVE_VERSION {
+            return Err(invalid_data("unsupported power grid save version"));
+        }
+
+        let mut grid = Self::new();
+        let count = u32::from_le_bytes(read_array(r)?);
+        for _ in 0..count {
+            let pos = IVec3::new(
+                i32::from_le_bytes(read_array(r)?),
+                i32::from_le_bytes(read_array(r)?),
+                i32::from_le_bytes(read_array(r)?),
+            );
+            let [tag] = read_array(r)?;
+            let block = match tag {
+                0 => Block::Cable,
+                1 => {
+                    let [output, remaining] = read_f32s(r)?;
+                    Block::Machine(Machine::Generator(Generator::NuclearBattery {
+                        output,
+                        remaining,
+                    }))
+                }
+                2 => {
+                    let [peak] = read_f32s(r)?;
+                    Block::Machine(Machine::Generator(Generator::Solar { peak }))
+                }
+                3 => {
+                    let [rated, cut_in, rated_speed, cut_out] = read_f32s(r)?;
+                    Block::Machine(Machine::Generator(Generator::Wind {
+                        rated,
+                        cut_in,
+                        rated_speed,
+                        cut_out,
+                    }))
+                }
+                4 => {
+                    let [capacity, charge, max_charge_rate, max_discharge_rate] = read_f32s(r)?;
+                    Block::Machine(Machine::Storage(Storage {
+                        capacity,
+                        charge,
+                        max_charge_rate,
+                        max_discharge_rate,
+                    }))
+                }
+                5 => {
+                    let [priority] = read_array(r)?;
+                    let [demand] = read_f32s(r)?;
+                    Block::Machine(Machine::Consumer(Consumer::new(demand, Priority(priority))))
+                }
+                _ => return Err(invalid_data("unknown power grid block")),
+            };
+            if !grid.place(pos, block) {
+                return Err(invalid_data("duplicate power grid block"));
+            }
+        }
+        Ok(grid)
+    }
+
+    fn find(&mut self, mut slot: usize) -> usize {
+        while self.parent[slot] != slot {
+            self.parent[slot] = self.parent[self.parent[slot]];
+            slot = self.parent[slot];
+        }
+        slot
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a == b {
+            return;
+        }
+        let (big, small) = if self.size[a] >= self.size[b] {
+            (a, b)
+        } else {
+            (b, a)
+        };
+        self.parent[small] = big;
+        self.size[big] += self.size[small];
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn read_array<const N: usize>(r: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut buf = [0; N];
+    r.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn read_f32s<const N: usize>(r: &mut impl Read) -> io::Result<[f32; N]> {
+    let mut values = [0.; N];
+    for value in &mut values {
+        *value = f32::from_le_bytes(read_array(r)?);
+    }
+    Ok(values)
+}
+
+fn write_f32s(w: &mut impl Write, values: &[f32]) -> io::Result<()> {
+    for value in values {
+        w.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cables(grid: &mut PowerGrid, positions: &[IVec3]) {
+        for &pos in positions {
+            assert!(grid.place(pos, Block::Cable));
+        }
+    }
+
+    #[test]
+    fn placing_a_block_merges_neighbouring_networks() {
+        let mut grid = PowerGrid::new();
+        cables(&mut grid, &[IVec3::ZERO, IVec3::new(2, 0, 0)]);
+        assert_eq!(grid.network_count(), 2);
+
+        cables(&mut grid, &[IVec3::X]);
+        assert_eq!(grid.network_count(), 1);
+        assert_eq!(
+            grid.network_at(IVec3::ZERO),
+            grid.network_at(IVec3::new(2, 0, 0))
+        );
+    }
+
+    #[test]
+    fn removing_a_bridge_splits_its_network() {
+        let mut grid = PowerGrid::new();
+        cables(
+            &mut grid,
+            &[
+                IVec3::ZERO,
+                IVec3::X,
+                IVec3::new(2, 0, 0),
+                IVec3::new(1, 1, 0),
+            ],
+        );
+        assert_eq!(grid.network_count(), 1);
+
+        assert_eq!(grid.remove(IVec3::X), Some(Block::Cable));
+        assert_eq!(grid.network_count(), 3);
+        assert_ne!(
+            grid.network_at(IVec3::ZERO),
+            grid.network_at(IVec3::new(2, 0, 0))
+        );
+        assert_eq!(grid.network_at(IVec3::X), None);
+    }
+
+    #[test]
+    fn removing_part_of_a_loop_keeps_it_connected() {
+        let mut grid = PowerGrid::new();
+        cables(
+            &mut grid,
+            &[IVec3::ZERO, IVec3::X, IVec3::new(1, 1, 0), IVec3::Y],
+        );
+
+        grid.remove(IVec3::X);
+        assert_eq!(grid.network_count(), 1);
+        assert_eq!(
+            grid.network_at(IVec3::ZERO),
+            grid.network_at(IVec3::new(1, 1, 0))
+        );
+    }
+
+    #[test]
+    fn networks_balance_separately() {
+        let mut grid = PowerGrid::new();
+        let generator = Generator::Solar { peak: 100. };
+        let consumer = Consumer::new(50., Priority::NORMAL);
+        grid.place(IVec3::ZERO, Block::Machine(Machine::Generator(generator)));
+        grid.place(IVec3::X, Block::Machine(Machine::Consumer(consumer)));
+        grid.place(
+            IVec3::new(5, 0, 0),
+            Block::Machine(Machine::Consumer(consumer)),
+        );
+
+        let env = Environment {
+            sun_intensity: 1.,
+            ..Environment::default()
+        };
+        grid.tick(1., &env);
+        let satisfaction = |grid: &mut PowerGrid, pos| match grid.machine_mut(pos) {
+            Some(Machine::Consumer(c)) => c.satisfaction,
+            _ => panic!("no consumer at {pos}"),
+        };
+        assert_eq!(satisfaction(&mut grid, IVec3::X), 1.);
+        assert_eq!(satisfaction(&mut grid, IVec3::new(5, 0, 0)), 0.);
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let mut grid = PowerGrid::new();
+        let blocks = [
+            (IVec3::ZERO, Block::Cable),
+            (
+                IVec3::X,
+                Block::Machine(Machine::Generator(Generator::nuclear_battery())),
+            ),
+            (
+                IVec3::Y,
+                Block::Machine(Machine::Generator(Generator::Solar { peak: 120. })),
+            ),
+            (
+                IVec3::Z,
+                Block::Machine(Machine::Generator(Generator::Wind {
+                    rated: 300.,
+                    cut_in: 3.,
+                    rated_speed: 12.,
+                    cut_out: 25.,
+                })),
+            ),
+            (
+                IVec3::NEG_X,
+                Block::Machine(Machine::Storage(Storage {
+                    charge: 250.,
+                    ..Storage::new(1000., 40., 60.)
+                })),
+            ),
+            (
+                IVec3::new(-4, 7, 2),
+                Block::Machine(Machine::Consumer(Consumer::new(25., Priority::CRITICAL))),
+            ),
+        ];
+        for (pos, block) in blocks {
+            grid.place(pos, block);
+        }
+
+        let mut saved = vec![];
+        grid.save(&mut saved).expect("writing to a Vec cannot fail");
+        let mut loaded = PowerGrid::load(&mut saved.as_slice()).expect("a valid save");
+
+        for (pos, block) in blocks {
+            assert_eq!(loaded.block(pos), Some(&block));
+        }
+        assert_eq!(loaded.network_count(), grid.network_count());
+
+        let mut resaved = vec![];
+        loaded
+            .save(&mut resaved)
+            .expect("writing to a Vec cannot fail");
+        assert_eq!(resaved, saved);
+    }
+
+    #[test]
+    fn load_rejects_other_data() {
+        let mut grid = PowerGrid::new();
+        cables(&mut grid, &[IVec3::ZERO]);
+        let mut saved = vec![];
+        grid.save(&mut saved).expect("writing to a Vec cannot fail");
+
+        assert!(PowerGrid::load(&mut &b"TIME\x01\0\0\0"[..]).is_err());
+        assert!(PowerGrid::load(&mut &saved[..saved.len() - 1]).is_err());
+    }
+}
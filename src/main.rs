@@ -1,17 +1,56 @@
 use sdl3::{event::Event, keyboard::Keycode};
-use std::time::Instant;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::path::Path;
+use std::time::{Duration, Instant};
+mod energy;
+#[cfg(feature = "neuro")]
+mod neuro;
+mod overlay;
+#[cfg(feature = "plugins")]
+mod plugins;
+mod profiling;
 mod render;
+mod time_of_day;
+mod world;
+#[cfg(feature = "logging")]
+use piglog::prelude::*;
 use rootcause::prelude::Report;
 
 const SENSITIVITY: f32 = std::f32::consts::PI / 1024.;
+const TICK: Duration = Duration::from_millis(50);
+const DAY_LENGTH: Duration = Duration::from_secs(20 * 60);
+/// Degrees north, somewhere under the aurora.
+const LATITUDE: f32 = 66.;
+/// Chunks loaded around the player along each horizontal axis, unless
+/// device memory is running short.
+const VIEW_DISTANCE: u32 = 8;
+/// Render scale [ and ] add or take away.
+const RENDER_SCALE_STEP: f32 = 0.25;
+/// Frame time dynamic resolution aims for once F4 turns it on.
+const FRAME_TIME_TARGET: Duration = Duration::from_micros(33_333);
+/// Factor - and = change the exposure by, a quarter of a stop.
+const EXPOSURE_STEP: f32 = 1.189_207;
+/// Fraction of a day T skips ahead, wrapping within the same day.
+const TIME_SKIP: f32 = 1. / 24.;
+#[cfg(feature = "plugins")]
+const PLUGIN_DIR: &str = "./plugins";
+#[cfg(feature = "neuro")]
+const NEURO_CONTEXT_INTERVAL: Duration = Duration::from_secs(10);
+/// Battery charge below which Neuro is made to decide what the robot does.
+#[cfg(feature = "neuro")]
+const NEURO_LOW_BATTERY: f32 = 0.2;
 
 fn main() -> Result<(), Report> {
+    #[cfg(feature = "tracy")]
+    let _tracy = profiling::start();
+
     let sdl_context = sdl3::init()?;
     let video_subsystem = sdl_context.video()?;
 
     let mut event_pump = sdl_context.event_pump()?;
 
-    let window = video_subsystem
+    let mut window = video_subsystem
         .window("rust-sdl3 demo", 800, 600)
         .position_centered()
         .vulkan()
@@ -21,13 +60,64 @@ fn main() -> Result<(), Report> {
 
     sdl_context.mouse().set_relative_mouse_mode(&window, true);
 
-    let mut r = render::Renderer::new(&window);
+    let data_dir = sdl3::filesystem::get_pref_path("glacian", "glacian")?;
+
+    let mut r = render::Renderer::new(&window, &data_dir);
 
     let mut player_pos = glam::vec3(0., 0., 0.);
 
     let (mut yaw, mut pitch) = (0., 0.);
 
-    let start_time = Instant::now();
+    let mut energy = energy::EnergySystem::new();
+    energy.add_generator(energy::Generator::nuclear_battery());
+    #[cfg_attr(not(feature = "neuro"), allow(unused_variables))]
+    let robot_battery = energy.add_storage(energy::Storage::new(50_000., 200., 400.));
+    energy.add_consumer(energy::Consumer::new(25., energy::Priority::CRITICAL));
+
+    let power_grid_path = data_dir.join("power-grid.bin");
+    let mut power_grid = load_or(
+        &power_grid_path,
+        energy::grid::PowerGrid::load,
+        energy::grid::PowerGrid::new,
+    );
+
+    let time_of_day_path = data_dir.join("time-of-day.bin");
+    let mut time_of_day = load_or(&time_of_day_path, time_of_day::TimeOfDay::load, || {
+        time_of_day::TimeOfDay::new(DAY_LENGTH, LATITUDE)
+    });
+
+    #[cfg(feature = "plugins")]
+    let mut plugin_host = {
+        let mut host = plugins::PluginHost::new(data_dir.join("plugin-cache"))?;
+        if std::path::Path::new(PLUGIN_DIR).is_dir() {
+            host.load_dir(PLUGIN_DIR)?;
+        }
+        #[cfg(feature = "logging")]
+        for (plugin, block) in host.registered_blocks() {
+            piglog::note!("Plugin {plugin} registered block {block}");
+        }
+        host
+    };
+
+    #[cfg(feature = "neuro")]
+    let neuro = match neuro::NeuroClient::from_env() {
+        Ok(client) => Some(client),
+        Err(_e) => {
+            #[cfg(feature = "logging")]
+            piglog::warning!("Not connected to Neuro: {_e}");
+            None
+        }
+    };
+    #[cfg(feature = "neuro")]
+    let mut last_neuro_context: Option<Instant> = None;
+    #[cfg(feature = "neuro")]
+    let mut neuro_low_battery = false;
+
+    let mut world = world::World::new(&r);
+    let mut overlay = overlay::Overlay::new(&window);
+
+    let mut last_update = Instant::now();
+    let mut tick_accumulator = Duration::ZERO;
 
     'running: loop {
         let mut velocity = glam::Vec3::ZERO;
@@ -66,6 +156,43 @@ fn main() -> Result<(), Report> {
                     keycode: Some(Keycode::Down),
                     ..
                 } => velocity.z += 1.,
+                Event::KeyDown {
+                    keycode: Some(Keycode::F3),
+                    repeat: false,
+                    ..
+                } => overlay.toggle(&mut window),
+                Event::KeyDown {
+                    keycode: Some(Keycode::T),
+                    ..
+                } => time_of_day.set_time(time_of_day.time() + TIME_SKIP),
+                Event::KeyDown {
+                    keycode: Some(Keycode::LeftBracket),
+                    ..
+                } => r.set_render_scale(r.render_scale() - RENDER_SCALE_STEP),
+                Event::KeyDown {
+                    keycode: Some(Keycode::RightBracket),
+                    ..
+                } => r.set_render_scale(r.render_scale() + RENDER_SCALE_STEP),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F4),
+                    repeat: false,
+                    ..
+                } => r.set_dynamic_resolution(
+                    r.dynamic_scale().is_none().then_some(FRAME_TIME_TARGET),
+                ),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F5),
+                    repeat: false,
+                    ..
+                } => r.set_tonemapper(r.tonemap_settings().tonemapper.next()),
+                Event::KeyDown {
+                    keycode: Some(Keycode::Minus),
+                    ..
+                } => r.set_exposure(r.tonemap_settings().exposure / EXPOSURE_STEP),
+                Event::KeyDown {
+                    keycode: Some(Keycode::Equals),
+                    ..
+                } => r.set_exposure(r.tonemap_settings().exposure * EXPOSURE_STEP),
                 Event::KeyDown {
                     keycode: Some(Keycode::Escape),
                     ..
@@ -94,15 +221,164 @@ fn main() -> Result<(), Report> {
             glam::Vec3::Z,
         );
 
-        let elapsed_time = start_time.elapsed().as_secs_f32() / 4.0;
+        let now = Instant::now();
+        tick_accumulator += now - last_update;
+        last_update = now;
+        while tick_accumulator >= TICK {
+            profiling::zone!("simulation tick");
+            time_of_day.advance(TICK.as_secs_f32());
+            let env = energy::Environment {
+                sun_intensity: time_of_day.sun_intensity(),
+                wind_speed: 0.,
+                temperature: time_of_day.temperature(),
+            };
+            energy.tick(TICK.as_secs_f32(), &env);
+            power_grid.tick(TICK.as_secs_f32(), &env);
+            #[cfg(feature = "plugins")]
+            {
+                plugin_host.tick(TICK.as_secs_f32());
+                // There are no entities to spawn yet, so requests are only
+                // logged, and drained so they do not pile up.
+                for _spawn in plugin_host.drain_spawns() {
+                    #[cfg(feature = "logging")]
+                    piglog::debug!(
+                        "Plugin {} asked to spawn {} at {}",
+                        _spawn.plugin,
+                        _spawn.kind,
+                        _spawn.pos
+                    );
+                }
+            }
 
-        let sky_color = glam::vec3a(0.7, 0.7, 1.0)
-            .lerp(glam::vec3a(0., 0., 0.), ((elapsed_time).cos() + 1.) * 0.5);
+            #[cfg(feature = "neuro")]
+            if let Some(neuro) = &neuro {
+                for request in neuro.poll() {
+                    let result = request.action.and_then(|action| {
+                        run_neuro_action(
+                            action,
+                            &mut player_pos,
+                            &mut power_grid,
+                            &energy,
+                            robot_battery,
+                        )
+                    });
+                    neuro.result(&request.id, result);
+                }
 
-        dbg!(sky_color);
+                if last_neuro_context.is_none_or(|last| last.elapsed() >= NEURO_CONTEXT_INTERVAL) {
+                    let battery = energy.storage(robot_battery);
+                    neuro.context(
+                        &format!(
+                            "You are at {}. Your battery is at {:.0}%. There are {} power networks in your base.",
+                            player_pos.round(),
+                            battery.fraction() * 100.,
+                            power_grid.network_count(),
+                        ),
+                        true,
+                    );
+                    last_neuro_context = Some(Instant::now());
+                }
 
-        r.render(view, sky_color);
+                let charge = energy.storage(robot_battery).fraction();
+                let low_battery = charge < NEURO_LOW_BATTERY;
+                if low_battery && !neuro_low_battery {
+                    neuro.force(
+                        "Your battery is running low. What do you do?",
+                        Some(&format!("Your battery is at {:.0}%.", charge * 100.)),
+                        &[
+                            neuro::actions::CHECK_BATTERY,
+                            neuro::actions::MOVE,
+                            neuro::actions::BUILD,
+                        ],
+                    );
+                }
+                neuro_low_battery = low_battery;
+            }
+
+            tick_accumulator -= TICK;
+        }
+
+        world.stream(player_pos, VIEW_DISTANCE, &mut r);
+        r.render(view, &time_of_day);
+        overlay.frame(&mut window, &r, &time_of_day);
+        profiling::frame_mark();
         std::thread::sleep(std::time::Duration::from_millis(16));
     }
+
+    save_atomically(&time_of_day_path, |w| time_of_day.save(w))?;
+    save_atomically(&power_grid_path, |w| power_grid.save(w))?;
     Ok(())
 }
+
+/// Read the save at `path` with `load`, or start from `default` if there is
+/// none. A save that cannot be read is logged and left to be overwritten
+/// rather than keeping the game from starting.
+fn load_or<T>(
+    path: &Path,
+    load: impl FnOnce(&mut BufReader<File>) -> io::Result<T>,
+    default: impl FnOnce() -> T,
+) -> T {
+    let loaded = File::open(path).and_then(|file| load(&mut BufReader::new(file)));
+    match loaded {
+        Ok(value) => value,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => default(),
+        Err(_e) => {
+            #[cfg(feature = "logging")]
+            piglog::warning!("Starting over without {}: {_e}", path.display());
+            default()
+        }
+    }
+}
+
+/// Write a save beside `path` and then move it into place, so a crash while
+/// saving leaves the previous save whole.
+fn save_atomically(
+    path: &Path,
+    save: impl FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut w = BufWriter::new(File::create(&tmp)?);
+    save(&mut w)?;
+    w.into_inner()?.sync_all()?;
+    std::fs::rename(&tmp, path)
+}
+
+#[cfg(feature = "neuro")]
+fn run_neuro_action(
+    action: neuro::Action,
+    player_pos: &mut glam::Vec3,
+    power_grid: &mut energy::grid::PowerGrid,
+    energy: &energy::EnergySystem,
+    robot_battery: energy::StorageId,
+) -> Result<String, String> {
+    let block = player_pos.round().as_ivec3();
+    match action {
+        neuro::Action::Move {
+            direction,
+            distance,
+        } => {
+            *player_pos += (direction.offset() * distance as i32).as_vec3();
+            Ok(format!("You moved {distance} blocks."))
+        }
+        neuro::Action::Mine { direction } => power_grid
+            .remove(block + direction.offset())
+            .map(|_| "You broke the block.".to_owned())
+            .ok_or_else(|| "There is nothing there to mine.".to_owned()),
+        neuro::Action::Build { direction } => {
+            if power_grid.place(block + direction.offset(), energy::grid::Block::Cable) {
+                Ok("You placed a cable.".to_owned())
+            } else {
+                Err("Something is already there.".to_owned())
+            }
+        }
+        neuro::Action::CheckBattery => {
+            let battery = energy.storage(robot_battery);
+            Ok(format!(
+                "Your battery holds {:.0} of {:.0} joules ({:.0}%).",
+                battery.charge,
+                battery.capacity,
+                battery.fraction() * 100.
+            ))
+        }
+    }
+}
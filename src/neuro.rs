@@ -0,0 +1,346 @@
+//! Client for the [Neuro game SDK](https://github.com/VedalAI/neuro-sdk).
+//!
+//! The websocket lives on its own thread so the game loop never blocks on the
+//! network. Outgoing messages are queued through a channel and incoming
+//! actions are buffered until [`NeuroClient::poll`] is called on the next
+//! simulation tick.
+
+use std::fmt;
+use std::io::ErrorKind;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::time::Duration;
+
+#[cfg(feature = "logging")]
+use piglog::prelude::*;
+use serde_json::{Value, json};
+use tungstenite::Message;
+use tungstenite::stream::MaybeTlsStream;
+
+pub mod actions;
+
+pub use actions::Action;
+
+pub const GAME: &str = "Glacian";
+
+/// Environment variable the SDK uses to tell games where to connect.
+pub const URL_ENV: &str = "NEURO_SDK_WS_URL";
+/// Where Randy and the other mock servers listen by default.
+pub const DEFAULT_URL: &str = "ws://localhost:8000";
+
+/// How long the socket thread waits for a message before flushing its queue.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(Debug)]
+pub enum NeuroError {
+    WebSocket(tungstenite::Error),
+    Io(std::io::Error),
+}
+
+impl fmt::Display for NeuroError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::WebSocket(e) => write!(f, "{e}"),
+            Self::Io(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for NeuroError {}
+
+impl From<tungstenite::Error> for NeuroError {
+    fn from(e: tungstenite::Error) -> Self {
+        Self::WebSocket(e)
+    }
+}
+
+impl From<std::io::Error> for NeuroError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// An action Neuro wants performed. Reply with [`NeuroClient::result`].
+#[derive(Debug, Clone)]
+pub struct ActionRequest {
+    pub id: String,
+    pub action: Result<Action, String>,
+}
+
+pub struct NeuroClient {
+    outgoing: Sender<String>,
+    incoming: Receiver<ActionRequest>,
+}
+
+impl NeuroClient {
+    /// Connect to the URL in [`URL_ENV`], falling back to [`DEFAULT_URL`].
+    pub fn from_env() -> Result<Self, NeuroError> {
+        let url = std::env::var(URL_ENV).unwrap_or_else(|_| DEFAULT_URL.to_owned());
+        Self::connect(&url)
+    }
+
+    /// Open the websocket, then announce the game and register its actions.
+    pub fn connect(url: &str) -> Result<Self, NeuroError> {
+        let (mut socket, _) = tungstenite::connect(url)?;
+        // The timeout goes on the TCP socket under any TLS, so reads give the
+        // queue a chance to flush on `wss://` as well.
+        let stream = match socket.get_mut() {
+            MaybeTlsStream::Plain(stream) => stream,
+            MaybeTlsStream::NativeTls(stream) => stream.get_mut(),
+            _ => return Err(std::io::Error::other("unsupported websocket stream").into()),
+        };
+        stream.set_read_timeout(Some(POLL_INTERVAL))?;
+
+        let (outgoing, outgoing_rx) = mpsc::channel::<String>();
+        let (incoming_tx, incoming) = mpsc::channel();
+
+        std::thread::Builder::new()
+            .name("neuro".to_owned())
+            .spawn(move || {
+                loop {
+                    loop {
+                        match outgoing_rx.try_recv() {
+                            Ok(text) => {
+                                if let Err(_e) = socket.send(Message::text(text)) {
+                                    #[cfg(feature = "logging")]
+                                    piglog::error!("Neuro connection lost: {_e}");
+                                    return;
+                                }
+                            }
+                            Err(TryRecvError::Empty) => break,
+                            Err(TryRecvError::Disconnected) => {
+                                _ = socket.close(None);
+                                return;
+                            }
+                        }
+                    }
+
+                    match socket.read() {
+                        Ok(Message::Text(text)) => match parse_incoming(text.as_str()) {
+                            Some(Incoming::Action(request)) => _ = incoming_tx.send(request),
+                            Some(Incoming::ReregisterAll) => {
+                                _ = socket.send(Message::text(register_message()));
+                            }
+                            None => {}
+                        },
+                        Ok(Message::Close(_)) => return,
+                        Ok(_) => {}
+                        Err(tungstenite::Error::Io(e))
+                            if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                        Err(_e) => {
+                            #[cfg(feature = "logging")]
+                            piglog::error!("Neuro connection lost: {_e}");
+                            return;
+                        }
+                    }
+                }
+            })?;
+
+        let client = Self { outgoing, incoming };
+        client.send("startup", None);
+        // The thread only hangs up once the connection is gone, at which point
+        // there is nobody left to tell.
+        _ = client.outgoing.send(register_message());
+        Ok(client)
+    }
+
+    fn send(&self, command: &str, data: Option<Value>) {
+        _ = self.outgoing.send(encode(command, data));
+    }
+
+    /// Tell Neuro what is happening. Silent context is not read out loud.
+    pub fn context(&self, message: &str, silent: bool) {
+        self.send(
+            "context",
+            Some(json!({ "message": message, "silent": silent })),
+        );
+    }
+
+    /// Make Neuro pick one of `action_names` as soon as possible.
+    pub fn force(&self, query: &str, state: Option<&str>, action_names: &[&str]) {
+        let mut data = json!({ "query": query, "action_names": action_names });
+        if let Some(state) = state {
+            data["state"] = json!(state);
+        }
+        self.send("actions/force", Some(data));
+    }
+
+    /// Report how an action went. A failed result makes Neuro try again.
+    pub fn result(&self, id: &str, result: Result<String, String>) {
+        let (success, message) = match result {
+            Ok(message) => (true, message),
+            Err(message) => (false, message),
+        };
+        self.send(
+            "action/result",
+            Some(json!({ "id": id, "success": success, "message": message })),
+        );
+    }
+
+    /// Actions received since the last call, in the order they arrived.
+    pub fn poll(&self) -> Vec<ActionRequest> {
+        self.incoming.try_iter().collect()
+    }
+}
+
+fn encode(command: &str, data: Option<Value>) -> String {
+    let mut message = json!({ "command": command, "game": GAME });
+    if let Some(data) = data {
+        message["data"] = data;
+    }
+    message.to_string()
+}
+
+fn register_message() -> String {
+    encode(
+        "actions/register",
+        Some(json!({ "actions": actions::definitions() })),
+    )
+}
+
+enum Incoming {
+    Action(ActionRequest),
+    ReregisterAll,
+}
+
+fn parse_incoming(text: &str) -> Option<Incoming> {
+    let message: Value = serde_json::from_str(text).ok()?;
+    match message.get("command")?.as_str()? {
+        "action" => {
+            let data = message.get("data")?;
+            let id = data.get("id")?.as_str()?.to_owned();
+            let name = data.get("name")?.as_str()?;
+            let args = data.get("data").and_then(Value::as_str);
+            Some(Incoming::Action(ActionRequest {
+                id,
+                action: actions::parse(name, args),
+            }))
+        }
+        "actions/reregister_all" => Some(Incoming::ReregisterAll),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{TcpListener, TcpStream};
+    use std::time::Instant;
+
+    use tungstenite::WebSocket;
+
+    use super::*;
+    use crate::neuro::actions::Direction;
+
+    #[test]
+    fn encodes_commands_for_the_game() {
+        let message: Value = serde_json::from_str(&encode("startup", None)).expect("valid JSON");
+        assert_eq!(message, json!({ "command": "startup", "game": GAME }));
+
+        let message: Value =
+            serde_json::from_str(&encode("context", Some(json!({ "a": 1 })))).expect("valid JSON");
+        assert_eq!(
+            message,
+            json!({ "command": "context", "game": GAME, "data": { "a": 1 } })
+        );
+    }
+
+    #[test]
+    fn parses_incoming_actions() {
+        let text = json!({
+            "command": "action",
+            "data": {
+                "id": "abc",
+                "name": "mine",
+                "data": r#"{"direction": "east"}"#,
+            },
+        })
+        .to_string();
+        let Some(Incoming::Action(request)) = parse_incoming(&text) else {
+            panic!("expected an action");
+        };
+        assert_eq!(request.id, "abc");
+        assert_eq!(
+            request.action,
+            Ok(Action::Mine {
+                direction: Direction::East
+            })
+        );
+    }
+
+    #[test]
+    fn parses_reregister_requests() {
+        let text = json!({ "command": "actions/reregister_all" }).to_string();
+        assert!(matches!(
+            parse_incoming(&text),
+            Some(Incoming::ReregisterAll)
+        ));
+    }
+
+    #[test]
+    fn ignores_unknown_or_malformed_messages() {
+        assert!(parse_incoming("not json").is_none());
+        assert!(parse_incoming(r#"{"command": "shutdown/graceful"}"#).is_none());
+        assert!(parse_incoming(r#"{"command": "action", "data": {"name": "mine"}}"#).is_none());
+    }
+
+    fn read_json(socket: &mut WebSocket<TcpStream>) -> Value {
+        let message = socket.read().expect("a message from the client");
+        serde_json::from_str(message.to_text().expect("a text message")).expect("valid JSON")
+    }
+
+    /// Plays the part of Randy: checks the handshake messages and the
+    /// client's force, sends the forced action and waits for its result.
+    #[test]
+    fn talks_to_a_mock_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("a free port");
+        let url = format!("ws://{}", listener.local_addr().expect("a bound address"));
+
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().expect("a client");
+            let mut socket = tungstenite::accept(stream).expect("a websocket handshake");
+
+            assert_eq!(read_json(&mut socket)["command"], "startup");
+            let register = read_json(&mut socket);
+            assert_eq!(register["command"], "actions/register");
+            assert_eq!(register["data"]["actions"], actions::definitions());
+            let force = read_json(&mut socket);
+            assert_eq!(force["command"], "actions/force");
+            assert_eq!(
+                force["data"],
+                json!({
+                    "query": "Battery low",
+                    "state": "At 10%",
+                    "action_names": ["check_battery"],
+                })
+            );
+
+            let action = json!({
+                "command": "action",
+                "data": { "id": "1", "name": "check_battery" },
+            });
+            socket
+                .send(Message::text(action.to_string()))
+                .expect("sending the action");
+            read_json(&mut socket)
+        });
+
+        let client = NeuroClient::connect(&url).expect("a connection to the mock server");
+        client.force("Battery low", Some("At 10%"), &[actions::CHECK_BATTERY]);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let requests = loop {
+            let requests = client.poll();
+            if !requests.is_empty() {
+                break requests;
+            }
+            assert!(Instant::now() < deadline, "no action arrived");
+            std::thread::sleep(POLL_INTERVAL);
+        };
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].action, Ok(Action::CheckBattery));
+        client.result(&requests[0].id, Ok("Battery at 50%".to_owned()));
+
+        let result = server.join().expect("the mock server to finish");
+        assert_eq!(result["command"], "action/result");
+        assert_eq!(result["data"]["id"], "1");
+        assert_eq!(result["data"]["success"], true);
+    }
+}
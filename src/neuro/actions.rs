@@ -0,0 +1,209 @@
+//! Actions the robot offers to Neuro and how their JSON is parsed.
+
+use glam::IVec3;
+use serde_json::{Value, json};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    North,
+    South,
+    East,
+    West,
+    Up,
+    Down,
+}
+
+impl Direction {
+    const NAMES: [&str; 6] = ["north", "south", "east", "west", "up", "down"];
+
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "north" => Self::North,
+            "south" => Self::South,
+            "east" => Self::East,
+            "west" => Self::West,
+            "up" => Self::Up,
+            "down" => Self::Down,
+            _ => return None,
+        })
+    }
+
+    /// Unit step in world space. `+Y` is north and `+Z` is down, matching the
+    /// camera in `main.rs`.
+    pub const fn offset(self) -> IVec3 {
+        match self {
+            Self::North => IVec3::Y,
+            Self::South => IVec3::NEG_Y,
+            Self::East => IVec3::X,
+            Self::West => IVec3::NEG_X,
+            Self::Up => IVec3::NEG_Z,
+            Self::Down => IVec3::Z,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Move { direction: Direction, distance: u32 },
+    Mine { direction: Direction },
+    Build { direction: Direction },
+    CheckBattery,
+}
+
+pub const MOVE: &str = "move";
+pub const MINE: &str = "mine";
+pub const BUILD: &str = "build";
+pub const CHECK_BATTERY: &str = "check_battery";
+
+pub const MAX_MOVE_DISTANCE: u32 = 16;
+
+/// The `actions` array for an `actions/register` message.
+pub fn definitions() -> Value {
+    let direction = json!({ "type": "string", "enum": Direction::NAMES });
+    json!([
+        {
+            "name": MOVE,
+            "description": "Walk a number of blocks in a direction.",
+            "schema": {
+                "type": "object",
+                "properties": {
+                    "direction": direction,
+                    "distance": { "type": "integer", "minimum": 1, "maximum": MAX_MOVE_DISTANCE },
+                },
+                "required": ["direction", "distance"],
+            },
+        },
+        {
+            "name": MINE,
+            "description": "Break the block next to you in a direction.",
+            "schema": {
+                "type": "object",
+                "properties": { "direction": direction },
+                "required": ["direction"],
+            },
+        },
+        {
+            "name": BUILD,
+            "description": "Place a power cable next to you in a direction.",
+            "schema": {
+                "type": "object",
+                "properties": { "direction": direction },
+                "required": ["direction"],
+            },
+        },
+        {
+            "name": CHECK_BATTERY,
+            "description": "Report how much charge your battery has left.",
+        },
+    ])
+}
+
+/// Parse an incoming action. `data` is the stringified JSON Neuro sends, if any.
+/// The error is sent back to Neuro so it can retry with better arguments.
+pub fn parse(name: &str, data: Option<&str>) -> Result<Action, String> {
+    let data: Value = match data {
+        Some(data) => serde_json::from_str(data).map_err(|e| format!("Invalid JSON: {e}"))?,
+        None => Value::Null,
+    };
+    let direction = || {
+        let name = data
+            .get("direction")
+            .and_then(Value::as_str)
+            .ok_or("Missing direction")?;
+        Direction::parse(name).ok_or_else(|| format!("Unknown direction {name:?}"))
+    };
+
+    match name {
+        MOVE => {
+            let distance = data
+                .get("distance")
+                .and_then(Value::as_u64)
+                .ok_or("Missing distance")?;
+            if !(1..=u64::from(MAX_MOVE_DISTANCE)).contains(&distance) {
+                return Err(format!(
+                    "Distance must be between 1 and {MAX_MOVE_DISTANCE}"
+                ));
+            }
+            Ok(Action::Move {
+                direction: direction()?,
+                distance: distance as u32,
+            })
+        }
+        MINE => Ok(Action::Mine {
+            direction: direction()?,
+        }),
+        BUILD => Ok(Action::Build {
+            direction: direction()?,
+        }),
+        CHECK_BATTERY => Ok(Action::CheckBattery),
+        _ => Err(format!("Unknown action {name:?}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_every_action() {
+        assert_eq!(
+            parse(MOVE, Some(r#"{"direction": "north", "distance": 3}"#)),
+            Ok(Action::Move {
+                direction: Direction::North,
+                distance: 3,
+            })
+        );
+        assert_eq!(
+            parse(MINE, Some(r#"{"direction": "down"}"#)),
+            Ok(Action::Mine {
+                direction: Direction::Down,
+            })
+        );
+        assert_eq!(
+            parse(BUILD, Some(r#"{"direction": "west"}"#)),
+            Ok(Action::Build {
+                direction: Direction::West,
+            })
+        );
+        assert_eq!(parse(CHECK_BATTERY, None), Ok(Action::CheckBattery));
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        assert!(parse(MOVE, Some("{")).is_err());
+        assert!(parse(MOVE, Some(r#"{"direction": "north"}"#)).is_err());
+        assert!(parse(MOVE, Some(r#"{"direction": "north", "distance": 0}"#)).is_err());
+        assert!(
+            parse(
+                MOVE,
+                Some(&format!(
+                    r#"{{"direction": "north", "distance": {}}}"#,
+                    MAX_MOVE_DISTANCE + 1
+                ))
+            )
+            .is_err()
+        );
+        assert!(parse(MINE, None).is_err());
+        assert!(parse(BUILD, Some(r#"{"direction": "sideways"}"#)).is_err());
+        assert!(parse("fly", None).is_err());
+    }
+
+    #[test]
+    fn every_definition_parses_by_name() {
+        let definitions = definitions();
+        let names: Vec<&str> = definitions
+            .as_array()
+            .expect("an array of actions")
+            .iter()
+            .filter_map(|action| action["name"].as_str())
+            .collect();
+        assert_eq!(names, [MOVE, MINE, BUILD, CHECK_BATTERY]);
+    }
+
+    #[test]
+    fn direction_names_round_trip() {
+        for name in Direction::NAMES {
+            assert!(Direction::parse(name).is_some(), "{name}");
+        }
+    }
+}
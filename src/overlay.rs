@@ -0,0 +1,121 @@
+//! Debug readout shown in the window title while F3 is toggled on.
+
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+use crate::render::Renderer;
+use crate::render::memory::Category;
+use crate::time_of_day::TimeOfDay;
+
+/// How often the readout is rebuilt, so the title stays readable.
+const REFRESH: Duration = Duration::from_millis(500);
+
+pub struct Overlay {
+    visible: bool,
+    /// Title the window had before the overlay replaced it.
+    title: String,
+    last_refresh: Instant,
+    frames: u32,
+}
+
+impl Overlay {
+    pub fn new(window: &sdl3::video::Window) -> Self {
+        Self {
+            visible: false,
+            title: window.title().to_owned(),
+            last_refresh: Instant::now(),
+            frames: 0,
+        }
+    }
+
+    pub fn toggle(&mut self, window: &mut sdl3::video::Window) {
+        self.visible = !self.visible;
+        self.frames = 0;
+        self.last_refresh = Instant::now();
+        if !self.visible {
+            set_title(window, &self.title);
+        }
+    }
+
+    /// Count a rendered frame and refresh the readout if it is due.
+    pub fn frame(
+        &mut self,
+        window: &mut sdl3::video::Window,
+        r: &Renderer,
+        time_of_day: &TimeOfDay,
+    ) {
+        if !self.visible {
+            return;
+        }
+        self.frames += 1;
+        let elapsed = self.last_refresh.elapsed();
+        if elapsed < REFRESH {
+            return;
+        }
+
+        let mut text = format!("{:.0} fps", self.frames as f32 / elapsed.as_secs_f32());
+        let minutes = (time_of_day.time() * 24. * 60.) as u32;
+        let _ = write!(
+            text,
+            " | day {} {:?} {:02}:{:02} {:.0}\u{b0}C",
+            time_of_day.day(),
+            time_of_day.season(),
+            minutes / 60,
+            minutes % 60,
+            time_of_day.temperature(),
+        );
+        let _ = write!(text, " | render scale {:.2}", r.render_scale());
+        if let Some(scale) = r.dynamic_scale() {
+            let _ = write!(text, " dynamic {scale:.2}");
+        }
+        let tonemap = r.tonemap_settings();
+        let _ = write!(
+            text,
+            " | {:?} exposure {:.2}",
+            tonemap.tonemapper, tonemap.exposure
+        );
+        let culling = r.culling_stats();
+        let _ = write!(
+            text,
+            " | chunks {} drawn {} frustum culled {} occluded {} shadow {}",
+            culling.chunks,
+            culling.drawn,
+            culling.frustum_culled,
+            culling.occlusion_culled,
+            culling.shadow_drawn,
+        );
+        let arena = r.chunk_memory_stats();
+        let _ = write!(
+            text,
+            " | chunk vertices {:.1}/{:.1} MiB in {} blocks, {} free ranges, largest {:.1} MiB",
+            mib(arena.used),
+            mib(arena.capacity),
+            arena.blocks,
+            arena.free_ranges,
+            mib(arena.largest_free),
+        );
+        let memory = r.memory_report();
+        let _ = write!(text, " | memory {:.0}%", memory.pressure() * 100.);
+        for category in Category::ALL {
+            let _ = write!(
+                text,
+                " {} {:.0} MiB",
+                category.name(),
+                mib(memory.category(category))
+            );
+        }
+        set_title(window, &text);
+
+        self.frames = 0;
+        self.last_refresh = Instant::now();
+    }
+}
+
+fn mib(bytes: u64) -> f64 {
+    bytes as f64 / f64::from(1 << 20)
+}
+
+fn set_title(window: &mut sdl3::video::Window, title: &str) {
+    // Only fails on an interior nul, which none of the readouts contain.
+    let _ = window.set_title(title);
+}
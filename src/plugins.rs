@@ -0,0 +1,311 @@
+//! WebAssembly plugins.
+//!
+//! Plugins are `.wasm` modules that import host functions from the
+//! [`api::MODULE`] namespace and export `glacian_api_version`, plus optional
+//! `init` and `on_tick` hooks. Modules are compiled ahead of time and the
+//! native code is cached on disk, so only the first launch after a plugin
+//! changes pays for compilation.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "logging")]
+use piglog::prelude::*;
+use wasmtime::{Config, Engine, Linker, Store, Trap, TypedFunc};
+
+mod api;
+mod cache;
+
+pub use api::{API_VERSION, SpawnRequest};
+
+/// Instructions a plugin may execute per hook call before it is stopped.
+const FUEL_PER_CALL: u64 = 50_000_000;
+
+#[derive(Debug)]
+pub enum PluginError {
+    Io(std::io::Error),
+    Wasm(wasmtime::Error),
+    ApiVersion { plugin: String, version: i32 },
+    OutOfFuel { plugin: String },
+}
+
+impl fmt::Display for PluginError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{e}"),
+            Self::Wasm(e) => write!(f, "{e:#}"),
+            Self::ApiVersion { plugin, version } => write!(
+                f,
+                "plugin {plugin} targets API version {version}, host provides {API_VERSION}"
+            ),
+            Self::OutOfFuel { plugin } => write!(f, "plugin {plugin} ran out of fuel"),
+        }
+    }
+}
+
+impl std::error::Error for PluginError {}
+
+impl From<std::io::Error> for PluginError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<wasmtime::Error> for PluginError {
+    fn from(e: wasmtime::Error) -> Self {
+        Self::Wasm(e)
+    }
+}
+
+struct Plugin {
+    name: String,
+    store: Store<api::HostState>,
+    on_tick: Option<TypedFunc<f32, ()>>,
+    disabled: bool,
+}
+
+impl Plugin {
+    fn call<P: wasmtime::WasmParams>(
+        &mut self,
+        func: TypedFunc<P, ()>,
+        params: P,
+    ) -> Result<(), PluginError> {
+        self.store.set_fuel(FUEL_PER_CALL)?;
+        func.call(&mut self.store, params).map_err(|e| {
+            if e.downcast_ref::<Trap>() == Some(&Trap::OutOfFuel) {
+                PluginError::OutOfFuel {
+                    plugin: self.name.clone(),
+                }
+            } else {
+                e.into()
+            }
+        })
+    }
+}
+
+pub struct PluginHost {
+    engine: Engine,
+    linker: Linker<api::HostState>,
+    cache: cache::ModuleCache,
+    plugins: Vec<Plugin>,
+}
+
+impl PluginHost {
+    /// `cache_dir` holds precompiled native code and is created if missing.
+    pub fn new(cache_dir: PathBuf) -> Result<Self, PluginError> {
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config)?;
+
+        let mut linker = Linker::new(&engine);
+        api::link(&mut linker)?;
+
+        Ok(Self {
+            cache: cache::ModuleCache::new(cache_dir)?,
+            engine,
+            linker,
+            plugins: vec![],
+        })
+    }
+
+    /// Load every `.wasm` file in `dir`. A plugin that fails to load is logged
+    /// and skipped so one broken plugin does not stop the others.
+    pub fn load_dir(&mut self, dir: impl AsRef<Path>) -> Result<(), PluginError> {
+        let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "wasm"))
+            .collect();
+        paths.sort();
+
+        for path in paths {
+            if let Err(_e) = self.load(&path) {
+                #[cfg(feature = "logging")]
+                piglog::error!("Failed to load plugin {}: {_e}", path.display());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn load(&mut self, path: &Path) -> Result<(), PluginError> {
+        let name = path
+            .file_stem()
+            .map_or_else(String::new, |stem| stem.to_string_lossy().into_owned());
+
+        let bytes = std::fs::read(path)?;
+        let module = self.cache.load(&self.engine, &bytes)?;
+
+        let mut store = Store::new(&self.engine, api::HostState::new(name.clone()));
+        store.set_fuel(FUEL_PER_CALL)?;
+        let instance = self.linker.instantiate(&mut store, &module)?;
+
+        let version = instance
+            .get_typed_func::<(), i32>(&mut store, "glacian_api_version")?
+            .call(&mut store, ())?;
+        if version != API_VERSION {
+            return Err(PluginError::ApiVersion {
+                plugin: name,
+                version,
+            });
+        }
+
+        let init = instance.get_typed_func::<(), ()>(&mut store, "init").ok();
+        let on_tick = instance
+            .get_typed_func::<f32, ()>(&mut store, "on_tick")
+            .ok();
+
+        let mut plugin = Plugin {
+            name,
+            store,
+            on_tick,
+            disabled: false,
+        };
+        if let Some(init) = init {
+            plugin.call(init, ())?;
+        }
+
+        #[cfg(feature = "logging")]
+        piglog::note!("Loaded plugin {}", plugin.name);
+
+        self.plugins.push(plugin);
+        Ok(())
+    }
+
+    /// Run every plugin's `on_tick` hook. A plugin that traps or runs out of
+    /// fuel is disabled for the rest of the session.
+    pub fn tick(&mut self, dt: f32) {
+        for plugin in &mut self.plugins {
+            if plugin.disabled {
+                continue;
+            }
+            let Some(on_tick) = plugin.on_tick.clone() else {
+                continue;
+            };
+            if let Err(_e) = plugin.call(on_tick, dt) {
+                #[cfg(feature = "logging")]
+                piglog::error!("Disabling plugin {}: {_e}", plugin.name);
+                plugin.disabled = true;
+            }
+        }
+    }
+
+    /// Blocks registered by each plugin, in registration order.
+    pub fn registered_blocks(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.plugins.iter().flat_map(|plugin| {
+            plugin
+                .store
+                .data()
+                .blocks
+                .iter()
+                .map(|block| (plugin.name.as_str(), block.as_str()))
+        })
+    }
+
+    /// Take the entity spawns requested since the last call.
+    pub fn drain_spawns(&mut self) -> Vec<SpawnRequest> {
+        self.plugins
+            .iter_mut()
+            .flat_map(|plugin| std::mem::take(&mut plugin.store.data_mut().spawns))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOST_CALLS: &str = r#"
+        (module
+          (import "glacian_v1" "register_block" (func $register_block (param i32 i32) (result i32)))
+          (import "glacian_v1" "spawn_entity" (func $spawn_entity (param i32 i32 f32 f32 f32)))
+          (memory (export "memory") 1)
+          (data (i32.const 0) "lampyeti")
+          (func (export "glacian_api_version") (result i32) i32.const 1)
+          (func (export "init")
+            (drop (call $register_block (i32.const 0) (i32.const 4))))
+          (func (export "on_tick") (param f32)
+            (call $spawn_entity (i32.const 4) (i32.const 4)
+              (f32.const 1) (f32.const 2) (local.get 0))))
+    "#;
+
+    const SPINS: &str = r#"
+        (module
+          (func (export "glacian_api_version") (result i32) i32.const 1)
+          (func (export "on_tick") (param f32) (loop $spin (br $spin))))
+    "#;
+
+    const FUTURE: &str = r#"
+        (module
+          (func (export "glacian_api_version") (result i32) i32.const 99))
+    "#;
+
+    /// A host with its cache in a fresh temp dir, where plugins can be
+    /// written with [`write`].
+    fn host(test: &str) -> (PluginHost, PathBuf) {
+        let dir =
+            std::env::temp_dir().join(format!("glacian-plugins-{test}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let host = PluginHost::new(dir.join("cache")).expect("host starts");
+        (host, dir)
+    }
+
+    fn write(dir: &Path, name: &str, wat: &str) -> PathBuf {
+        let path = dir.join(format!("{name}.wat"));
+        std::fs::write(&path, wat).expect("temp file is writable");
+        path
+    }
+
+    #[test]
+    fn plugins_call_the_host_api() {
+        let (mut host, dir) = host("api");
+        host.load(&write(&dir, "lamps", HOST_CALLS))
+            .expect("plugin loads");
+        assert_eq!(
+            host.registered_blocks().collect::<Vec<_>>(),
+            [("lamps", "lamp")]
+        );
+        assert!(host.drain_spawns().is_empty());
+
+        host.tick(0.5);
+        let spawns = host.drain_spawns();
+        assert_eq!(
+            spawns,
+            [SpawnRequest {
+                plugin: "lamps".to_owned(),
+                kind: "yeti".to_owned(),
+                pos: glam::Vec3::new(1., 2., 0.5),
+            }]
+        );
+        assert!(host.drain_spawns().is_empty());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn running_out_of_fuel_disables_the_plugin() {
+        let (mut host, dir) = host("fuel");
+        host.load(&write(&dir, "spins", SPINS))
+            .expect("plugin loads");
+        host.load(&write(&dir, "lamps", HOST_CALLS))
+            .expect("plugin loads");
+
+        host.tick(0.05);
+        assert!(host.plugins[0].disabled);
+        assert!(!host.plugins[1].disabled);
+        assert_eq!(host.drain_spawns().len(), 1);
+
+        host.tick(0.05);
+        assert_eq!(host.drain_spawns().len(), 1);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn other_api_versions_are_refused() {
+        let (mut host, dir) = host("version");
+        let result = host.load(&write(&dir, "future", FUTURE));
+        assert!(
+            matches!(result, Err(PluginError::ApiVersion { version: 99, .. })),
+            "{result:?}"
+        );
+        assert!(host.plugins.is_empty());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,104 @@
+//! Functions the host exposes to plugins.
+//!
+//! Strings are passed as a pointer and length into the plugin's exported
+//! `memory`. Anything that changes the world is queued here and applied by the
+//! engine between ticks, so plugins never hold references into engine state.
+
+use glam::Vec3;
+#[cfg(feature = "logging")]
+use piglog::prelude::*;
+use wasmtime::{Caller, Extern, Linker};
+
+/// Bumped whenever a host function changes signature or meaning.
+pub const API_VERSION: i32 = 1;
+
+/// Import namespace for [`API_VERSION`].
+pub const MODULE: &str = "glacian_v1";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpawnRequest {
+    pub plugin: String,
+    pub kind: String,
+    pub pos: Vec3,
+}
+
+pub struct HostState {
+    pub plugin: String,
+    pub blocks: Vec<String>,
+    pub spawns: Vec<SpawnRequest>,
+}
+
+impl HostState {
+    pub const fn new(plugin: String) -> Self {
+        Self {
+            plugin,
+            blocks: vec![],
+            spawns: vec![],
+        }
+    }
+}
+
+/// Copy a UTF-8 string out of the caller's memory.
+fn read_str(caller: &mut Caller<'_, HostState>, ptr: u32, len: u32) -> wasmtime::Result<String> {
+    let Some(Extern::Memory(memory)) = caller.get_export("memory") else {
+        return Err(wasmtime::Error::msg("plugin does not export its memory"));
+    };
+    let start = ptr as usize;
+    let Some(bytes) = memory.data(&caller).get(start..start + len as usize) else {
+        return Err(wasmtime::Error::msg("string out of bounds"));
+    };
+    Ok(std::str::from_utf8(bytes)?.to_owned())
+}
+
+pub fn link(linker: &mut Linker<HostState>) -> wasmtime::Result<()> {
+    linker.func_wrap(MODULE, "api_version", || API_VERSION)?;
+
+    // Levels: 0 debug, 1 info, 2 warning, 3 error.
+    linker.func_wrap(
+        MODULE,
+        "log",
+        |mut caller: Caller<'_, HostState>, _level: i32, ptr: u32, len: u32| {
+            let _message = read_str(&mut caller, ptr, len)?;
+            #[cfg(feature = "logging")]
+            {
+                let plugin = &caller.data().plugin;
+                match _level {
+                    i32::MIN..=0 => piglog::debug!("[{plugin}] {_message}"),
+                    1 => piglog::note!("[{plugin}] {_message}"),
+                    2 => piglog::warning!("[{plugin}] {_message}"),
+                    _ => piglog::error!("[{plugin}] {_message}"),
+                }
+            }
+            Ok(())
+        },
+    )?;
+
+    // Returns the block's index among the blocks this plugin has registered.
+    linker.func_wrap(
+        MODULE,
+        "register_block",
+        |mut caller: Caller<'_, HostState>, ptr: u32, len: u32| {
+            let name = read_str(&mut caller, ptr, len)?;
+            let blocks = &mut caller.data_mut().blocks;
+            blocks.push(name);
+            Ok(blocks.len() as u32 - 1)
+        },
+    )?;
+
+    linker.func_wrap(
+        MODULE,
+        "spawn_entity",
+        |mut caller: Caller<'_, HostState>, ptr: u32, len: u32, x: f32, y: f32, z: f32| {
+            let kind = read_str(&mut caller, ptr, len)?;
+            let state = caller.data_mut();
+            state.spawns.push(SpawnRequest {
+                plugin: state.plugin.clone(),
+                kind,
+                pos: Vec3::new(x, y, z),
+            });
+            Ok(())
+        },
+    )?;
+
+    Ok(())
+}
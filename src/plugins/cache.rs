@@ -0,0 +1,115 @@
+use std::path::PathBuf;
+
+#[cfg(feature = "logging")]
+use piglog::prelude::*;
+use wasmtime::{Engine, Module};
+
+use super::PluginError;
+
+/// Native code for compiled modules, stored as `<blake3 of the wasm>.cwasm`.
+pub struct ModuleCache {
+    dir: PathBuf,
+}
+
+impl ModuleCache {
+    pub fn new(dir: PathBuf) -> Result<Self, PluginError> {
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    pub fn load(&self, engine: &Engine, wasm: &[u8]) -> Result<Module, PluginError> {
+        let path = self.path(wasm);
+
+        if path.exists() {
+            // SAFETY: artifacts only appear under their final name once `load`
+            // has written all of what `precompile_module` returned, so they are
+            // whole. Artifacts from another wasmtime build or config are
+            // rejected by the header check and recompiled.
+            match unsafe { Module::deserialize_file(engine, &path) } {
+                Ok(module) => return Ok(module),
+                Err(_e) => {
+                    #[cfg(feature = "logging")]
+                    piglog::warning!("Discarding stale plugin cache {}: {_e}", path.display());
+                }
+            }
+        }
+
+        let native = engine.precompile_module(wasm)?;
+        // Write then rename so a crash mid-write cannot leave a torn artifact.
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, &native)?;
+        std::fs::rename(&tmp, &path)?;
+        // SAFETY: `native` was produced by this engine just above.
+        Ok(unsafe { Module::deserialize(engine, &native) }?)
+    }
+
+    fn path(&self, wasm: &[u8]) -> PathBuf {
+        self.dir
+            .join(format!("{}.cwasm", blake3::hash(wasm).to_hex()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: &str = r#"(module (func (export "a")))"#;
+    const B: &str = r#"(module (func (export "b")))"#;
+
+    fn cache(name: &str) -> ModuleCache {
+        let dir = std::env::temp_dir().join(format!(
+            "glacian-plugin-cache-{name}-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        ModuleCache::new(dir).expect("temp dir is writable")
+    }
+
+    fn exports(module: &Module) -> Vec<&str> {
+        module.exports().map(|export| export.name()).collect()
+    }
+
+    #[test]
+    fn a_miss_compiles_and_stores_the_module() {
+        let engine = Engine::default();
+        let cache = cache("miss");
+        let module = cache.load(&engine, B.as_bytes()).expect("module compiles");
+        assert_eq!(exports(&module), ["b"]);
+
+        let path = cache.path(B.as_bytes());
+        assert!(path.exists());
+        assert!(!path.with_extension("tmp").exists());
+        let _ = std::fs::remove_dir_all(&cache.dir);
+    }
+
+    #[test]
+    fn a_hit_loads_the_stored_artifact() {
+        let engine = Engine::default();
+        let cache = cache("hit");
+        // Store A's code under B's hash, so only a cache hit can give A back.
+        let native = engine.precompile_module(A.as_bytes()).expect("A compiles");
+        std::fs::write(cache.path(B.as_bytes()), native).expect("temp file is writable");
+
+        let module = cache.load(&engine, B.as_bytes()).expect("artifact loads");
+        assert_eq!(exports(&module), ["a"]);
+        let _ = std::fs::remove_dir_all(&cache.dir);
+    }
+
+    #[test]
+    fn a_corrupt_artifact_is_recompiled() {
+        let engine = Engine::default();
+        let cache = cache("corrupt");
+        let path = cache.path(B.as_bytes());
+        std::fs::write(&path, b"not native code").expect("temp file is writable");
+
+        let module = cache
+            .load(&engine, B.as_bytes())
+            .expect("module recompiles");
+        assert_eq!(exports(&module), ["b"]);
+        assert_ne!(
+            std::fs::read(&path).expect("artifact was rewritten"),
+            b"not native code"
+        );
+        let _ = std::fs::remove_dir_all(&cache.dir);
+    }
+}
@@ -0,0 +1,31 @@
+//! Tracy instrumentation that disappears when the `tracy` feature is off.
+
+/// Open a CPU zone that lasts until the end of the enclosing block.
+#[cfg(feature = "tracy")]
+macro_rules! zone {
+    ($name:literal) => {
+        let _zone = tracy_client::span!($name);
+    };
+}
+
+#[cfg(not(feature = "tracy"))]
+macro_rules! zone {
+    ($name:literal) => {};
+}
+
+pub(crate) use zone;
+
+/// Start the Tracy client. Keep the returned value alive for the whole run.
+#[cfg(feature = "tracy")]
+pub fn start() -> tracy_client::Client {
+    tracy_client::Client::start()
+}
+
+/// Mark the end of a frame.
+#[inline(always)]
+pub fn frame_mark() {
+    #[cfg(feature = "tracy")]
+    if let Some(client) = tracy_client::Client::running() {
+        client.frame_mark();
+    }
+}
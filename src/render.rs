@@ -1,5 +1,6 @@
 use std::ffi::CString;
 use std::mem::ManuallyDrop;
+use std::time::{Duration, Instant};
 
 use vulkanalia::vk::{
     self, ExtDebugUtilsExtensionInstanceCommands, Handle, HasBuilder,
@@ -7,22 +8,57 @@ use vulkanalia::vk::{
 };
 use vulkanalia::vk::{DeviceV1_0, DeviceV1_3, InstanceV1_0};
 mod allocations;
-use allocations::{AllocatedImage, DescriptorAllocator};
 mod swapchain;
 use swapchain::{FrameData, SwapchainData};
+pub mod memory;
+pub mod mesh_arena;
 mod mesh_buffer;
 
 mod utils;
-use utils::{copy_image_to_image, transition_image};
 
+mod bindless;
+use bindless::Bindless;
+pub mod block_textures;
+use block_textures::BlockTextures;
+pub mod chunks;
+use chunks::{ChunkVertex, Chunks};
+pub mod culling;
+use culling::Culling;
 mod descriptor;
+pub mod graph;
+use graph::{Access, Graph, ImportedImage, Load};
 
 use piglog::prelude::*;
 use piglog::warning;
 
 mod debug;
 
+mod pipeline;
+mod pipeline_cache;
+use pipeline_cache::PipelineCache;
+mod scene;
+use scene::{SceneBuffers, SceneData};
+mod shaders;
+pub mod shadows;
 mod skybox;
+mod targets;
+mod timeline;
+use timeline::Timeline;
+pub mod tonemap;
+mod upscale;
+
+#[cfg(feature = "tracy")]
+mod gpu_profiler;
+
+#[cfg(feature = "hot-reload")]
+mod hot_reload;
+
+use crate::profiling::zone;
+use crate::time_of_day::TimeOfDay;
+
+const FIELD_OF_VIEW_Y: f32 = 70_f32.to_radians();
+const NEAR_PLANE: f32 = 0.1;
+const FAR_PLANE: f32 = 1000.;
 
 pub struct Renderer {
     _entry: vulkanalia::Entry,
@@ -34,6 +70,7 @@ pub struct Renderer {
     queue: vk::Queue,
 
     allocator: ManuallyDrop<vulkanalia_vma::Allocator>,
+    memory: memory::Tracker,
 
     #[cfg(feature = "logging")]
     debug_messenger: vk::DebugUtilsMessengerEXT,
@@ -42,23 +79,62 @@ pub struct Renderer {
 
     frame_data: [FrameData; 2],
     frame_count: u64,
-
-    draw_image: AllocatedImage,
+    /// Signalled by every submission, frames and uploads alike.
+    timeline: Timeline,
+
+    transients: graph::Transients,
+    /// Size of the images drawn at render resolution.
+    render_extent: vk::Extent2D,
+    linear_sampler: vk::Sampler,
+    linear_sampler_handle: bindless::Handle<bindless::Sampler>,
+    /// Part of the draw image rendered to this frame.
     draw_extent: vk::Extent2D,
+    /// Size of the draw image relative to the swapchain.
+    render_scale: f32,
+    dynamic_resolution: Option<upscale::DynamicResolution>,
+    last_frame: Option<Instant>,
+    start_time: Instant,
+
+    bindless: Bindless,
 
-    descriptor_allocator: DescriptorAllocator,
+    block_textures: BlockTextures,
+
+    chunks: Chunks,
+    chunk_data: chunks::Data,
+    culling: Culling,
+    culling_data: culling::Data,
+    scene_buffers: SceneBuffers,
+    shadow_data: shadows::Data,
+    shadow_map: shadows::Map,
+    shadow_settings: shadows::Settings,
 
     aspect_ratio: f32,
 
+    pipeline_cache: PipelineCache,
+
     skybox_data: skybox::Data,
+    pub sky: skybox::Settings,
+    tonemap_data: tonemap::Data,
+    tonemap: tonemap::Settings,
+    upscale_data: upscale::Data,
+    pub upscale: upscale::Settings,
+
+    #[cfg(feature = "tracy")]
+    /// `None` if the GPU context could not be set up, leaving Tracy with
+    /// CPU zones only.
+    gpu_profiler: Option<gpu_profiler::GpuProfiler>,
+
+    #[cfg(feature = "hot-reload")]
+    shader_watcher: hot_reload::ShaderWatcher,
 }
 
 impl Renderer {
-    const fn get_current_framedata(&self) -> FrameData {
-        self.frame_data[self.frame_count as usize & 1]
+    const fn get_current_framedata(&self) -> &FrameData {
+        &self.frame_data[self.frame_count as usize & 1]
     }
 
-    pub fn new(window: &sdl3::video::Window) -> Self
+    /// `data_dir` is where per-user files such as the pipeline cache are kept.
+    pub fn new(window: &sdl3::video::Window, data_dir: &std::path::Path) -> Self
     where
         Self: Sized,
     {
@@ -79,6 +155,14 @@ impl Renderer {
             .collect::<Vec<CString>>();
 
         let mut extension_names: Vec<*const i8> = extensions.iter().map(|cs| cs.as_ptr()).collect();
+        // VMA queries heap budgets through this extension's entry point,
+        // which the loader only has to provide when it is enabled.
+        extension_names.push(
+            vk::KHR_GET_PHYSICAL_DEVICE_PROPERTIES2_EXTENSION
+                .name
+                .as_cstr()
+                .as_ptr(),
+        );
 
         #[cfg(feature = "logging")]
         extension_names.push(vk::EXT_DEBUG_UTILS_EXTENSION.name.as_cstr().as_ptr());
@@ -138,7 +222,15 @@ impl Renderer {
                     .unwrap()
             });
 
-        let device_extensions = vec![vk::KHR_SWAPCHAIN_EXTENSION.name.as_cstr().as_ptr()];
+        let mut device_extensions = vec![vk::KHR_SWAPCHAIN_EXTENSION.name.as_cstr().as_ptr()];
+        let memory_budget =
+            unsafe { instance.enumerate_device_extension_properties(physical_device, None) }
+                .unwrap()
+                .iter()
+                .any(|ext| ext.extension_name == vk::EXT_MEMORY_BUDGET_EXTENSION.name);
+        if memory_budget {
+            device_extensions.push(vk::EXT_MEMORY_BUDGET_EXTENSION.name.as_cstr().as_ptr());
+        }
 
         let queuefamilyproperties =
             unsafe { instance.get_physical_device_queue_family_properties(physical_device) };
@@ -164,10 +256,17 @@ impl Renderer {
                     &vk::DeviceCreateInfo::builder()
                         .enabled_extension_names(&device_extensions)
                         .queue_create_infos(queue_create_info)
+                        .enabled_features(
+                            &vk::PhysicalDeviceFeatures::builder()
+                                .multi_draw_indirect(true)
+                                .draw_indirect_first_instance(true),
+                        )
                         .push_next(
-                            &mut vk::PhysicalDeviceVulkan12Features::builder()
-                                .descriptor_indexing(true)
-                                .buffer_device_address(true),
+                            &mut Bindless::features()
+                                .buffer_device_address(true)
+                                .draw_indirect_count(true)
+                                .sampler_filter_minmax(true)
+                                .timeline_semaphore(true),
                         )
                         .push_next(
                             &mut vk::PhysicalDeviceVulkan11Features::builder()
@@ -184,6 +283,7 @@ impl Renderer {
         };
 
         let queue = unsafe { device.get_device_queue(qfamindices, 0) };
+        let mut timeline = Timeline::new(&device);
 
         let swapchain_data =
             SwapchainData::new(window, &instance, &physical_device, &[qfamindices], &device);
@@ -192,68 +292,115 @@ impl Renderer {
 
         let mut alloc_create_info =
             vulkanalia_vma::AllocatorOptions::new(&instance, &device, physical_device);
+        alloc_create_info.version = vulkanalia::Version::V1_3_0;
         alloc_create_info.flags = vulkanalia_vma::AllocatorCreateFlags::BUFFER_DEVICE_ADDRESS;
+        if memory_budget {
+            alloc_create_info.flags |= vulkanalia_vma::AllocatorCreateFlags::EXT_MEMORY_BUDGET;
+        }
 
         let allocator = unsafe { vulkanalia_vma::Allocator::new(&alloc_create_info) }.unwrap();
 
-        let win_size = window.size();
-        let draw_extent = vk::Extent2D {
-            height: win_size.1,
-            width: win_size.0,
-        };
+        let render_scale = 1.;
+        let render_extent = targets::scale_extent(swapchain_data.extent, render_scale);
 
-        let draw_image = AllocatedImage::new(
-            vk::Format::R16G16B16A16_SFLOAT,
-            vk::ImageUsageFlags::TRANSFER_SRC
-                | vk::ImageUsageFlags::TRANSFER_DST
-                | vk::ImageUsageFlags::STORAGE
-                | vk::ImageUsageFlags::COLOR_ATTACHMENT,
-            vk::Extent3D::builder()
-                .width(draw_extent.width)
-                .height(draw_extent.height)
-                .depth(1)
-                .build(),
-            vk::ImageAspectFlags::COLOR,
+        let mut bindless = Bindless::new(&instance, physical_device, &device).unwrap();
+        let linear_sampler = allocations::create_sampler(
+            &device,
+            vk::Filter::LINEAR,
+            vk::Filter::LINEAR,
+            vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            1,
+        );
+        let linear_sampler_handle = bindless.add_sampler(&device, linear_sampler);
+
+        let block_textures = BlockTextures::new(
+            std::path::Path::new(block_textures::TEXTURE_DIR),
+            &mut bindless,
             &allocator,
             &device,
+            queue,
+            &mut timeline,
         );
 
-        let sizes: Vec<(vk::DescriptorType, u32)> = (0..10)
-            .map(|_| (vk::DescriptorType::STORAGE_IMAGE, 1))
-            .collect();
-
-        let descriptor_allocator = DescriptorAllocator::new(&device, 10, &sizes);
-        // let draw_image_descriptor_layout = {
-        //     let mut builder = DescriptorLayoutBuilder::new();
-        //     builder.add_binding(0, vk::DescriptorType::STORAGE_IMAGE);
-        //     builder.build(
-        //         &device,
-        //         vk::ShaderStageFlags::COMPUTE,
-        //         vk::DescriptorSetLayoutCreateFlags::empty(),
-        //     )
-        // };
-        // let draw_image_descriptors =
-        //     descriptor_allocator.allocate(&device, draw_image_descriptor_layout);
-        //
-        // let img_info = [vk::DescriptorImageInfo::default()
-        //     .image_layout(vk::ImageLayout::GENERAL)
-        //     .image_view(draw_image.view)];
-        //
-        // let draw_image_write = vk::WriteDescriptorSet::default()
-        //     .dst_binding(0)
-        //     .dst_set(draw_image_descriptors)
-        //     .descriptor_count(1)
-        //     .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
-        //     .image_info(&img_info);
-        //
-        // unsafe { device.update_descriptor_sets(&[draw_image_write], &[]) };
-
-        let skybox_data = skybox::Data::new(&device).unwrap();
+        let pipeline_cache = PipelineCache::new(
+            &instance,
+            physical_device,
+            &device,
+            data_dir.join("pipeline-cache.bin"),
+        );
+
+        let skybox_data = skybox::Data::new(
+            &device,
+            pipeline_cache.cache,
+            &mut bindless,
+            &allocator,
+            queue,
+            &mut timeline,
+        )
+        .unwrap();
+        let chunk_data = chunks::Data::new(
+            &device,
+            pipeline_cache.cache,
+            bindless.layout,
+            targets::DRAW_FORMAT,
+            targets::DEPTH_FORMAT,
+        )
+        .unwrap();
+        let chunks = Chunks::new(&allocator, &device, queue, &mut timeline);
+        let culling = Culling::new(render_extent, &mut bindless, &allocator, &device);
+        let culling_data =
+            culling::Data::new(&device, pipeline_cache.cache, bindless.layout).unwrap();
+        let scene_buffers = SceneBuffers::new(&mut bindless, &allocator, &device);
+        let shadow_settings = shadows::Settings::default();
+        let shadow_data =
+            shadows::Data::new(&device, pipeline_cache.cache, bindless.layout).unwrap();
+        let shadow_map = shadows::Map::new(
+            shadow_settings.resolution,
+            &mut bindless,
+            &allocator,
+            &device,
+        );
+        let tonemap_data = tonemap::Data::new(
+            &device,
+            pipeline_cache.cache,
+            bindless.layout,
+            targets::LDR_FORMAT,
+        )
+        .unwrap();
+        let upscale_data = upscale::Data::new(
+            &device,
+            pipeline_cache.cache,
+            bindless.layout,
+            targets::LDR_FORMAT,
+            swapchain::FORMAT,
+        )
+        .unwrap();
 
         let (width, height) = window.size();
+        let device_type =
+            unsafe { instance.get_physical_device_properties(physical_device) }.device_type;
 
         let allocator = ManuallyDrop::new(allocator);
 
+        #[cfg(feature = "logging")]
+        if !memory_budget {
+            piglog::warning!("VK_EXT_memory_budget is unsupported, memory budgets are estimates");
+        }
+
+        #[cfg(feature = "tracy")]
+        let gpu_profiler = gpu_profiler::GpuProfiler::new(
+            &instance,
+            physical_device,
+            &device,
+            queue,
+            &mut timeline,
+        )
+        .inspect_err(|_e| {
+            #[cfg(feature = "logging")]
+            piglog::warning!("Profiling without GPU zones: {_e}");
+        })
+        .ok();
+
         Self {
             _entry: entry,
             instance,
@@ -265,14 +412,32 @@ impl Renderer {
             swapchain_data,
             frame_data,
             frame_count: 0,
+            timeline,
             allocator,
-
-            draw_extent,
-            draw_image,
-
-            descriptor_allocator,
-            // draw_image_descriptors,
-            // draw_image_descriptor_layout,
+            memory: memory::Tracker::new(memory_budget),
+
+            draw_extent: render_extent,
+            transients: graph::Transients::default(),
+            render_extent,
+            render_scale,
+            dynamic_resolution: None,
+            last_frame: None,
+            start_time: Instant::now(),
+            linear_sampler,
+            linear_sampler_handle,
+
+            bindless,
+
+            block_textures,
+
+            chunks,
+            chunk_data,
+            culling,
+            culling_data,
+            scene_buffers,
+            shadow_data,
+            shadow_map,
+            shadow_settings,
 
             // comp_pipeline,
             // comp_pipeline_layout,
@@ -281,12 +446,102 @@ impl Renderer {
             // graphics_pipeline_layout,
             queue,
 
+            pipeline_cache,
+
             skybox_data,
+            sky: skybox::Settings {
+                quality: skybox::Quality::for_device(device_type),
+                ..Default::default()
+            },
+            tonemap_data,
+            tonemap: tonemap::Settings::default(),
+            upscale_data,
+            upscale: upscale::Settings::default(),
+
+            #[cfg(feature = "tracy")]
+            gpu_profiler,
+
+            #[cfg(feature = "hot-reload")]
+            shader_watcher: hot_reload::ShaderWatcher::new(),
 
             aspect_ratio: width as f32 / height as f32,
         }
     }
 
+    pub const fn block_textures(&self) -> &BlockTextures {
+        &self.block_textures
+    }
+
+    /// Upload the quads of the chunk at `pos`, four vertices each as
+    /// [`chunks::mesher::mesh`] makes them, replacing any previous mesh. An
+    /// empty mesh removes the chunk.
+    pub fn upload_chunk(&mut self, pos: glam::IVec3, vertices: &[ChunkVertex]) {
+        self.chunks.insert(
+            pos,
+            vertices,
+            &self.allocator,
+            &self.device,
+            self.queue,
+            &mut self.timeline,
+        );
+    }
+
+    pub fn remove_chunk(&mut self, pos: glam::IVec3) {
+        self.chunks.remove(pos);
+    }
+
+    /// Chunk culling counts from a recent frame, for the debug overlay.
+    pub const fn culling_stats(&self) -> culling::Stats {
+        self.culling.stats()
+    }
+
+    /// How full and fragmented the arena holding chunk vertices is.
+    pub fn chunk_memory_stats(&self) -> mesh_arena::Stats {
+        self.chunks.arena_stats()
+    }
+
+    /// Per heap usage and budget and bytes by category as of the latest
+    /// frame, for the debug overlay and for chunk streaming to size the view
+    /// distance with [`memory::Report::view_distance`].
+    pub const fn memory_report(&self) -> &memory::Report {
+        self.memory.report()
+    }
+
+    pub const fn shadow_settings(&self) -> &shadows::Settings {
+        &self.shadow_settings
+    }
+
+    /// Takes effect from the next frame. Changing the resolution waits for the
+    /// device to go idle and recreates the shadow map.
+    pub fn set_shadow_settings(&mut self, settings: shadows::Settings) {
+        if settings.resolution != self.shadow_map.resolution() {
+            unsafe { self.device.device_wait_idle() }.unwrap();
+            self.shadow_map
+                .destroy(&mut self.bindless, &self.allocator, &self.device);
+            self.shadow_map = shadows::Map::new(
+                settings.resolution,
+                &mut self.bindless,
+                &self.allocator,
+                &self.device,
+            );
+        }
+        self.shadow_settings = settings;
+    }
+
+    pub const fn tonemap_settings(&self) -> tonemap::Settings {
+        self.tonemap
+    }
+
+    pub const fn set_tonemapper(&mut self, tonemapper: tonemap::Tonemapper) {
+        self.tonemap.tonemapper = tonemapper;
+    }
+
+    /// Linear multiplier applied to the HDR image before tonemapping.
+    /// Negative values are treated as 0.
+    pub const fn set_exposure(&mut self, exposure: f32) {
+        self.tonemap.exposure = exposure.max(0.);
+    }
+
     pub fn resize(&mut self, window: &sdl3::video::Window) {
         let (width, height) = window.size();
 
@@ -300,32 +555,245 @@ impl Renderer {
             &self.device,
         );
 
-        self.draw_extent = vk::Extent2D { height, width };
-        self.draw_image.flush(&self.device, &self.allocator);
-        self.draw_image = AllocatedImage::new(
-            vk::Format::R16G16B16A16_SFLOAT,
-            vk::ImageUsageFlags::TRANSFER_SRC
-                | vk::ImageUsageFlags::TRANSFER_DST
-                | vk::ImageUsageFlags::STORAGE
-                | vk::ImageUsageFlags::COLOR_ATTACHMENT,
-            vk::Extent3D::builder()
-                .width(self.draw_extent.width)
-                .height(self.draw_extent.height)
-                .depth(1)
-                .build(),
-            vk::ImageAspectFlags::COLOR,
+        self.recreate_targets();
+
+        self.aspect_ratio = width as f32 / height as f32;
+    }
+
+    pub const fn render_scale(&self) -> f32 {
+        self.render_scale
+    }
+
+    /// Size the draw image at `scale` times the window, clamped to
+    /// [`upscale::MIN_RENDER_SCALE`]..=[`upscale::MAX_RENDER_SCALE`].
+    pub fn set_render_scale(&mut self, scale: f32) {
+        let scale = scale.clamp(upscale::MIN_RENDER_SCALE, upscale::MAX_RENDER_SCALE);
+        if scale == self.render_scale {
+            return;
+        }
+        self.render_scale = scale;
+
+        unsafe { self.device.device_wait_idle() }.unwrap();
+        self.recreate_targets();
+    }
+
+    /// Render only as much of the draw image as fits in a `target` frame
+    /// time, or always all of it with `None`.
+    pub fn set_dynamic_resolution(&mut self, target: Option<Duration>) {
+        self.dynamic_resolution = target.map(upscale::DynamicResolution::new);
+    }
+
+    /// Share of the draw image rendered along each axis while dynamic
+    /// resolution is on.
+    pub fn dynamic_scale(&self) -> Option<f32> {
+        self.dynamic_resolution
+            .as_ref()
+            .map(upscale::DynamicResolution::scale)
+    }
+
+    /// Expects the device to be idle.
+    fn recreate_targets(&mut self) {
+        self.render_extent = targets::scale_extent(self.swapchain_data.extent, self.render_scale);
+        self.draw_extent = self.render_extent;
+        self.culling.resize(
+            self.render_extent,
+            &mut self.bindless,
             &self.allocator,
             &self.device,
         );
+    }
 
-        self.aspect_ratio = width as f32 / height as f32;
+    /// Rebuild pipelines whose SPIR-V changed on disk. A pipeline that fails
+    /// to build is logged and the previous one stays in use.
+    #[cfg(feature = "hot-reload")]
+    fn reload_shaders(&mut self) {
+        let changed = self.shader_watcher.poll();
+        let affects = |shader| changed.iter().any(|path| hot_reload::affects(path, shader));
+
+        if affects(&shaders::SKYBOX) {
+            match skybox::Data::new(
+                &self.device,
+                self.pipeline_cache.cache,
+                &mut self.bindless,
+                &self.allocator,
+                self.queue,
+                &mut self.timeline,
+            ) {
+                Ok(data) => {
+                    unsafe { self.device.device_wait_idle() }.unwrap();
+                    std::mem::replace(&mut self.skybox_data, data).destroy(
+                        &mut self.bindless,
+                        &self.allocator,
+                        &self.device,
+                    );
+                    piglog::note!("Reloaded {}", shaders::SKYBOX.name);
+                }
+                Err(e) => piglog::error!("Keeping the previous skybox pipeline: {e}"),
+            }
+        }
+
+        if affects(&shaders::CHUNK) {
+            match chunks::Data::new(
+                &self.device,
+                self.pipeline_cache.cache,
+                self.bindless.layout,
+                targets::DRAW_FORMAT,
+                targets::DEPTH_FORMAT,
+            ) {
+                Ok(data) => {
+                    unsafe { self.device.device_wait_idle() }.unwrap();
+                    std::mem::replace(&mut self.chunk_data, data).destroy(&self.device);
+                    piglog::note!("Reloaded {}", shaders::CHUNK.name);
+                }
+                Err(e) => piglog::error!("Keeping the previous chunk pipeline: {e}"),
+            }
+        }
+
+        if affects(&shaders::SHADOW) {
+            match shadows::Data::new(
+                &self.device,
+                self.pipeline_cache.cache,
+                self.bindless.layout,
+            ) {
+                Ok(data) => {
+                    unsafe { self.device.device_wait_idle() }.unwrap();
+                    std::mem::replace(&mut self.shadow_data, data).destroy(&self.device);
+                    piglog::note!("Reloaded {}", shaders::SHADOW.name);
+                }
+                Err(e) => piglog::error!("Keeping the previous shadow pipeline: {e}"),
+            }
+        }
+
+        if affects(&shaders::CULL) || affects(&shaders::HIZ) {
+            match culling::Data::new(
+                &self.device,
+                self.pipeline_cache.cache,
+                self.bindless.layout,
+            ) {
+                Ok(data) => {
+                    unsafe { self.device.device_wait_idle() }.unwrap();
+                    std::mem::replace(&mut self.culling_data, data).destroy(&self.device);
+                    piglog::note!("Reloaded the culling shaders");
+                }
+                Err(e) => piglog::error!("Keeping the previous culling pipelines: {e}"),
+            }
+        }
+
+        if affects(&shaders::TONEMAP) {
+            match tonemap::Data::new(
+                &self.device,
+                self.pipeline_cache.cache,
+                self.bindless.layout,
+                targets::LDR_FORMAT,
+            ) {
+                Ok(data) => {
+                    unsafe { self.device.device_wait_idle() }.unwrap();
+                    std::mem::replace(&mut self.tonemap_data, data).destroy(&self.device);
+                    piglog::note!("Reloaded {}", shaders::TONEMAP.name);
+                }
+                Err(e) => piglog::error!("Keeping the previous tonemap pipeline: {e}"),
+            }
+        }
+
+        if affects(&shaders::EASU) || affects(&shaders::RCAS) {
+            match upscale::Data::new(
+                &self.device,
+                self.pipeline_cache.cache,
+                self.bindless.layout,
+                targets::LDR_FORMAT,
+                swapchain::FORMAT,
+            ) {
+                Ok(data) => {
+                    unsafe { self.device.device_wait_idle() }.unwrap();
+                    std::mem::replace(&mut self.upscale_data, data).destroy(&self.device);
+                    piglog::note!("Reloaded the upscaling shaders");
+                }
+                Err(e) => piglog::error!("Keeping the previous upscaling pipelines: {e}"),
+            }
+        }
     }
 
-    pub fn render(&mut self, _view_mat: glam::Mat4, sky_color: glam::Vec3A) {
-        let fence = self.get_current_framedata().render_fence;
-        unsafe { self.device.wait_for_fences(&[fence], true, 1_000_000_000) }.unwrap();
-        unsafe { self.device.reset_fences(&[fence]) }.unwrap();
+    fn update_memory(&mut self) {
+        let allocator = &self.allocator;
+        let tagged = [
+            (
+                memory::Category::Chunks,
+                self.chunks.allocated_bytes(allocator) + self.culling.buffer_bytes(allocator),
+            ),
+            (
+                memory::Category::Textures,
+                self.block_textures.allocated_bytes(allocator)
+                    + self.skybox_data.allocated_bytes(allocator),
+            ),
+            (
+                memory::Category::RenderTargets,
+                self.transients.allocated_bytes(allocator)
+                    + self.shadow_map.allocated_bytes(allocator)
+                    + self.culling.pyramid_bytes(allocator),
+            ),
+        ];
+        self.memory.update(self.frame_count, tagged, allocator);
+    }
+
+    /// Block until the GPU has finished frame `frame` or `timeout` has
+    /// passed, returning whether it finished. Frames not rendered yet never
+    /// finish.
+    pub fn wait_for_frame(&self, frame: u64, timeout: Duration) -> bool {
+        let timeout = u64::try_from(timeout.as_nanos()).unwrap_or(u64::MAX);
+        self.timeline.wait_for_frame(&self.device, frame, timeout)
+    }
+
+    /// Perspective projection into Vulkan's clip space. Clip space Y points
+    /// down the screen like world space Z, so the camera's up vector is `+Z`.
+    fn projection(&self) -> glam::Mat4 {
+        glam::Mat4::perspective_rh(FIELD_OF_VIEW_Y, self.aspect_ratio, NEAR_PLANE, FAR_PLANE)
+    }
+
+    pub fn render(&mut self, view: glam::Mat4, time: &TimeOfDay) {
+        zone!("render");
+
+        #[cfg(feature = "hot-reload")]
+        self.reload_shaders();
+
+        // The frame that last used this frame's slot.
+        if let Some(previous) = self.frame_count.checked_sub(self.frame_data.len() as u64) {
+            zone!("wait for frame");
+            // A slow GPU is not an error, so keep waiting but say so. A lost
+            // device fails the wait rather than timing out.
+            while !self.wait_for_frame(previous, Duration::from_secs(1)) {
+                #[cfg(feature = "logging")]
+                warning!("Frame {previous} has taken more than a second on the GPU");
+            }
+        }
+        let finished = self.timeline.frames_finished(&self.device);
+        self.bindless.begin_frame(self.frame_count, finished);
+        self.transients.begin_frame(
+            self.frame_count,
+            finished,
+            &mut self.bindless,
+            &self.allocator,
+            &self.device,
+        );
+        self.chunks.begin_frame(
+            self.frame_count,
+            &self.allocator,
+            &self.device,
+            self.queue,
+            &mut self.timeline,
+        );
+        self.culling.begin_frame(
+            self.frame_count as usize & 1,
+            &self.chunks,
+            &mut self.bindless,
+            &self.allocator,
+            &self.device,
+        );
+        self.update_memory();
+        self.frame_data[self.frame_count as usize & 1]
+            .descriptors
+            .clear_descriptors(&self.device);
         let cmd_buf = self.get_current_framedata().buf;
+        let frame = self.frame_count as usize & 1;
 
         let swapchain_images = unsafe {
             self.device
@@ -334,6 +802,7 @@ impl Renderer {
         .unwrap();
 
         let next_img = unsafe {
+            zone!("acquire swapchain image");
             self.device
                 .acquire_next_image_khr(
                     self.swapchain_data.swapchain,
@@ -355,8 +824,14 @@ impl Renderer {
         }
         .unwrap();
 
-        self.draw_extent.width = self.draw_image.extent.width;
-        self.draw_extent.height = self.draw_image.extent.height;
+        let now = Instant::now();
+        let dynamic_scale = match (&mut self.dynamic_resolution, self.last_frame) {
+            (Some(dynamic), Some(last)) => dynamic.update(now - last),
+            (Some(dynamic), None) => dynamic.scale(),
+            (None, _) => 1.,
+        };
+        self.last_frame = Some(now);
+        self.draw_extent = targets::scale_extent(self.render_extent, dynamic_scale);
 
         unsafe {
             self.device.begin_command_buffer(
@@ -367,61 +842,226 @@ impl Renderer {
         }
         .unwrap();
 
-        transition_image(
-            cmd_buf,
-            self.draw_image.image,
-            vk::ImageLayout::UNDEFINED,
-            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-            &self.device,
+        #[cfg(feature = "tracy")]
+        if let Some(profiler) = &mut self.gpu_profiler {
+            profiler.begin_frame(&self.device, cmd_buf, frame);
+        }
+
+        let view_proj = self.projection() * view;
+        let light = time.light();
+        let cascades = shadows::fit(
+            view,
+            FIELD_OF_VIEW_Y,
+            self.aspect_ratio,
+            NEAR_PLANE,
+            light.direction,
+            &self.shadow_settings,
         );
+        let mut scene_data = SceneData {
+            view_proj,
+            view,
+            light_direction: light.direction,
+            shadow_map: self.shadow_map.texture.index(),
+            light_color: light.color * scene::SUN_ILLUMINANCE,
+            shadow_sampler: self.shadow_map.sampler_handle.index(),
+            ambient: scene::ambient(time),
+            block_textures: self.block_textures.texture.index(),
+            camera: view.inverse().w_axis.truncate(),
+            block_sampler: self.block_textures.sampler_handle.index(),
+            previous_view_proj: self.culling.previous_view_proj().unwrap_or(view_proj),
+            ..bytemuck::Zeroable::zeroed()
+        };
+        scene_data.set_cascades(&cascades);
+        let scene =
+            self.scene_buffers
+                .write(self.frame_count as usize & 1, &scene_data, &self.allocator);
+
+        let image = next_img.0 as usize;
+        let mut graph = Graph::new(&mut self.transients);
+        #[cfg(feature = "tracy")]
+        if let Some(profiler) = &mut self.gpu_profiler {
+            graph.profile(profiler, frame);
+        }
 
-        self.skybox_data.draw(
+        let draw = graph.create_image(
+            targets::draw(self.render_extent),
+            &mut self.bindless,
+            &self.allocator,
             &self.device,
-            cmd_buf,
-            self.draw_image.view,
-            self.draw_extent,
-            skybox::PushConstants::new(sky_color),
         );
-
-        unsafe { self.device.cmd_end_rendering(cmd_buf) };
-
-        transition_image(
-            cmd_buf,
-            self.draw_image.image,
-            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        let depth = graph.create_image(
+            targets::depth(self.render_extent),
+            &mut self.bindless,
+            &self.allocator,
+            &self.device,
+        );
+        let ldr = graph.create_image(
+            targets::ldr(self.render_extent),
+            &mut self.bindless,
+            &self.allocator,
             &self.device,
         );
-        transition_image(
-            cmd_buf,
-            swapchain_images[next_img.0 as usize],
-            vk::ImageLayout::UNDEFINED,
-            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        let upscaled = graph.create_image(
+            targets::upscaled(self.swapchain_data.extent),
+            &mut self.bindless,
+            &self.allocator,
             &self.device,
         );
+        // Nothing drawn to the swapchain image before survives presenting it.
+        let swapchain = graph.import_image(ImportedImage {
+            previous: Access::Present,
+            discard: true,
+            ..ImportedImage::new(
+                swapchain_images[image],
+                self.swapchain_data.image_views[image],
+                self.swapchain_data.extent,
+                vk::ImageAspectFlags::COLOR,
+            )
+        });
+        graph.export_image(swapchain, Access::Present);
+        let shadow_map = self.shadow_map.import(&mut graph);
+        let culled = self.culling.import(&mut graph, frame);
 
-        copy_image_to_image(
-            cmd_buf,
-            self.draw_image.image,
-            swapchain_images[next_img.0 as usize],
-            self.draw_extent,
-            self.swapchain_data.extent,
+        let pyramid_sets = self.culling_data.pyramid_sets(
             &self.device,
+            &mut self.frame_data[frame].descriptors,
+            &self.culling,
         );
 
-        transition_image(
-            cmd_buf,
-            swapchain_images[next_img.0 as usize],
-            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-            vk::ImageLayout::PRESENT_SRC_KHR,
-            &self.device,
+        let bindless = &self.bindless;
+        let chunks = &self.chunks;
+        let culling = &self.culling;
+        let draw_extent = self.draw_extent;
+        let clear_depth = Load::Clear(vk::ClearValue {
+            depth_stencil: vk::ClearDepthStencilValue {
+                depth: 1.,
+                stencil: 0,
+            },
+        });
+
+        graph
+            .add_pass("clear counters")
+            .buffer(culled.counters, Access::TransferWrite)
+            .record(move |device, cmd| culling.clear_counters(device, cmd, frame));
+
+        let culling_data = &self.culling_data;
+        graph
+            .add_pass("culling")
+            .buffer(culled.commands, Access::ComputeStorageWrite)
+            .buffer(culled.counters, Access::ComputeStorageWrite)
+            .image(culled.pyramid, Access::ComputeSampled)
+            .record(move |device, cmd| {
+                culling_data.cull(device, cmd, bindless, culling, frame, scene);
+            });
+
+        graph
+            .add_pass("read back culling")
+            .buffer(culled.counters, Access::TransferRead)
+            .buffer(culled.readback, Access::TransferWrite)
+            .record(move |device, cmd| culling.read_back(device, cmd, frame));
+
+        let shadow_data = &self.shadow_data;
+        for (cascade, &layer) in shadow_map.iter().enumerate() {
+            let draws = culling.cascade_draws(frame, cascade);
+            graph
+                .add_pass("shadows")
+                .depth_attachment(layer, clear_depth)
+                .buffer(culled.commands, Access::IndirectRead)
+                .buffer(culled.counters, Access::IndirectRead)
+                .record(move |device, cmd| {
+                    shadow_data.draw(device, cmd, bindless, chunks, &draws, scene, cascade);
+                });
+        }
+
+        let skybox_data = &self.skybox_data;
+        let sky = skybox::PushConstants::new(
+            view_proj,
+            time.sun_direction(),
+            time.moon_direction(),
+            self.skybox_data.transmittance_texture,
+            self.linear_sampler_handle,
+            &self.sky,
+            self.start_time.elapsed().as_secs_f32(),
         );
+        graph
+            .add_pass("skybox")
+            .color_attachment(draw, Load::DontCare)
+            .render_area(draw_extent)
+            .record(move |device, cmd| skybox_data.draw(device, cmd, bindless, sky));
+
+        let chunk_data = &self.chunk_data;
+        let draws = culling.camera_draws(frame);
+        let mut pass = graph
+            .add_pass("chunks")
+            .color_attachment(draw, Load::Keep)
+            .depth_attachment(depth, clear_depth)
+            .render_area(draw_extent)
+            .buffer(culled.commands, Access::IndirectRead)
+            .buffer(culled.counters, Access::IndirectRead);
+        for layer in shadow_map {
+            pass = pass.image(layer, Access::FragmentSampled);
+        }
+        pass.record(move |device, cmd| {
+            chunk_data.draw(device, cmd, bindless, chunks, &draws, scene.index());
+        });
+
+        let depth_texture = graph.sampled(depth);
+        graph
+            .add_pass("depth pyramid")
+            .image(depth, Access::ComputeSampled)
+            .image(culled.pyramid, Access::ComputeStorageWrite)
+            .record(move |device, cmd| {
+                culling_data.build_pyramid(
+                    device,
+                    cmd,
+                    bindless,
+                    culling,
+                    &pyramid_sets,
+                    depth_texture,
+                    draw_extent,
+                );
+            });
+
+        let tonemap_data = &self.tonemap_data;
+        let tonemap = tonemap::PushConstants::new(self.tonemap, graph.sampled(draw));
+        graph
+            .add_pass("tonemap")
+            .color_attachment(ldr, Load::DontCare)
+            .render_area(draw_extent)
+            .image(draw, Access::FragmentSampled)
+            .record(move |device, cmd| tonemap_data.draw(device, cmd, bindless, tonemap));
+
+        let upscale_data = &self.upscale_data;
+        let easu = upscale::EasuPushConstants::new(
+            graph.sampled(ldr),
+            self.linear_sampler_handle,
+            draw_extent,
+            self.swapchain_data.extent,
+            self.render_extent,
+        );
+        graph
+            .add_pass("easu")
+            .color_attachment(upscaled, Load::DontCare)
+            .image(ldr, Access::FragmentSampled)
+            .record(move |device, cmd| upscale_data.draw_easu(device, cmd, bindless, easu));
+
+        let rcas = upscale::RcasPushConstants::new(graph.sampled(upscaled), self.upscale);
+        graph
+            .add_pass("rcas")
+            .color_attachment(swapchain, Load::DontCare)
+            .image(upscaled, Access::FragmentSampled)
+            .record(move |device, cmd| upscale_data.draw_rcas(device, cmd, bindless, rcas));
+
+        graph.execute(&self.device, cmd_buf);
+        self.culling.recorded(frame, view_proj);
 
         unsafe { self.device.end_command_buffer(cmd_buf) }.unwrap();
 
         let current_render_semaphore = self.swapchain_data.render_semaphores[next_img.0 as usize];
+        let value = self.timeline.next_frame_value(self.frame_count);
 
         unsafe {
+            zone!("submit");
             self.device.queue_submit2(
                 self.queue,
                 &[vk::SubmitInfo2::builder()
@@ -431,15 +1071,21 @@ impl Renderer {
                     .command_buffer_infos(&[vk::CommandBufferSubmitInfo::builder()
                         .command_buffer(cmd_buf)
                         .device_mask(0)])
-                    .signal_semaphore_infos(&[vk::SemaphoreSubmitInfo::builder()
-                        .semaphore(current_render_semaphore)
-                        .stage_mask(vk::PipelineStageFlags2::ALL_GRAPHICS)])],
-                self.get_current_framedata().render_fence,
+                    .signal_semaphore_infos(&[
+                        vk::SemaphoreSubmitInfo::builder()
+                            .semaphore(current_render_semaphore)
+                            .stage_mask(vk::PipelineStageFlags2::ALL_GRAPHICS)
+                            .build(),
+                        self.timeline
+                            .signal_info(value, vk::PipelineStageFlags2::ALL_COMMANDS),
+                    ])],
+                vk::Fence::null(),
             )
         }
         .unwrap();
 
         unsafe {
+            zone!("present");
             self.device.queue_present_khr(
                 self.queue,
                 &vk::PresentInfoKHR::builder()
@@ -460,23 +1106,47 @@ impl Drop for Renderer {
         unsafe {
             self.device.device_wait_idle().unwrap();
 
-            self.descriptor_allocator.flush(&self.device);
-
-            self.draw_image.flush(&self.device, &self.allocator);
+            self.block_textures
+                .destroy(&mut self.bindless, &self.allocator, &self.device);
+            self.transients
+                .destroy(&mut self.bindless, &self.allocator, &self.device);
+            self.skybox_data
+                .destroy(&mut self.bindless, &self.allocator, &self.device);
+            self.chunks.destroy(&self.allocator);
+            self.culling
+                .destroy(&mut self.bindless, &self.allocator, &self.device);
+            self.scene_buffers
+                .destroy(&mut self.bindless, &self.allocator);
+            self.shadow_map
+                .destroy(&mut self.bindless, &self.allocator, &self.device);
+            self.bindless.destroy(&self.device);
 
             self.swapchain_data.flush(&self.device, &self.instance);
 
-            self.skybox_data.destroy(&self.device);
+            self.chunk_data.destroy(&self.device);
+            self.culling_data.destroy(&self.device);
+            self.shadow_data.destroy(&self.device);
+            self.tonemap_data.destroy(&self.device);
+            self.upscale_data.destroy(&self.device);
+            self.device.destroy_sampler(self.linear_sampler, None);
+
+            self.pipeline_cache.save(&self.device);
+            self.pipeline_cache.destroy(&self.device);
+
+            #[cfg(feature = "tracy")]
+            if let Some(profiler) = &mut self.gpu_profiler {
+                profiler.destroy(&self.device);
+            }
 
             #[cfg(feature = "logging")]
             self.instance
                 .destroy_debug_utils_messenger_ext(self.debug_messenger, None);
 
+            self.timeline.destroy(&self.device);
             for i in 0..self.frame_data.len() {
-                self.device
-                    .destroy_fence(self.frame_data[i].render_fence, None);
                 self.device
                     .destroy_semaphore(self.frame_data[i].swapchain_semaphore, None);
+                self.frame_data[i].descriptors.flush(&self.device);
             }
 
             self.device
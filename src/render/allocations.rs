@@ -1,12 +1,17 @@
-use vulkanalia::vk::{self, DeviceV1_0, HasBuilder};
+use vulkanalia::vk::{self, DeviceV1_0, DeviceV1_3, HasBuilder};
 use vulkanalia_vma::Alloc;
 
+use super::timeline::Timeline;
+use super::utils::immediate_submit;
+
 #[derive(Debug)]
 pub struct AllocatedImage {
     pub image: vk::Image,
     pub view: vk::ImageView,
     pub allocation: vulkanalia_vma::Allocation,
     pub extent: vk::Extent3D,
+    pub mip_levels: u32,
+    pub layers: u32,
 }
 
 impl AllocatedImage {
@@ -18,12 +23,53 @@ impl AllocatedImage {
         allocator: &vulkanalia_vma::Allocator,
         device: &vulkanalia::Device,
     ) -> Self {
+        Self::with_levels(
+            format,
+            usage_flags,
+            extent,
+            aspect_flags,
+            1,
+            1,
+            vk::ImageViewType::_2D,
+            allocator,
+            device,
+        )
+    }
+
+    /// Like [`AllocatedImage::new`] with several mip levels and array layers.
+    /// Cube views need a multiple of six layers.
+    #[allow(clippy::too_many_arguments)]
+    pub fn with_levels(
+        format: vk::Format,
+        usage_flags: vk::ImageUsageFlags,
+        extent: vk::Extent3D,
+        aspect_flags: vk::ImageAspectFlags,
+        mip_levels: u32,
+        layers: u32,
+        view_type: vk::ImageViewType,
+        allocator: &vulkanalia_vma::Allocator,
+        device: &vulkanalia::Device,
+    ) -> Self {
+        let cube = matches!(
+            view_type,
+            vk::ImageViewType::CUBE | vk::ImageViewType::CUBE_ARRAY
+        );
+        assert!(
+            !cube || layers.is_multiple_of(6),
+            "cube images need six layers per cube"
+        );
+
         let img_create_info = vk::ImageCreateInfo::builder()
+            .flags(if cube {
+                vk::ImageCreateFlags::CUBE_COMPATIBLE
+            } else {
+                vk::ImageCreateFlags::empty()
+            })
             .image_type(vk::ImageType::_2D)
             .format(format)
             .extent(extent)
-            .mip_levels(1)
-            .array_layers(1)
+            .mip_levels(mip_levels)
+            .array_layers(layers)
             .samples(vk::SampleCountFlags::_1)
             .tiling(vk::ImageTiling::OPTIMAL)
             .usage(usage_flags);
@@ -41,15 +87,15 @@ impl AllocatedImage {
         .unwrap();
 
         let img_view_create_info = vk::ImageViewCreateInfo::builder()
-            .view_type(vk::ImageViewType::_2D)
+            .view_type(view_type)
             .image(image)
             .format(format)
             .subresource_range(
                 vk::ImageSubresourceRange::builder()
                     .base_mip_level(0)
-                    .level_count(1)
+                    .level_count(mip_levels)
                     .base_array_layer(0)
-                    .layer_count(1)
+                    .layer_count(layers)
                     .aspect_mask(aspect_flags),
             );
 
@@ -60,7 +106,224 @@ impl AllocatedImage {
             view,
             allocation,
             extent,
+            mip_levels,
+            layers,
+        }
+    }
+
+    /// A colour image holding `pixels`, tightly packed RGBA8 texels for each
+    /// layer in turn. With `mipmapped` the full mip chain is generated from
+    /// the first level. The image ends up in `SHADER_READ_ONLY_OPTIMAL`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_pixels(
+        pixels: &[u8],
+        width: u32,
+        height: u32,
+        layers: u32,
+        view_type: vk::ImageViewType,
+        format: vk::Format,
+        mipmapped: bool,
+        allocator: &vulkanalia_vma::Allocator,
+        device: &vulkanalia::Device,
+        queue: vk::Queue,
+        timeline: &mut Timeline,
+    ) -> Self {
+        let size = width as usize * height as usize * 4 * layers as usize;
+        assert_eq!(
+            pixels.len(),
+            size,
+            "pixel data does not match the image size"
+        );
+
+        let mip_levels = if mipmapped {
+            mip_levels(width, height)
+        } else {
+            1
+        };
+
+        let image = Self::with_levels(
+            format,
+            vk::ImageUsageFlags::SAMPLED
+                | vk::ImageUsageFlags::TRANSFER_DST
+                | vk::ImageUsageFlags::TRANSFER_SRC,
+            vk::Extent3D {
+                width,
+                height,
+                depth: 1,
+            },
+            vk::ImageAspectFlags::COLOR,
+            mip_levels,
+            layers,
+            view_type,
+            allocator,
+            device,
+        );
+
+        let mut staging = AllocatedBuffer::new(
+            allocator,
+            size as u64,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vulkanalia_vma::MemoryUsage::AutoPreferHost,
+        );
+        unsafe {
+            let mem = allocator.map_memory(staging.allocation).unwrap();
+            std::slice::from_raw_parts_mut(mem, size).copy_from_slice(pixels);
+            allocator.unmap_memory(staging.allocation);
+        }
+
+        immediate_submit(device, queue, timeline, |cmd| {
+            image.barrier(
+                cmd,
+                0,
+                mip_levels,
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                device,
+            );
+
+            let region = vk::BufferImageCopy::builder()
+                .image_subresource(
+                    vk::ImageSubresourceLayers::builder()
+                        .aspect_mask(vk::ImageAspectFlags::COLOR)
+                        .mip_level(0)
+                        .base_array_layer(0)
+                        .layer_count(layers),
+                )
+                .image_extent(image.extent);
+            unsafe {
+                device.cmd_copy_buffer_to_image(
+                    cmd,
+                    staging.buf,
+                    image.image,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &[region],
+                )
+            };
+
+            image.generate_mips(cmd, device);
+        });
+
+        staging.flush(allocator);
+
+        image
+    }
+
+    /// Downsample each level into the next with linear blits, leaving every
+    /// level in `SHADER_READ_ONLY_OPTIMAL`. Expects all levels in
+    /// `TRANSFER_DST_OPTIMAL` with level 0 filled in.
+    fn generate_mips(&self, cmd: vk::CommandBuffer, device: &vulkanalia::Device) {
+        let mut width = self.extent.width as i32;
+        let mut height = self.extent.height as i32;
+
+        for level in 1..self.mip_levels {
+            self.barrier(
+                cmd,
+                level - 1,
+                1,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                device,
+            );
+
+            let next_width = (width / 2).max(1);
+            let next_height = (height / 2).max(1);
+
+            let subresource = |mip_level| {
+                vk::ImageSubresourceLayers::builder()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .mip_level(mip_level)
+                    .base_array_layer(0)
+                    .layer_count(self.layers)
+                    .build()
+            };
+            let mut blit_region = vk::ImageBlit2::builder()
+                .src_subresource(subresource(level - 1))
+                .dst_subresource(subresource(level));
+            blit_region.src_offsets[1] = vk::Offset3D {
+                x: width,
+                y: height,
+                z: 1,
+            };
+            blit_region.dst_offsets[1] = vk::Offset3D {
+                x: next_width,
+                y: next_height,
+                z: 1,
+            };
+
+            unsafe {
+                device.cmd_blit_image2(
+                    cmd,
+                    &vk::BlitImageInfo2::builder()
+                        .src_image(self.image)
+                        .src_image_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+                        .dst_image(self.image)
+                        .dst_image_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                        .filter(vk::Filter::LINEAR)
+                        .regions(&[blit_region]),
+                )
+            };
+
+            self.barrier(
+                cmd,
+                level - 1,
+                1,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                device,
+            );
+
+            width = next_width;
+            height = next_height;
         }
+
+        self.barrier(
+            cmd,
+            self.mip_levels - 1,
+            1,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            device,
+        );
+    }
+
+    /// Transition `level_count` mip levels starting at `base_level`, across
+    /// every layer.
+    fn barrier(
+        &self,
+        cmd: vk::CommandBuffer,
+        base_level: u32,
+        level_count: u32,
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+        device: &vulkanalia::Device,
+    ) {
+        let barrier = vk::ImageMemoryBarrier2::builder()
+            .src_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+            .src_access_mask(vk::AccessFlags2::MEMORY_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+            .dst_access_mask(vk::AccessFlags2::MEMORY_WRITE | vk::AccessFlags2::MEMORY_READ)
+            .old_layout(old_layout)
+            .new_layout(new_layout)
+            .image(self.image)
+            .subresource_range(
+                vk::ImageSubresourceRange::builder()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .base_mip_level(base_level)
+                    .level_count(level_count)
+                    .base_array_layer(0)
+                    .layer_count(self.layers),
+            );
+        unsafe {
+            device.cmd_pipeline_barrier2(
+                cmd,
+                &vk::DependencyInfo::builder().image_memory_barriers(&[barrier]),
+            )
+        };
+    }
+
+    /// Bytes of device memory backing the image.
+    pub fn size(&self, allocator: &vulkanalia_vma::Allocator) -> u64 {
+        allocator.get_allocation_info(self.allocation).size
     }
 
     pub fn flush(&mut self, device: &vulkanalia::Device, alloc: &vulkanalia_vma::Allocator) {
@@ -71,60 +334,149 @@ impl AllocatedImage {
     }
 }
 
+/// Levels in a full mip chain down to 1x1.
+pub const fn mip_levels(width: u32, height: u32) -> u32 {
+    let largest = if width > height { width } else { height };
+    u32::BITS - (largest | 1).leading_zeros()
+}
+
+/// A sampler covering every mip level of an image. Nearest `mag_filter` keeps
+/// block textures crisp up close while `min_filter` controls mip blending.
+pub fn create_sampler(
+    device: &vulkanalia::Device,
+    mag_filter: vk::Filter,
+    min_filter: vk::Filter,
+    address_mode: vk::SamplerAddressMode,
+    mip_levels: u32,
+) -> vk::Sampler {
+    let mipmap_mode = if min_filter == vk::Filter::LINEAR {
+        vk::SamplerMipmapMode::LINEAR
+    } else {
+        vk::SamplerMipmapMode::NEAREST
+    };
+
+    let info = vk::SamplerCreateInfo::builder()
+        .mag_filter(mag_filter)
+        .min_filter(min_filter)
+        .mipmap_mode(mipmap_mode)
+        .address_mode_u(address_mode)
+        .address_mode_v(address_mode)
+        .address_mode_w(address_mode)
+        .min_lod(0.)
+        .max_lod(mip_levels as f32);
+
+    unsafe { device.create_sampler(&info, None) }.unwrap()
+}
+
+/// Pools never grow past this many sets; later pools are created at this size.
+const MAX_SETS_PER_POOL: u32 = 4092;
+
+/// Allocates descriptor sets from a list of pools, creating a larger pool
+/// whenever the current one runs out. `ratios` give descriptors of each type
+/// per set.
+#[derive(Debug)]
 pub struct DescriptorAllocator {
-    pool: vk::DescriptorPool,
+    ratios: Vec<(vk::DescriptorType, f32)>,
+    full_pools: Vec<vk::DescriptorPool>,
+    ready_pools: Vec<vk::DescriptorPool>,
+    sets_per_pool: u32,
 }
 
 impl DescriptorAllocator {
     pub fn new(
         device: &vulkanalia::Device,
-        max_sets: u32,
-        pool_ratios: &[(vk::DescriptorType, u32)],
+        initial_sets: u32,
+        ratios: &[(vk::DescriptorType, f32)],
     ) -> Self {
-        let pool_sizes: Vec<vk::DescriptorPoolSizeBuilder> = pool_ratios
-            .iter()
-            .map(|ratio| {
-                vk::DescriptorPoolSize::builder()
-                    .descriptor_count(ratio.1 * max_sets)
-                    .type_(ratio.0)
-            })
-            .collect();
-
-        let pool_info = vk::DescriptorPoolCreateInfo::builder()
-            .max_sets(max_sets)
-            .pool_sizes(&pool_sizes);
-
+        let pool = create_pool(device, initial_sets, ratios);
         Self {
-            pool: unsafe { device.create_descriptor_pool(&pool_info, None) }.unwrap(),
+            ratios: ratios.to_vec(),
+            full_pools: vec![],
+            ready_pools: vec![pool],
+            sets_per_pool: grow(initial_sets),
         }
     }
 
+    /// Free every set allocated so far. None of them may still be in use.
     pub fn clear_descriptors(&mut self, device: &vulkanalia::Device) {
-        unsafe { device.reset_descriptor_pool(self.pool, vk::DescriptorPoolResetFlags::empty()) }
-            .unwrap();
+        self.ready_pools.append(&mut self.full_pools);
+        for pool in &self.ready_pools {
+            unsafe { device.reset_descriptor_pool(*pool, vk::DescriptorPoolResetFlags::empty()) }
+                .unwrap();
+        }
     }
 
     pub fn flush(&mut self, device: &vulkanalia::Device) {
-        unsafe {
-            device.destroy_descriptor_pool(self.pool, None);
-        };
+        for pool in self.ready_pools.drain(..).chain(self.full_pools.drain(..)) {
+            unsafe { device.destroy_descriptor_pool(pool, None) };
+        }
     }
 
     pub fn allocate(
-        &self,
+        &mut self,
         device: &vulkanalia::Device,
         layout: vk::DescriptorSetLayout,
     ) -> vk::DescriptorSet {
         let layout = [layout];
-        unsafe {
+        let try_allocate = |pool| unsafe {
             device.allocate_descriptor_sets(
                 &vk::DescriptorSetAllocateInfo::builder()
-                    .descriptor_pool(self.pool)
+                    .descriptor_pool(pool)
                     .set_layouts(&layout),
             )
-        }
-        .unwrap()[0]
+        };
+
+        let pool = self.get_pool(device);
+        let sets = match try_allocate(pool) {
+            Err(vk::ErrorCode::OUT_OF_POOL_MEMORY | vk::ErrorCode::FRAGMENTED_POOL) => {
+                self.full_pools.push(pool);
+                let pool = self.get_pool(device);
+                let sets = try_allocate(pool);
+                self.ready_pools.push(pool);
+                sets
+            }
+            sets => {
+                self.ready_pools.push(pool);
+                sets
+            }
+        };
+        sets.unwrap()[0]
     }
+
+    /// Take a pool with space left, creating one if there is none. The caller
+    /// puts it back on `ready_pools` or `full_pools`.
+    fn get_pool(&mut self, device: &vulkanalia::Device) -> vk::DescriptorPool {
+        self.ready_pools.pop().unwrap_or_else(|| {
+            let pool = create_pool(device, self.sets_per_pool, &self.ratios);
+            self.sets_per_pool = grow(self.sets_per_pool);
+            pool
+        })
+    }
+}
+
+fn grow(sets: u32) -> u32 {
+    (sets + sets / 2 + 1).min(MAX_SETS_PER_POOL)
+}
+
+fn create_pool(
+    device: &vulkanalia::Device,
+    max_sets: u32,
+    ratios: &[(vk::DescriptorType, f32)],
+) -> vk::DescriptorPool {
+    let pool_sizes: Vec<vk::DescriptorPoolSizeBuilder> = ratios
+        .iter()
+        .map(|ratio| {
+            vk::DescriptorPoolSize::builder()
+                .descriptor_count(((ratio.1 * max_sets as f32) as u32).max(1))
+                .type_(ratio.0)
+        })
+        .collect();
+
+    let pool_info = vk::DescriptorPoolCreateInfo::builder()
+        .max_sets(max_sets)
+        .pool_sizes(&pool_sizes);
+
+    unsafe { device.create_descriptor_pool(&pool_info, None) }.unwrap()
 }
 
 pub struct AllocatedBuffer {
@@ -138,12 +490,29 @@ impl AllocatedBuffer {
         size: u64,
         usage: vk::BufferUsageFlags,
         memory_usage: vulkanalia_vma::MemoryUsage,
+    ) -> Self {
+        Self::with_host_access(
+            allocator,
+            size,
+            usage,
+            memory_usage,
+            vulkanalia_vma::AllocationCreateFlags::HOST_ACCESS_SEQUENTIAL_WRITE,
+        )
+    }
+
+    /// A buffer the host accesses as `host_access` describes, such as
+    /// `HOST_ACCESS_RANDOM` for one the GPU writes and the host reads back.
+    pub fn with_host_access(
+        allocator: &vulkanalia_vma::Allocator,
+        size: u64,
+        usage: vk::BufferUsageFlags,
+        memory_usage: vulkanalia_vma::MemoryUsage,
+        host_access: vulkanalia_vma::AllocationCreateFlags,
     ) -> Self {
         let info = vk::BufferCreateInfo::builder().size(size).usage(usage);
         let alloc_create_info = vulkanalia_vma::AllocationOptions {
             usage: memory_usage,
-            flags: vulkanalia_vma::AllocationCreateFlags::MAPPED
-                | vulkanalia_vma::AllocationCreateFlags::HOST_ACCESS_SEQUENTIAL_WRITE,
+            flags: vulkanalia_vma::AllocationCreateFlags::MAPPED | host_access,
             ..Default::default()
         };
 
@@ -153,6 +522,11 @@ impl AllocatedBuffer {
         Self { buf, allocation }
     }
 
+    /// Bytes of memory backing the buffer.
+    pub fn size(&self, allocator: &vulkanalia_vma::Allocator) -> u64 {
+        allocator.get_allocation_info(self.allocation).size
+    }
+
     pub fn flush(&mut self, allocator: &vulkanalia_vma::Allocator) {
         unsafe { allocator.destroy_buffer(self.buf, self.allocation) };
     }
@@ -0,0 +1,393 @@
+//! One global descriptor set holding every texture, sampler and storage buffer
+//! the renderer knows about.
+//!
+//! Shaders declare the set as
+//!
+//! ```slang
+//! [[vk::binding(0, 0)]] Texture2D textures[];
+//! [[vk::binding(2, 0)]] SamplerState samplers[];
+//! [[vk::binding(3, 0)]] ByteAddressBuffer buffers[];
+//! ```
+//!
+//! and index it with the handles returned here, passed in push constants.
+//! Binding 1 is left free for storage images.
+
+use std::marker::PhantomData;
+
+use rootcause::Report;
+use vulkanalia::vk::{self, DeviceV1_0, HasBuilder, InstanceV1_1};
+
+pub const SAMPLED_IMAGE_BINDING: u32 = 0;
+pub const SAMPLER_BINDING: u32 = 2;
+pub const STORAGE_BUFFER_BINDING: u32 = 3;
+
+const MAX_SAMPLED_IMAGES: u32 = 16384;
+const MAX_SAMPLERS: u32 = 64;
+const MAX_STORAGE_BUFFERS: u32 = 16384;
+
+/// Index of a descriptor in one of the bindless arrays. Stays valid until it is
+/// passed back to the matching `remove_*` method.
+#[derive(Debug)]
+pub struct Handle<T> {
+    index: u32,
+    _kind: PhantomData<T>,
+}
+
+impl<T> Handle<T> {
+    const fn new(index: u32) -> Self {
+        Self {
+            index,
+            _kind: PhantomData,
+        }
+    }
+
+    /// The array index to hand to shaders.
+    pub const fn index(self) -> u32 {
+        self.index
+    }
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+#[derive(Debug)]
+pub enum SampledImage {}
+#[derive(Debug)]
+pub enum Sampler {}
+#[derive(Debug)]
+pub enum StorageBuffer {}
+
+/// Slots of one binding. Freed slots wait for the GPU to finish the frame
+/// they were freed in before they are handed out again, so a descriptor is
+/// never rewritten while a submitted command buffer can still read it.
+struct Slots {
+    capacity: u32,
+    next: u32,
+    free: Vec<u32>,
+    retired: Vec<(u32, u64)>,
+}
+
+impl Slots {
+    const fn new(capacity: u32) -> Self {
+        Self {
+            capacity,
+            next: 0,
+            free: vec![],
+            retired: vec![],
+        }
+    }
+
+    fn allocate(&mut self, kind: &str) -> u32 {
+        if let Some(slot) = self.free.pop() {
+            return slot;
+        }
+        assert!(
+            self.next < self.capacity,
+            "out of bindless {kind} slots ({})",
+            self.capacity
+        );
+        self.next += 1;
+        self.next - 1
+    }
+
+    fn retire(&mut self, slot: u32, frame: u64) {
+        self.retired.push((slot, frame));
+    }
+
+    fn recycle(&mut self, finished: u64) {
+        let free = &mut self.free;
+        self.retired.retain(|&(slot, retired)| {
+            let done = retired < finished;
+            if done {
+                free.push(slot);
+            }
+            !done
+        });
+    }
+}
+
+pub struct Bindless {
+    pub layout: vk::DescriptorSetLayout,
+    pub set: vk::DescriptorSet,
+    pool: vk::DescriptorPool,
+
+    sampled_images: Slots,
+    samplers: Slots,
+    storage_buffers: Slots,
+
+    /// Frame that frees happen in, for deferring slot reuse.
+    frame: u64,
+}
+
+impl Bindless {
+    /// The device must have been created with [`Bindless::features`].
+    pub fn new(
+        instance: &vulkanalia::Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &vulkanalia::Device,
+    ) -> Result<Self, Report> {
+        let mut limits = vk::PhysicalDeviceVulkan12Properties::builder();
+        let mut props = vk::PhysicalDeviceProperties2::builder().push_next(&mut limits);
+        unsafe { instance.get_physical_device_properties2(physical_device, &mut props) };
+        let color_attachments = props.properties.limits.max_color_attachments;
+
+        let bindings = [
+            (
+                SAMPLED_IMAGE_BINDING,
+                vk::DescriptorType::SAMPLED_IMAGE,
+                MAX_SAMPLED_IMAGES
+                    .min(limits.max_per_stage_descriptor_update_after_bind_sampled_images)
+                    .min(limits.max_descriptor_set_update_after_bind_sampled_images),
+            ),
+            (
+                SAMPLER_BINDING,
+                vk::DescriptorType::SAMPLER,
+                MAX_SAMPLERS
+                    .min(limits.max_per_stage_descriptor_update_after_bind_samplers)
+                    .min(limits.max_descriptor_set_update_after_bind_samplers),
+            ),
+            (
+                STORAGE_BUFFER_BINDING,
+                vk::DescriptorType::STORAGE_BUFFER,
+                MAX_STORAGE_BUFFERS
+                    .min(limits.max_per_stage_descriptor_update_after_bind_storage_buffers)
+                    .min(limits.max_descriptor_set_update_after_bind_storage_buffers),
+            ),
+        ];
+        // Every binding is visible to every stage, so between them they also
+        // have to fit the per stage total, which counts colour attachments too.
+        let total: u32 = bindings.iter().map(|&(_, _, count)| count).sum();
+        let available = limits
+            .max_per_stage_update_after_bind_resources
+            .saturating_sub(color_attachments);
+        if total > available {
+            return Err(rootcause::report!(
+                "the device allows {available} bindless descriptors per stage, {total} are needed"
+            ));
+        }
+
+        let layout_bindings: Vec<_> = bindings
+            .iter()
+            .map(|&(binding, descriptor_type, count)| {
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(binding)
+                    .descriptor_type(descriptor_type)
+                    .descriptor_count(count)
+                    .stage_flags(vk::ShaderStageFlags::ALL)
+            })
+            .collect();
+        let binding_flags = [vk::DescriptorBindingFlags::PARTIALLY_BOUND
+            | vk::DescriptorBindingFlags::UPDATE_AFTER_BIND; 3];
+
+        let layout = unsafe {
+            device.create_descriptor_set_layout(
+                &vk::DescriptorSetLayoutCreateInfo::builder()
+                    .flags(vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL)
+                    .bindings(&layout_bindings)
+                    .push_next(
+                        &mut vk::DescriptorSetLayoutBindingFlagsCreateInfo::builder()
+                            .binding_flags(&binding_flags),
+                    ),
+                None,
+            )
+        }
+        .unwrap();
+
+        let pool_sizes: Vec<_> = bindings
+            .iter()
+            .map(|&(_, descriptor_type, count)| {
+                vk::DescriptorPoolSize::builder()
+                    .type_(descriptor_type)
+                    .descriptor_count(count)
+            })
+            .collect();
+
+        let pool = unsafe {
+            device.create_descriptor_pool(
+                &vk::DescriptorPoolCreateInfo::builder()
+                    .flags(vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND)
+                    .max_sets(1)
+                    .pool_sizes(&pool_sizes),
+                None,
+            )
+        }
+        .unwrap();
+
+        let set = unsafe {
+            device.allocate_descriptor_sets(
+                &vk::DescriptorSetAllocateInfo::builder()
+                    .descriptor_pool(pool)
+                    .set_layouts(&[layout]),
+            )
+        }
+        .unwrap()[0];
+
+        Ok(Self {
+            layout,
+            set,
+            pool,
+            sampled_images: Slots::new(bindings[0].2),
+            samplers: Slots::new(bindings[1].2),
+            storage_buffers: Slots::new(bindings[2].2),
+            frame: 0,
+        })
+    }
+
+    /// Device features the bindless set relies on.
+    pub fn features() -> vk::PhysicalDeviceVulkan12FeaturesBuilder {
+        vk::PhysicalDeviceVulkan12Features::builder()
+            .descriptor_indexing(true)
+            .runtime_descriptor_array(true)
+            .descriptor_binding_partially_bound(true)
+            .descriptor_binding_sampled_image_update_after_bind(true)
+            .descriptor_binding_storage_buffer_update_after_bind(true)
+            .shader_sampled_image_array_non_uniform_indexing(true)
+            .shader_storage_image_array_non_uniform_indexing(true)
+            .shader_storage_buffer_array_non_uniform_indexing(true)
+    }
+
+    /// Start frame `frame`, making slots freed in the frames before
+    /// `finished`, which the GPU has finished, reusable.
+    pub fn begin_frame(&mut self, frame: u64, finished: u64) {
+        self.frame = frame;
+        for slots in [
+            &mut self.sampled_images,
+            &mut self.samplers,
+            &mut self.storage_buffers,
+        ] {
+            slots.recycle(finished);
+        }
+    }
+
+    pub fn bind(
+        &self,
+        device: &vulkanalia::Device,
+        cmd: vk::CommandBuffer,
+        bind_point: vk::PipelineBindPoint,
+        pipeline_layout: vk::PipelineLayout,
+    ) {
+        unsafe {
+            device.cmd_bind_descriptor_sets(cmd, bind_point, pipeline_layout, 0, &[self.set], &[])
+        };
+    }
+
+    pub fn add_sampled_image(
+        &mut self,
+        device: &vulkanalia::Device,
+        view: vk::ImageView,
+        layout: vk::ImageLayout,
+    ) -> Handle<SampledImage> {
+        let handle = Handle::new(self.sampled_images.allocate("sampled image"));
+        self.write_sampled_image(device, handle, view, layout);
+        handle
+    }
+
+    /// Point an existing handle at a new view, e.g. after a resize. The old
+    /// view must no longer be in use by the GPU.
+    pub fn write_sampled_image(
+        &self,
+        device: &vulkanalia::Device,
+        handle: Handle<SampledImage>,
+        view: vk::ImageView,
+        layout: vk::ImageLayout,
+    ) {
+        let info = [vk::DescriptorImageInfo::builder()
+            .image_view(view)
+            .image_layout(layout)];
+        self.write_images(
+            device,
+            SAMPLED_IMAGE_BINDING,
+            handle.index,
+            vk::DescriptorType::SAMPLED_IMAGE,
+            &info,
+        );
+    }
+
+    pub fn remove_sampled_image(&mut self, handle: Handle<SampledImage>) {
+        self.sampled_images.retire(handle.index, self.frame);
+    }
+
+    pub fn add_sampler(
+        &mut self,
+        device: &vulkanalia::Device,
+        sampler: vk::Sampler,
+    ) -> Handle<Sampler> {
+        let handle = Handle::new(self.samplers.allocate("sampler"));
+        let info = [vk::DescriptorImageInfo::builder().sampler(sampler)];
+        self.write_images(
+            device,
+            SAMPLER_BINDING,
+            handle.index,
+            vk::DescriptorType::SAMPLER,
+            &info,
+        );
+        handle
+    }
+
+    pub fn remove_sampler(&mut self, handle: Handle<Sampler>) {
+        self.samplers.retire(handle.index, self.frame);
+    }
+
+    pub fn add_storage_buffer(
+        &mut self,
+        device: &vulkanalia::Device,
+        buffer: vk::Buffer,
+        offset: u64,
+        range: u64,
+    ) -> Handle<StorageBuffer> {
+        let handle = Handle::new(self.storage_buffers.allocate("storage buffer"));
+        let info = [vk::DescriptorBufferInfo::builder()
+            .buffer(buffer)
+            .offset(offset)
+            .range(range)];
+        let write = vk::WriteDescriptorSet::builder()
+            .dst_set(self.set)
+            .dst_binding(STORAGE_BUFFER_BINDING)
+            .dst_array_element(handle.index)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .buffer_info(&info);
+        unsafe { device.update_descriptor_sets(&[write], &[] as &[vk::CopyDescriptorSet]) };
+        handle
+    }
+
+    pub fn remove_storage_buffer(&mut self, handle: Handle<StorageBuffer>) {
+        self.storage_buffers.retire(handle.index, self.frame);
+    }
+
+    fn write_images(
+        &self,
+        device: &vulkanalia::Device,
+        binding: u32,
+        index: u32,
+        descriptor_type: vk::DescriptorType,
+        info: &[vk::DescriptorImageInfoBuilder],
+    ) {
+        let write = vk::WriteDescriptorSet::builder()
+            .dst_set(self.set)
+            .dst_binding(binding)
+            .dst_array_element(index)
+            .descriptor_type(descriptor_type)
+            .image_info(info);
+        unsafe { device.update_descriptor_sets(&[write], &[] as &[vk::CopyDescriptorSet]) };
+    }
+
+    pub fn destroy(&self, device: &vulkanalia::Device) {
+        unsafe {
+            device.destroy_descriptor_pool(self.pool, None);
+            device.destroy_descriptor_set_layout(self.layout, None);
+        }
+    }
+}
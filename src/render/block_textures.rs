@@ -0,0 +1,182 @@
+//! Block textures packed into one mipmapped 2D texture array.
+//!
+//! Textures are read from `assets/textures/blocks/<name>.png` at startup.
+//! A face looks for `<block>_<face>`, then `<block>_side` for the four side
+//! faces, then `<block>`, and finally uses the checkerboard in layer 0.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use vulkanalia::vk::{self, DeviceV1_0};
+
+use super::allocations::{AllocatedImage, create_sampler};
+use super::bindless::{self, Bindless};
+use super::timeline::Timeline;
+
+mod pack;
+
+pub use pack::MISSING_LAYER;
+
+pub const TEXTURE_DIR: &str = "./assets/textures/blocks";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Face {
+    Top,
+    Bottom,
+    North,
+    South,
+    East,
+    West,
+}
+
+impl Face {
+    pub const ALL: [Self; 6] = [
+        Self::Top,
+        Self::Bottom,
+        Self::North,
+        Self::South,
+        Self::East,
+        Self::West,
+    ];
+
+    const fn suffix(self) -> &'static str {
+        match self {
+            Self::Top => "top",
+            Self::Bottom => "bottom",
+            Self::North => "north",
+            Self::South => "south",
+            Self::East => "east",
+            Self::West => "west",
+        }
+    }
+
+    const fn is_side(self) -> bool {
+        !matches!(self, Self::Top | Self::Bottom)
+    }
+}
+
+pub struct BlockTextures {
+    image: AllocatedImage,
+    sampler: vk::Sampler,
+    pub texture: bindless::Handle<bindless::SampledImage>,
+    pub sampler_handle: bindless::Handle<bindless::Sampler>,
+    layers: HashMap<String, u32>,
+}
+
+impl BlockTextures {
+    pub fn new(
+        dir: &Path,
+        bindless: &mut Bindless,
+        allocator: &vulkanalia_vma::Allocator,
+        device: &vulkanalia::Device,
+        queue: vk::Queue,
+        timeline: &mut Timeline,
+    ) -> Self {
+        let packed = pack::pack(dir);
+
+        let image = AllocatedImage::from_pixels(
+            &packed.pixels,
+            pack::TEXTURE_SIZE,
+            pack::TEXTURE_SIZE,
+            packed.layer_count(),
+            vk::ImageViewType::_2D_ARRAY,
+            vk::Format::R8G8B8A8_SRGB,
+            true,
+            allocator,
+            device,
+            queue,
+            timeline,
+        );
+        let sampler = create_sampler(
+            device,
+            vk::Filter::NEAREST,
+            vk::Filter::LINEAR,
+            vk::SamplerAddressMode::REPEAT,
+            image.mip_levels,
+        );
+
+        let texture = bindless.add_sampled_image(
+            device,
+            image.view,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        );
+        let sampler_handle = bindless.add_sampler(device, sampler);
+
+        Self {
+            image,
+            sampler,
+            texture,
+            sampler_handle,
+            layers: packed.layers,
+        }
+    }
+
+    /// The array layer for one face of `block`, for the mesher to store per quad.
+    pub fn layer(&self, block: &str, face: Face) -> u32 {
+        find_layer(&self.layers, block, face)
+    }
+
+    pub fn allocated_bytes(&self, allocator: &vulkanalia_vma::Allocator) -> u64 {
+        self.image.size(allocator)
+    }
+
+    pub fn destroy(
+        &mut self,
+        bindless: &mut Bindless,
+        allocator: &vulkanalia_vma::Allocator,
+        device: &vulkanalia::Device,
+    ) {
+        bindless.remove_sampled_image(self.texture);
+        bindless.remove_sampler(self.sampler_handle);
+        unsafe { device.destroy_sampler(self.sampler, None) };
+        self.image.flush(device, allocator);
+    }
+}
+
+/// The layer for one face of `block` among the packed `layers`, falling back
+/// as the module documentation describes.
+fn find_layer(layers: &HashMap<String, u32>, block: &str, face: Face) -> u32 {
+    let lookup = |name: &str| layers.get(name).copied();
+
+    lookup(&format!("{block}_{}", face.suffix()))
+        .or_else(|| lookup(&format!("{block}_side")).filter(|_| face.is_side()))
+        .or_else(|| lookup(block))
+        .unwrap_or(MISSING_LAYER)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layers(names: &[&str]) -> HashMap<String, u32> {
+        names
+            .iter()
+            .zip(1..)
+            .map(|(name, layer)| ((*name).to_owned(), layer))
+            .collect()
+    }
+
+    #[test]
+    fn faces_prefer_their_own_texture() {
+        let layers = layers(&["log", "log_side", "log_top"]);
+        assert_eq!(find_layer(&layers, "log", Face::Top), 3);
+        assert_eq!(find_layer(&layers, "log", Face::North), 2);
+        assert_eq!(find_layer(&layers, "log", Face::Bottom), 1);
+    }
+
+    #[test]
+    fn side_textures_only_cover_the_sides() {
+        let layers = layers(&["grass_side"]);
+        assert_eq!(find_layer(&layers, "grass", Face::East), 1);
+        assert_eq!(find_layer(&layers, "grass", Face::Top), MISSING_LAYER);
+    }
+
+    #[test]
+    fn missing_blocks_use_the_checkerboard() {
+        let layers = layers(&["stone"]);
+        for face in Face::ALL {
+            assert_eq!(find_layer(&layers, "dirt", face), MISSING_LAYER);
+            assert_eq!(find_layer(&layers, "stone", face), 1);
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+use std::io::BufReader;
+use std::path::Path;
+
+#[cfg(feature = "logging")]
+use piglog::prelude::*;
+#[cfg(feature = "logging")]
+use piglog::warning;
+
+/// Width and height of every block texture.
+pub const TEXTURE_SIZE: u32 = 16;
+/// Bytes in one RGBA8 layer.
+pub const LAYER_BYTES: usize = (TEXTURE_SIZE * TEXTURE_SIZE * 4) as usize;
+/// Layer 0 is always the checkerboard shown for missing textures.
+pub const MISSING_LAYER: u32 = 0;
+
+/// Every texture in a directory packed into consecutive RGBA8 layers.
+pub struct Packed {
+    pub pixels: Vec<u8>,
+    pub layers: HashMap<String, u32>,
+}
+
+impl Packed {
+    pub const fn layer_count(&self) -> u32 {
+        (self.pixels.len() / LAYER_BYTES) as u32
+    }
+}
+
+/// Pack the PNGs in `dir`, keyed by file stem, in name order so layer indices
+/// are stable between runs. Files that fail to load or are the wrong size are
+/// logged and left out, so they fall back to [`MISSING_LAYER`].
+pub fn pack(dir: &Path) -> Packed {
+    let mut paths: Vec<_> = std::fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.extension().is_some_and(|ext| ext == "png"))
+                .collect()
+        })
+        .unwrap_or_default();
+    paths.sort();
+
+    let mut packed = Packed {
+        pixels: checkerboard(),
+        layers: HashMap::new(),
+    };
+
+    for path in paths {
+        let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+        match load(&path) {
+            Ok(pixels) => {
+                packed.layers.insert(name.to_owned(), packed.layer_count());
+                packed.pixels.extend_from_slice(&pixels);
+            }
+            Err(_e) => {
+                #[cfg(feature = "logging")]
+                warning!("Skipping block texture {}: {_e}", path.display());
+            }
+        }
+    }
+    packed
+}
+
+/// Decode a PNG to RGBA8, requiring it to be `TEXTURE_SIZE` square.
+fn load(path: &Path) -> Result<Vec<u8>, String> {
+    let file = std::fs::File::open(path).map_err(|e| e.to_string())?;
+    let mut decoder = png::Decoder::new(BufReader::new(file));
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(|e| e.to_string())?;
+
+    let mut buf = vec![0; reader.output_buffer_size().ok_or("image too large")?];
+    let info = reader.next_frame(&mut buf).map_err(|e| e.to_string())?;
+    if info.width != TEXTURE_SIZE || info.height != TEXTURE_SIZE {
+        return Err(format!(
+            "expected {TEXTURE_SIZE}x{TEXTURE_SIZE}, found {}x{}",
+            info.width, info.height
+        ));
+    }
+    let buf = &buf[..info.buffer_size()];
+
+    Ok(match info.color_type {
+        png::ColorType::Rgba => buf.to_vec(),
+        png::ColorType::Rgb => buf
+            .chunks_exact(3)
+            .flat_map(|p| [p[0], p[1], p[2], u8::MAX])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => buf
+            .chunks_exact(2)
+            .flat_map(|p| [p[0], p[0], p[0], p[1]])
+            .collect(),
+        png::ColorType::Grayscale => buf.iter().flat_map(|&g| [g, g, g, u8::MAX]).collect(),
+        png::ColorType::Indexed => return Err("palette was not expanded".to_owned()),
+    })
+}
+
+/// Magenta and black checks four texels wide, so the pattern survives the
+/// first couple of mip levels.
+fn checkerboard() -> Vec<u8> {
+    const CHECK: u32 = TEXTURE_SIZE / 4;
+    (0..TEXTURE_SIZE * TEXTURE_SIZE)
+        .flat_map(|i| {
+            let (x, y) = (i % TEXTURE_SIZE, i / TEXTURE_SIZE);
+            if (x / CHECK + y / CHECK).is_multiple_of(2) {
+                [255, 0, 255, 255]
+            } else {
+                [0, 0, 0, 255]
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// A fresh directory under the system temp dir, removed on drop.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("glacian-pack-{name}-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).expect("temp dir is writable");
+            Self(path)
+        }
+
+        /// Write a `size` square RGBA PNG filled with `colour`.
+        fn png(&self, name: &str, size: u32, colour: [u8; 4]) {
+            let file = std::fs::File::create(self.0.join(name)).expect("temp file is writable");
+            let mut encoder = png::Encoder::new(file, size, size);
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_depth(png::BitDepth::Eight);
+            let pixels: Vec<u8> = (0..size * size).flat_map(|_| colour).collect();
+            encoder
+                .write_header()
+                .and_then(|mut writer| writer.write_image_data(&pixels))
+                .expect("PNG encodes");
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn layer_pixels(packed: &Packed, layer: u32) -> &[u8] {
+        let start = layer as usize * LAYER_BYTES;
+        &packed.pixels[start..start + LAYER_BYTES]
+    }
+
+    #[test]
+    fn layers_follow_name_order() {
+        let dir = TempDir::new("order");
+        dir.png("stone.png", TEXTURE_SIZE, [128, 128, 128, 255]);
+        dir.png("dirt.png", TEXTURE_SIZE, [90, 60, 30, 255]);
+        dir.png("snow.png", TEXTURE_SIZE, [250, 250, 255, 255]);
+        std::fs::write(dir.0.join("notes.txt"), "not a texture").expect("temp file is writable");
+
+        let packed = pack(&dir.0);
+        assert_eq!(packed.layer_count(), 4);
+        assert_eq!(packed.layers["dirt"], 1);
+        assert_eq!(packed.layers["snow"], 2);
+        assert_eq!(packed.layers["stone"], 3);
+        assert_eq!(&layer_pixels(&packed, 1)[..4], [90, 60, 30, 255]);
+    }
+
+    #[test]
+    fn the_first_layer_is_a_checkerboard() {
+        let packed = pack(Path::new("/nonexistent/glacian/textures"));
+        assert_eq!(packed.layer_count(), 1);
+        assert!(packed.layers.is_empty());
+
+        let missing = layer_pixels(&packed, MISSING_LAYER);
+        let texel = |x: u32, y: u32| {
+            let i = ((y * TEXTURE_SIZE + x) * 4) as usize;
+            &missing[i..i + 4]
+        };
+        let check = TEXTURE_SIZE / 4;
+        assert_eq!(texel(0, 0), [255, 0, 255, 255]);
+        assert_eq!(texel(check - 1, check - 1), [255, 0, 255, 255]);
+        assert_eq!(texel(check, 0), [0, 0, 0, 255]);
+        assert_eq!(texel(0, check), [0, 0, 0, 255]);
+        assert_eq!(texel(check, check), [255, 0, 255, 255]);
+    }
+
+    #[test]
+    fn wrong_sizes_are_left_out() {
+        let dir = TempDir::new("size");
+        dir.png("big.png", TEXTURE_SIZE * 2, [255, 255, 255, 255]);
+        dir.png("ok.png", TEXTURE_SIZE, [255, 255, 255, 255]);
+
+        let packed = pack(&dir.0);
+        assert_eq!(packed.layer_count(), 2);
+        assert!(!packed.layers.contains_key("big"));
+        assert_eq!(packed.layers["ok"], 1);
+    }
+}
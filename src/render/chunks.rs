@@ -0,0 +1,340 @@
+//! Chunk meshes on the GPU and the pass that draws them lit and shadowed.
+//!
+//! Vertices are pulled in the shaders through their buffer device address, so
+//! the pipelines have no vertex input state. Meshes are lists of quads sharing
+//! one index buffer, with their vertices sub-allocated from a
+//! [`MeshArena`]. Which of them get drawn is decided on the GPU by
+//! [`super::culling`].
+
+use std::collections::HashMap;
+
+use bytemuck::{Pod, Zeroable};
+use glam::{IVec3, Vec3};
+use rootcause::Report;
+use vulkanalia::vk::{self, DeviceV1_0, DeviceV1_2};
+
+use super::allocations::AllocatedBuffer;
+use super::bindless::Bindless;
+use super::block_textures::Face;
+use super::culling::DrawList;
+use super::mesh_arena::{self, MeshArena, Range};
+use super::mesh_buffer::upload_buffer;
+use super::pipeline::{Pipeline, PipelineBuilder};
+use super::shaders;
+use super::timeline::Timeline;
+
+pub mod mesher;
+
+/// Most quads a chunk can have, with every other block solid.
+pub const MAX_QUADS: usize = mesher::CHUNK_SIZE.pow(3) / 2 * 6;
+
+/// Vertices in each block of the arena, 64 MiB worth.
+const ARENA_BLOCK_VERTICES: u32 = 1 << 22;
+/// Most vertices moved between arena blocks each frame.
+const DEFRAGMENT_VERTICES_PER_FRAME: u32 = 1 << 18;
+
+/// Must match `ChunkVertex` in `chunk.slang` and `shadow.slang`.
+#[repr(C)]
+#[derive(Debug, Pod, Zeroable, Copy, Clone, PartialEq)]
+pub struct ChunkVertex {
+    pub position: [f32; 3],
+    /// Bits 0-15 are the block texture layer, bits 16-18 the [`Face`] and
+    /// bits 19-20 the ambient occlusion, from 0 (darkest) to 3.
+    pub data: u32,
+}
+
+impl ChunkVertex {
+    pub fn new(position: Vec3, face: Face, layer: u32, ao: u8) -> Self {
+        Self {
+            position: position.to_array(),
+            data: (layer & 0xffff) | ((face as u32) << 16) | ((u32::from(ao) & 3) << 19),
+        }
+    }
+}
+
+/// A chunk's world space bounds and mesh, one per entry of the table the
+/// culling pass reads. Must match `ChunkInfo` in `cull.slang`, `chunk.slang`
+/// and `shadow.slang`.
+#[repr(C)]
+#[derive(Debug, Pod, Zeroable, Copy, Clone)]
+pub struct ChunkInfo {
+    pub min: Vec3,
+    pub quad_count: u32,
+    pub max: Vec3,
+    /// Offset of the chunk's vertices from `vertices`, passed to draws as
+    /// their `vertexOffset`.
+    pub first_vertex: u32,
+    /// The arena block holding the chunk's vertices.
+    pub vertices: vk::DeviceAddress,
+    /// Rounds the size up to the 16 byte alignment `float3` gives the struct
+    /// in the shaders' std430 arrays.
+    pub _pad: [u32; 2],
+}
+
+const _: () = assert!(size_of::<ChunkInfo>() == 48);
+
+#[repr(C)]
+#[derive(Debug, Pod, Zeroable, Copy, Clone)]
+pub struct PushConstants {
+    /// Bindless index of the frame's [`ChunkInfo`] table. Draws pick their
+    /// entry with `firstInstance`.
+    chunks: u32,
+    /// Bindless index of the frame's [`super::scene::SceneData`].
+    scene: u32,
+    /// Shadow cascade being drawn. Unused by the main pass.
+    cascade: u32,
+}
+
+struct ChunkMesh {
+    vertices: Range,
+    quad_count: u32,
+    min: Vec3,
+    max: Vec3,
+}
+
+/// Every chunk mesh that is resident on the GPU, by chunk position.
+pub struct Chunks {
+    meshes: HashMap<IVec3, ChunkMesh>,
+    /// Vertices of meshes replaced, removed or moved in a frame, kept until it
+    /// has finished.
+    retired: Vec<(Range, u64)>,
+    /// The frame being recorded, or the last one submitted between frames.
+    frame: u64,
+    arena: MeshArena,
+    /// `0, 1, 2, 0, 2, 3` for each of [`MAX_QUADS`] quads, four vertices
+    /// apart.
+    quad_indices: AllocatedBuffer,
+}
+
+impl Chunks {
+    pub fn new(
+        allocator: &vulkanalia_vma::Allocator,
+        device: &vulkanalia::Device,
+        queue: vk::Queue,
+        timeline: &mut Timeline,
+    ) -> Self {
+        let indices: Vec<u32> = (0..MAX_QUADS as u32)
+            .flat_map(|quad| [0, 1, 2, 0, 2, 3].map(|i| quad * 4 + i))
+            .collect();
+        Self {
+            meshes: HashMap::new(),
+            retired: vec![],
+            frame: 0,
+            arena: MeshArena::new::<ChunkVertex>(ARENA_BLOCK_VERTICES),
+            quad_indices: upload_buffer(
+                &indices,
+                vk::BufferUsageFlags::INDEX_BUFFER,
+                allocator,
+                device,
+                queue,
+                timeline,
+            ),
+        }
+    }
+
+    /// Upload the quads of the chunk at `pos`, four vertices each, replacing
+    /// any previous mesh. An empty mesh removes the chunk.
+    pub fn insert(
+        &mut self,
+        pos: IVec3,
+        vertices: &[ChunkVertex],
+        allocator: &vulkanalia_vma::Allocator,
+        device: &vulkanalia::Device,
+        queue: vk::Queue,
+        timeline: &mut Timeline,
+    ) {
+        debug_assert!(vertices.len().is_multiple_of(4), "chunk meshes are quads");
+        debug_assert!(
+            vertices.len() / 4 <= MAX_QUADS,
+            "more quads than a chunk can have"
+        );
+        if vertices.is_empty() {
+            self.remove(pos);
+            return;
+        }
+
+        let (min, max) = vertices
+            .iter()
+            .map(|v| Vec3::from(v.position))
+            .fold((Vec3::INFINITY, Vec3::NEG_INFINITY), |(min, max), p| {
+                (min.min(p), max.max(p))
+            });
+        let range = self
+            .arena
+            .allocate(vertices.len() as u32, allocator, device);
+        self.arena
+            .write(range, vertices, allocator, device, queue, timeline);
+        let mesh = ChunkMesh {
+            vertices: range,
+            quad_count: (vertices.len() / 4) as u32,
+            min,
+            max,
+        };
+        if let Some(old) = self.meshes.insert(pos, mesh) {
+            self.retired.push((old.vertices, self.frame));
+        }
+    }
+
+    pub fn remove(&mut self, pos: IVec3) {
+        if let Some(old) = self.meshes.remove(&pos) {
+            self.retired.push((old.vertices, self.frame));
+        }
+    }
+
+    /// Free vertices retired in frames the GPU has finished, then compact the
+    /// arena a little.
+    pub fn begin_frame(
+        &mut self,
+        frame: u64,
+        allocator: &vulkanalia_vma::Allocator,
+        device: &vulkanalia::Device,
+        queue: vk::Queue,
+        timeline: &mut Timeline,
+    ) {
+        self.frame = frame;
+        let finished = timeline.frames_finished(device);
+        let arena = &mut self.arena;
+        self.retired.retain(|&(range, retired)| {
+            let done = retired < finished;
+            if done {
+                arena.free(range);
+            }
+            !done
+        });
+        arena.release_empty_blocks(allocator);
+
+        let retired = &mut self.retired;
+        arena.defragment(
+            DEFRAGMENT_VERTICES_PER_FRAME,
+            self.meshes.values_mut().map(|chunk| &mut chunk.vertices),
+            |range| retired.push((range, frame)),
+            device,
+            queue,
+            timeline,
+        );
+    }
+
+    pub fn arena_stats(&self) -> mesh_arena::Stats {
+        self.arena.stats()
+    }
+
+    /// Bytes of device memory held by chunk vertices and the shared index
+    /// buffer.
+    pub fn allocated_bytes(&self, allocator: &vulkanalia_vma::Allocator) -> u64 {
+        self.arena.allocated_bytes(allocator) + self.quad_indices.size(allocator)
+    }
+
+    /// The culling table entry of every resident chunk.
+    pub fn infos(&self) -> impl ExactSizeIterator<Item = ChunkInfo> + '_ {
+        self.meshes.values().map(|chunk| ChunkInfo {
+            min: chunk.min,
+            quad_count: chunk.quad_count,
+            max: chunk.max,
+            first_vertex: chunk.vertices.offset,
+            vertices: self.arena.address(chunk.vertices),
+            _pad: [0; 2],
+        })
+    }
+
+    /// Record the draws the culling pass left in `draws` with the bound
+    /// pipeline.
+    pub fn draw(
+        &self,
+        device: &vulkanalia::Device,
+        cmd: vk::CommandBuffer,
+        layout: vk::PipelineLayout,
+        draws: &DrawList,
+        scene: u32,
+        cascade: u32,
+    ) {
+        let constants = PushConstants {
+            chunks: draws.chunks.index(),
+            scene,
+            cascade,
+        };
+        unsafe {
+            device.cmd_push_constants(
+                cmd,
+                layout,
+                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                0,
+                bytemuck::bytes_of(&constants),
+            );
+            device.cmd_bind_index_buffer(cmd, self.quad_indices.buf, 0, vk::IndexType::UINT32);
+            device.cmd_draw_indexed_indirect_count(
+                cmd,
+                draws.commands,
+                draws.offset,
+                draws.count,
+                draws.count_offset,
+                draws.max_draws,
+                size_of::<vk::DrawIndexedIndirectCommand>() as u32,
+            );
+        }
+    }
+
+    /// Expects the device to be idle.
+    pub fn destroy(&mut self, allocator: &vulkanalia_vma::Allocator) {
+        self.meshes.clear();
+        self.retired.clear();
+        self.arena.destroy(allocator);
+        self.quad_indices.flush(allocator);
+    }
+}
+
+/// The main chunk pipeline, drawing over the sky into the HDR draw image.
+pub struct Data {
+    pipeline: Pipeline,
+}
+
+impl Data {
+    pub fn new(
+        device: &vulkanalia::Device,
+        pipeline_cache: vk::PipelineCache,
+        bindless_layout: vk::DescriptorSetLayout,
+        color_format: vk::Format,
+        depth_format: vk::Format,
+    ) -> Result<Self, Report> {
+        let pipeline = PipelineBuilder::new(&shaders::CHUNK, c"vs_main", c"fs_main")
+            .color_formats(&[color_format])
+            .depth_format(depth_format)
+            .depth(true, true, vk::CompareOp::LESS)
+            // Meshes wind their faces counter-clockwise as seen on screen.
+            .cull(vk::CullModeFlags::BACK, vk::FrontFace::COUNTER_CLOCKWISE)
+            .set_layout(bindless_layout)
+            .push_constants::<PushConstants>(
+                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+            )
+            .build(device, pipeline_cache)?;
+
+        Ok(Self { pipeline })
+    }
+
+    /// Draw the chunks the culling pass kept for the camera into the pass's
+    /// colour and depth attachments.
+    pub fn draw(
+        &self,
+        device: &vulkanalia::Device,
+        cmd: vk::CommandBuffer,
+        bindless: &Bindless,
+        chunks: &Chunks,
+        draws: &DrawList,
+        scene: u32,
+    ) {
+        unsafe {
+            device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, self.pipeline.pipeline);
+        }
+        bindless.bind(
+            device,
+            cmd,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline.layout,
+        );
+
+        chunks.draw(device, cmd, self.pipeline.layout, draws, scene, 0);
+    }
+
+    pub fn destroy(&mut self, device: &vulkanalia::Device) {
+        self.pipeline.destroy(device);
+    }
+}
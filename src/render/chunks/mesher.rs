@@ -0,0 +1,453 @@
+//! Greedy meshing of a chunk's voxels with per-vertex ambient occlusion.
+//!
+//! Faces are merged into larger quads only where that cannot change their
+//! shading: two faces merge when their block and all four corner AO values
+//! match, and only along an axis the AO does not vary on.
+
+use glam::{IVec3, Vec3};
+
+use super::ChunkVertex;
+use crate::render::block_textures::Face;
+
+/// Width of a chunk in voxels along each axis.
+pub const CHUNK_SIZE: usize = 32;
+/// A chunk plus a one voxel border on every side.
+const PADDED_SIZE: usize = CHUNK_SIZE + 2;
+
+/// Block ids of one chunk and the layer of voxels around it from its
+/// neighbours, so faces and AO along the edges see across. 0 is air.
+#[derive(Clone)]
+pub struct Voxels {
+    blocks: Box<[u16]>,
+}
+
+impl Default for Voxels {
+    fn default() -> Self {
+        Self {
+            blocks: vec![0; PADDED_SIZE.pow(3)].into_boxed_slice(),
+        }
+    }
+}
+
+impl Voxels {
+    /// The block at `pos`, where each axis runs from -1 to `CHUNK_SIZE`
+    /// inclusive. Anything further out is air.
+    pub fn get(&self, pos: IVec3) -> u16 {
+        Self::index(pos).map_or(0, |i| self.blocks[i])
+    }
+
+    /// Set the block at `pos`, within the same range as [`Voxels::get`].
+    /// Positions outside it are ignored.
+    pub fn set(&mut self, pos: IVec3, block: u16) {
+        if let Some(i) = Self::index(pos) {
+            self.blocks[i] = block;
+        }
+    }
+
+    fn is_solid(&self, pos: IVec3) -> bool {
+        self.get(pos) != 0
+    }
+
+    fn index(pos: IVec3) -> Option<usize> {
+        let padded = pos + IVec3::ONE;
+        if padded.cmplt(IVec3::ZERO).any() || padded.cmpge(IVec3::splat(PADDED_SIZE as i32)).any() {
+            return None;
+        }
+        let [x, y, z] = padded.to_array().map(|c| c as usize);
+        Some(x + PADDED_SIZE * (y + PADDED_SIZE * z))
+    }
+}
+
+/// Quads of four vertices each, drawn as the triangles `(0, 1, 2)` and
+/// `(0, 2, 3)` through the index buffer all chunks share.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Mesh {
+    pub vertices: Vec<ChunkVertex>,
+}
+
+/// Light reaching a face corner, from 0 (boxed in) to 3 (open), given which
+/// of the two voxels along its edges and the one diagonal from it are solid.
+pub const fn vertex_ao(side1: bool, side2: bool, corner: bool) -> u8 {
+    if side1 && side2 {
+        0
+    } else {
+        3 - side1 as u8 - side2 as u8 - corner as u8
+    }
+}
+
+/// What a visible face needs to match to merge with its neighbours.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FaceKey {
+    block: u16,
+    /// AO at the corners (-u, -v), (+u, -v), (+u, +v) and (-u, +v).
+    ao: [u8; 4],
+}
+
+impl FaceKey {
+    /// Whether the AO is the same at both ends of the face along `u`, so a
+    /// quad can stretch along it without changing the shading.
+    const fn uniform_along_u(self) -> bool {
+        self.ao[0] == self.ao[1] && self.ao[3] == self.ao[2]
+    }
+
+    const fn uniform_along_v(self) -> bool {
+        self.ao[0] == self.ao[3] && self.ao[1] == self.ao[2]
+    }
+}
+
+/// Mesh the chunk in `voxels` with its minimum corner at `origin` in world
+/// space. `layer` gives the texture layer for a face of a block.
+pub fn mesh(voxels: &Voxels, origin: Vec3, layer: impl Fn(u16, Face) -> u32) -> Mesh {
+    let mut mesh = Mesh::default();
+    let mut mask = vec![None; CHUNK_SIZE * CHUNK_SIZE];
+
+    for face in Face::ALL {
+        let (axis, sign) = face_axis(face);
+        // The two axes across the face, ordered so `u` cross `v` is `+axis`.
+        let u_axis = (axis + 1) % 3;
+        let v_axis = (axis + 2) % 3;
+        let unit = |axis: usize| IVec3::AXES[axis];
+        let normal = unit(axis) * sign;
+
+        for slice in 0..CHUNK_SIZE as i32 {
+            for v in 0..CHUNK_SIZE as i32 {
+                for u in 0..CHUNK_SIZE as i32 {
+                    let pos = unit(axis) * slice + unit(u_axis) * u + unit(v_axis) * v;
+                    let front = pos + normal;
+                    let block = voxels.get(pos);
+                    mask[u as usize + v as usize * CHUNK_SIZE] =
+                        (block != 0 && !voxels.is_solid(front)).then(|| FaceKey {
+                            block,
+                            ao: [(-1, -1), (1, -1), (1, 1), (-1, 1)].map(|(du, dv)| {
+                                let du = unit(u_axis) * du;
+                                let dv = unit(v_axis) * dv;
+                                vertex_ao(
+                                    voxels.is_solid(front + du),
+                                    voxels.is_solid(front + dv),
+                                    voxels.is_solid(front + du + dv),
+                                )
+                            }),
+                        });
+                }
+            }
+
+            // The face sits on the far side of the voxel when it points
+            // along the axis.
+            let plane = slice + i32::from(sign > 0);
+            greedy(&mut mask, |u, v, width, height, key| {
+                let corner = |u: usize, v: usize| {
+                    let pos =
+                        unit(axis) * plane + unit(u_axis) * u as i32 + unit(v_axis) * v as i32;
+                    origin + pos.as_vec3()
+                };
+                let positions = [
+                    corner(u, v),
+                    corner(u + width, v),
+                    corner(u + width, v + height),
+                    corner(u, v + height),
+                ];
+                let layer = layer(key.block, face);
+                push_quad(&mut mesh, positions, key.ao, sign > 0, |position, ao| {
+                    ChunkVertex::new(position, face, layer, ao)
+                });
+            });
+        }
+    }
+
+    mesh
+}
+
+/// The axis a face's normal lies on and which way along it the face points.
+/// World space has `+Z` down, so the top face points along `-Z`.
+const fn face_axis(face: Face) -> (usize, i32) {
+    match face {
+        Face::Top => (2, -1),
+        Face::Bottom => (2, 1),
+        Face::North => (1, 1),
+        Face::South => (1, -1),
+        Face::East => (0, 1),
+        Face::West => (0, -1),
+    }
+}
+
+/// Cover the faces in `mask` with as few rectangles as possible, clearing it
+/// as it goes. `emit` gets each rectangle's corner, size and face.
+fn greedy(mask: &mut [Option<FaceKey>], mut emit: impl FnMut(usize, usize, usize, usize, FaceKey)) {
+    for v in 0..CHUNK_SIZE {
+        let mut u = 0;
+        while u < CHUNK_SIZE {
+            let Some(key) = mask[u + v * CHUNK_SIZE] else {
+                u += 1;
+                continue;
+            };
+
+            let mut width = 1;
+            if key.uniform_along_u() {
+                while u + width < CHUNK_SIZE && mask[u + width + v * CHUNK_SIZE] == Some(key) {
+                    width += 1;
+                }
+            }
+
+            let mut height = 1;
+            if key.uniform_along_v() {
+                while v + height < CHUNK_SIZE
+                    && mask[(v + height) * CHUNK_SIZE..][u..u + width]
+                        .iter()
+                        .all(|&cell| cell == Some(key))
+                {
+                    height += 1;
+                }
+            }
+
+            for row in v..v + height {
+                mask[row * CHUNK_SIZE..][u..u + width].fill(None);
+            }
+            emit(u, v, width, height, key);
+            u += width;
+        }
+    }
+}
+
+/// Add a quad with corners in `(-u, -v)`, `(+u, -v)`, `(+u, +v)`, `(-u, +v)`
+/// order, which winds counter-clockwise on screen for faces pointing along
+/// `-axis`. `reverse` winds faces pointing along `+axis` the other way.
+fn push_quad(
+    mesh: &mut Mesh,
+    mut positions: [Vec3; 4],
+    mut ao: [u8; 4],
+    reverse: bool,
+    vertex: impl Fn(Vec3, u8) -> ChunkVertex,
+) {
+    if reverse {
+        positions.swap(1, 3);
+        ao.swap(1, 3);
+    }
+
+    // Split along the diagonal between the darker corners so the gradient
+    // stays symmetric instead of smearing along one triangle. Quads always
+    // split between their first and third corner, so start one corner on.
+    if u32::from(ao[0]) + u32::from(ao[2]) > u32::from(ao[1]) + u32::from(ao[3]) {
+        positions.rotate_left(1);
+        ao.rotate_left(1);
+    }
+
+    mesh.vertices.extend(
+        positions
+            .into_iter()
+            .zip(ao)
+            .map(|(position, ao)| vertex(position, ao)),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Mat4;
+
+    use super::*;
+
+    fn voxels(blocks: &[IVec3]) -> Voxels {
+        let mut voxels = Voxels::default();
+        for &pos in blocks {
+            voxels.set(pos, 1);
+        }
+        voxels
+    }
+
+    fn mesh_of(blocks: &[IVec3]) -> Mesh {
+        mesh(&voxels(blocks), Vec3::ZERO, |_, _| 0)
+    }
+
+    fn ao(vertex: &ChunkVertex) -> u8 {
+        ((vertex.data >> 19) & 3) as u8
+    }
+
+    fn is_top(vertex: &ChunkVertex) -> bool {
+        (vertex.data >> 16) & 7 == Face::Top as u32
+    }
+
+    /// AO of the top face vertex of the block at `block` on the corner
+    /// towards `(dx, dy)`.
+    fn top_ao(mesh: &Mesh, block: IVec3, dx: i32, dy: i32) -> u8 {
+        let corner = Vec3::new(
+            (block.x + i32::from(dx > 0)) as f32,
+            (block.y + i32::from(dy > 0)) as f32,
+            block.z as f32,
+        );
+        let vertex = mesh
+            .vertices
+            .iter()
+            .find(|v| is_top(v) && Vec3::from(v.position) == corner)
+            .expect("top face vertex at the corner");
+        ao(vertex)
+    }
+
+    /// Top faces lying at height `z`, leaving out those of occluders above.
+    fn top_quad_count(mesh: &Mesh, z: i32) -> usize {
+        mesh.vertices
+            .iter()
+            .filter(|v| is_top(v) && v.position[2] == z as f32)
+            .count()
+            / 4
+    }
+
+    #[test]
+    fn vertex_ao_levels() {
+        assert_eq!(vertex_ao(false, false, false), 3);
+        assert_eq!(vertex_ao(false, false, true), 2);
+        assert_eq!(vertex_ao(true, false, false), 2);
+        assert_eq!(vertex_ao(false, true, false), 2);
+        assert_eq!(vertex_ao(true, false, true), 1);
+        assert_eq!(vertex_ao(true, true, false), 0);
+        assert_eq!(vertex_ao(true, true, true), 0);
+    }
+
+    #[test]
+    fn lone_block_is_unoccluded() {
+        let mesh = mesh_of(&[IVec3::splat(5)]);
+        assert_eq!(mesh.vertices.len(), 24);
+        assert!(mesh.vertices.iter().all(|v| ao(v) == 3));
+    }
+
+    #[test]
+    fn block_beside_the_face_darkens_one_edge() {
+        // `-Z` is up, so (6, 5, 4) sits above the block's eastern neighbour.
+        let block = IVec3::splat(5);
+        let mesh = mesh_of(&[block, IVec3::new(6, 5, 4)]);
+        assert_eq!(top_ao(&mesh, block, 1, -1), 2);
+        assert_eq!(top_ao(&mesh, block, 1, 1), 2);
+        assert_eq!(top_ao(&mesh, block, -1, -1), 3);
+        assert_eq!(top_ao(&mesh, block, -1, 1), 3);
+    }
+
+    #[test]
+    fn inner_corner_is_darkest() {
+        let block = IVec3::splat(5);
+        let mesh = mesh_of(&[block, IVec3::new(6, 5, 4), IVec3::new(5, 6, 4)]);
+        assert_eq!(top_ao(&mesh, block, 1, 1), 0);
+        assert_eq!(top_ao(&mesh, block, 1, -1), 2);
+        assert_eq!(top_ao(&mesh, block, -1, 1), 2);
+        assert_eq!(top_ao(&mesh, block, -1, -1), 3);
+    }
+
+    #[test]
+    fn side_and_corner_together() {
+        let block = IVec3::splat(5);
+        let mesh = mesh_of(&[block, IVec3::new(6, 5, 4), IVec3::new(6, 6, 4)]);
+        assert_eq!(top_ao(&mesh, block, 1, 1), 1);
+        assert_eq!(top_ao(&mesh, block, 1, -1), 2);
+    }
+
+    #[test]
+    fn diagonal_block_darkens_one_corner() {
+        let block = IVec3::splat(5);
+        let mesh = mesh_of(&[block, IVec3::new(6, 6, 4)]);
+        assert_eq!(top_ao(&mesh, block, 1, 1), 2);
+        assert_eq!(top_ao(&mesh, block, 1, -1), 3);
+        assert_eq!(top_ao(&mesh, block, -1, 1), 3);
+        assert_eq!(top_ao(&mesh, block, -1, -1), 3);
+    }
+
+    #[test]
+    fn neighbouring_chunk_border_occludes() {
+        // The padding holds the next chunk's voxels.
+        let block = IVec3::new(CHUNK_SIZE as i32 - 1, 5, 5);
+        let mesh = mesh_of(&[block, IVec3::new(CHUNK_SIZE as i32, 5, 4)]);
+        assert_eq!(top_ao(&mesh, block, 1, 1), 2);
+        // Only faces of the chunk's own voxels are meshed.
+        assert_eq!(top_quad_count(&mesh, 5), 1);
+    }
+
+    #[test]
+    fn flat_floor_merges_into_one_quad() {
+        let floor: Vec<_> = (0..4)
+            .flat_map(|x| (0..4).map(move |y| IVec3::new(x, y, 5)))
+            .collect();
+        let mesh = mesh_of(&floor);
+        assert_eq!(top_quad_count(&mesh, 5), 1);
+        assert!(mesh.vertices.iter().all(|v| ao(v) == 3));
+    }
+
+    #[test]
+    fn faces_with_different_ao_are_not_merged() {
+        let mut blocks: Vec<_> = (0..5).map(|x| IVec3::new(x, 5, 5)).collect();
+        blocks.push(IVec3::new(3, 6, 4));
+        let mesh = mesh_of(&blocks);
+
+        // The two open faces merge; the three touched by the block above
+        // stay separate so each keeps its own corners.
+        assert_eq!(top_quad_count(&mesh, 5), 4);
+        assert_eq!(top_ao(&mesh, IVec3::new(2, 5, 5), 1, 1), 2);
+        assert_eq!(top_ao(&mesh, IVec3::new(3, 5, 5), -1, 1), 2);
+        assert_eq!(top_ao(&mesh, IVec3::new(3, 5, 5), 1, 1), 2);
+        assert_eq!(top_ao(&mesh, IVec3::new(4, 5, 5), -1, 1), 2);
+        assert_eq!(top_ao(&mesh, IVec3::new(0, 5, 5), -1, 1), 3);
+    }
+
+    #[test]
+    fn faces_merge_along_an_even_gradient() {
+        // A wall along the north edge darkens every face the same way, so
+        // they can still merge along it.
+        let mut blocks: Vec<_> = (0..4).map(|x| IVec3::new(x, 5, 5)).collect();
+        blocks.extend((-1..5).map(|x| IVec3::new(x, 6, 4)));
+        let mesh = mesh_of(&blocks);
+        assert_eq!(top_quad_count(&mesh, 5), 1);
+        // Each northern corner has the wall beside it and diagonal from it.
+        assert_eq!(top_ao(&mesh, IVec3::new(0, 5, 5), -1, 1), 1);
+        assert_eq!(top_ao(&mesh, IVec3::new(3, 5, 5), 1, 1), 1);
+        assert_eq!(top_ao(&mesh, IVec3::new(0, 5, 5), -1, -1), 3);
+    }
+
+    #[test]
+    fn quads_split_along_the_darker_diagonal() {
+        let block = IVec3::splat(5);
+        for occluder in [
+            IVec3::new(6, 6, 4),
+            IVec3::new(6, 4, 4),
+            IVec3::new(4, 4, 4),
+            IVec3::new(4, 6, 4),
+        ] {
+            let mesh = mesh_of(&[block, occluder]);
+            let quad = mesh
+                .vertices
+                .chunks(4)
+                .find(|quad| is_top(&quad[0]) && quad[0].position[2] == 5.)
+                .expect("top face");
+            // Both triangles share the edge from the first to third corner.
+            let diagonal = [ao(&quad[0]), ao(&quad[2])];
+            assert!(diagonal.contains(&2), "diagonal misses the dark corner");
+        }
+    }
+
+    #[test]
+    fn faces_wind_counter_clockwise_on_screen() {
+        let mesh = mesh_of(&[IVec3::splat(5)]);
+        let proj = Mat4::perspective_rh(1., 1., 0.1, 100.);
+
+        let triangles = mesh
+            .vertices
+            .chunks(4)
+            .flat_map(|quad| [[quad[0], quad[1], quad[2]], [quad[0], quad[2], quad[3]]]);
+        for triangle in triangles {
+            let [a, b, c] = triangle.map(|v| Vec3::from(v.position));
+            let face = (triangle[0].data >> 16) & 7;
+            let normal = [
+                Vec3::NEG_Z,
+                Vec3::Z,
+                Vec3::Y,
+                Vec3::NEG_Y,
+                Vec3::X,
+                Vec3::NEG_X,
+            ][face as usize];
+
+            let centre = (a + b + c) / 3.;
+            let up = if normal.z == 0. { Vec3::Z } else { Vec3::Y };
+            let view_proj = proj * Mat4::look_to_rh(centre + normal * 3., -normal, up);
+            let [a, b, c] = [a, b, c].map(|p| view_proj.project_point3(p).truncate());
+
+            // Vulkan's signed area in framebuffer space, positive for faces
+            // it counts as counter-clockwise.
+            let area = -0.5 * ((b - a).perp_dot(c - a));
+            assert!(area > 0., "face {face} winds clockwise");
+        }
+    }
+}
@@ -0,0 +1,630 @@
+//! Chunk culling on the GPU for the camera and every shadow cascade.
+//!
+//! A compute pass tests each chunk's bounds against the view frustums and,
+//! for the camera, against a depth pyramid built from the previous frame. The
+//! chunks that pass are appended as indexed indirect draws with a count per
+//! view, which the chunk and shadow passes draw with
+//! `cmd_draw_indexed_indirect_count`.
+
+use bytemuck::{Pod, Zeroable};
+use glam::{Mat4, Vec2};
+use rootcause::Report;
+use vulkanalia::vk::{self, DeviceV1_0, Handle, HasBuilder};
+
+use super::allocations::{AllocatedBuffer, DescriptorAllocator};
+use super::bindless::{self, Bindless};
+use super::chunks::{ChunkInfo, Chunks};
+use super::descriptor::{DescriptorLayoutBuilder, DescriptorWriter};
+use super::graph::{Access, BufferId, Graph, ImageId};
+use super::pipeline::{ComputePipelineBuilder, Pipeline};
+use super::shaders;
+use super::shadows::CASCADE_COUNT;
+use super::utils::memory_barrier;
+
+mod pyramid;
+
+use pyramid::Pyramid;
+
+/// The camera followed by each shadow cascade.
+const VIEW_COUNT: usize = 1 + CASCADE_COUNT;
+/// A draw count per view, then the camera's frustum and occlusion culled
+/// chunks. Must match `cull.slang`.
+const COUNTER_COUNT: usize = VIEW_COUNT + 2;
+const FRUSTUM_CULLED: usize = VIEW_COUNT;
+const OCCLUSION_CULLED: usize = VIEW_COUNT + 1;
+
+/// Chunks each workgroup of `cull.slang` tests.
+const CULL_GROUP_SIZE: u32 = 64;
+/// Width and height of each workgroup of `hiz.slang`.
+const PYRAMID_GROUP_SIZE: u32 = 8;
+
+/// Smallest number of chunks the per frame buffers are sized for.
+const MIN_CAPACITY: u32 = 256;
+
+/// What culling did in a recent frame. Counts are read back once the frame
+/// has finished, so they trail rendering by a couple of frames.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Chunks resident on the GPU.
+    pub chunks: u32,
+    /// Chunks drawn by the camera.
+    pub drawn: u32,
+    /// Chunks outside the camera's frustum.
+    pub frustum_culled: u32,
+    /// Chunks inside the frustum but behind the previous frame's depth.
+    pub occlusion_culled: u32,
+    /// Chunk draws across every shadow cascade.
+    pub shadow_drawn: u32,
+}
+
+/// Must match `PushConstants` in `cull.slang`.
+#[repr(C)]
+#[derive(Debug, Pod, Zeroable, Copy, Clone)]
+struct CullPushConstants {
+    scene: u32,
+    chunks: u32,
+    chunk_count: u32,
+    commands: u32,
+    counters: u32,
+    /// Draw commands each view has room for.
+    capacity: u32,
+    pyramid: u32,
+    pyramid_sampler: u32,
+    pyramid_size: Vec2,
+    pyramid_levels: u32,
+    /// Whether the pyramid holds a previous frame to test against.
+    occlusion: u32,
+}
+
+/// Must match `PushConstants` in `hiz.slang`.
+#[repr(C)]
+#[derive(Debug, Pod, Zeroable, Copy, Clone)]
+struct PyramidPushConstants {
+    /// The depth target for level 0, the pyramid itself after that.
+    source: u32,
+    sampler: u32,
+    level: u32,
+    _pad: u32,
+    /// Depth texels across each level 0 texel.
+    scale: Vec2,
+}
+
+/// Indirect draws written by the culling pass for one view.
+pub struct DrawList {
+    pub commands: vk::Buffer,
+    pub offset: vk::DeviceSize,
+    pub count: vk::Buffer,
+    pub count_offset: vk::DeviceSize,
+    pub max_draws: u32,
+    /// The chunk table draws index with `firstInstance`.
+    pub chunks: bindless::Handle<bindless::StorageBuffer>,
+}
+
+/// One frame in flight's chunk table, draw commands and counters.
+struct Frame {
+    /// Chunks the buffers have room for.
+    capacity: u32,
+    chunk_count: u32,
+    table: AllocatedBuffer,
+    table_handle: bindless::Handle<bindless::StorageBuffer>,
+    commands: AllocatedBuffer,
+    commands_handle: bindless::Handle<bindless::StorageBuffer>,
+    counters: AllocatedBuffer,
+    counters_handle: bindless::Handle<bindless::StorageBuffer>,
+    /// Host visible copy of `counters`, read once the frame has finished.
+    readback: AllocatedBuffer,
+    /// Whether `readback` has been written since the buffers were created.
+    submitted: bool,
+}
+
+impl Frame {
+    fn new(
+        capacity: u32,
+        bindless: &mut Bindless,
+        allocator: &vulkanalia_vma::Allocator,
+        device: &vulkanalia::Device,
+    ) -> Self {
+        let table_size = u64::from(capacity) * size_of::<ChunkInfo>() as u64;
+        let commands_size = u64::from(capacity)
+            * VIEW_COUNT as u64
+            * size_of::<vk::DrawIndexedIndirectCommand>() as u64;
+        let counters_size = (COUNTER_COUNT * size_of::<u32>()) as u64;
+
+        let table = AllocatedBuffer::new(
+            allocator,
+            table_size,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            vulkanalia_vma::MemoryUsage::AutoPreferDevice,
+        );
+        let commands = AllocatedBuffer::new(
+            allocator,
+            commands_size,
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::INDIRECT_BUFFER,
+            vulkanalia_vma::MemoryUsage::AutoPreferDevice,
+        );
+        let counters = AllocatedBuffer::new(
+            allocator,
+            counters_size,
+            vk::BufferUsageFlags::STORAGE_BUFFER
+                | vk::BufferUsageFlags::INDIRECT_BUFFER
+                | vk::BufferUsageFlags::TRANSFER_SRC
+                | vk::BufferUsageFlags::TRANSFER_DST,
+            vulkanalia_vma::MemoryUsage::AutoPreferDevice,
+        );
+        let readback = AllocatedBuffer::with_host_access(
+            allocator,
+            counters_size,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            vulkanalia_vma::MemoryUsage::AutoPreferHost,
+            vulkanalia_vma::AllocationCreateFlags::HOST_ACCESS_RANDOM,
+        );
+
+        Self {
+            capacity,
+            chunk_count: 0,
+            table_handle: bindless.add_storage_buffer(device, table.buf, 0, table_size),
+            table,
+            commands_handle: bindless.add_storage_buffer(device, commands.buf, 0, commands_size),
+            commands,
+            counters_handle: bindless.add_storage_buffer(device, counters.buf, 0, counters_size),
+            counters,
+            readback,
+            submitted: false,
+        }
+    }
+
+    /// The counters of the frame's last submission, which must have finished.
+    fn read_counters(&self, allocator: &vulkanalia_vma::Allocator) -> [u32; COUNTER_COUNT] {
+        let mut counters = [0; COUNTER_COUNT];
+        if !self.submitted {
+            return counters;
+        }
+        let bytes: &mut [u8] = bytemuck::cast_slice_mut(&mut counters);
+        unsafe {
+            allocator
+                .invalidate_allocation(self.readback.allocation, 0, vk::WHOLE_SIZE)
+                .unwrap();
+            let mem = allocator.map_memory(self.readback.allocation).unwrap();
+            bytes.copy_from_slice(std::slice::from_raw_parts(mem, bytes.len()));
+            allocator.unmap_memory(self.readback.allocation);
+        }
+        counters
+    }
+
+    fn draws(&self, view: usize) -> DrawList {
+        DrawList {
+            commands: self.commands.buf,
+            offset: (view as u64)
+                * u64::from(self.capacity)
+                * size_of::<vk::DrawIndexedIndirectCommand>() as u64,
+            count: self.counters.buf,
+            count_offset: (view * size_of::<u32>()) as u64,
+            max_draws: self.capacity,
+            chunks: self.table_handle,
+        }
+    }
+
+    fn destroy(&mut self, bindless: &mut Bindless, allocator: &vulkanalia_vma::Allocator) {
+        bindless.remove_storage_buffer(self.table_handle);
+        bindless.remove_storage_buffer(self.commands_handle);
+        bindless.remove_storage_buffer(self.counters_handle);
+        self.table.flush(allocator);
+        self.commands.flush(allocator);
+        self.counters.flush(allocator);
+        self.readback.flush(allocator);
+    }
+
+    fn allocated_bytes(&self, allocator: &vulkanalia_vma::Allocator) -> u64 {
+        [&self.table, &self.commands, &self.counters, &self.readback]
+            .iter()
+            .map(|buffer| buffer.size(allocator))
+            .sum()
+    }
+}
+
+/// A frame's culling buffers and the depth pyramid, as imported into its
+/// frame graph by [`Culling::import`].
+#[derive(Debug, Clone, Copy)]
+pub struct Imported {
+    pub commands: BufferId,
+    pub counters: BufferId,
+    /// Exported for the host to read once the frame has finished.
+    pub readback: BufferId,
+    /// Kept in `GENERAL`.
+    pub pyramid: ImageId,
+}
+
+/// Per frame culling buffers, the depth pyramid and the culling statistics.
+pub struct Culling {
+    frames: [Frame; 2],
+    pyramid: Pyramid,
+    /// The camera's view projection when the pyramid was last built, or
+    /// `None` if it holds nothing to test against yet.
+    previous_view_proj: Option<Mat4>,
+    stats: Stats,
+}
+
+impl Culling {
+    pub fn new(
+        render_extent: vk::Extent2D,
+        bindless: &mut Bindless,
+        allocator: &vulkanalia_vma::Allocator,
+        device: &vulkanalia::Device,
+    ) -> Self {
+        Self {
+            frames: [(); 2].map(|()| Frame::new(MIN_CAPACITY, bindless, allocator, device)),
+            pyramid: Pyramid::new(render_extent, bindless, allocator, device),
+            previous_view_proj: None,
+            stats: Stats::default(),
+        }
+    }
+
+    /// Read back the statistics of `frame`'s previous submission, which must
+    /// have finished, and write its chunk table. The table's buffers grow to
+    /// fit every chunk.
+    pub fn begin_frame(
+        &mut self,
+        frame: usize,
+        chunks: &Chunks,
+        bindless: &mut Bindless,
+        allocator: &vulkanalia_vma::Allocator,
+        device: &vulkanalia::Device,
+    ) {
+        let counters = self.frames[frame].read_counters(allocator);
+        if self.frames[frame].submitted {
+            self.stats = Stats {
+                chunks: self.frames[frame].chunk_count,
+                drawn: counters[0],
+                frustum_culled: counters[FRUSTUM_CULLED],
+                occlusion_culled: counters[OCCLUSION_CULLED],
+                shadow_drawn: counters[1..VIEW_COUNT].iter().sum(),
+            };
+        }
+
+        let infos = chunks.infos();
+        let chunk_count = infos.len() as u32;
+        if chunk_count > self.frames[frame].capacity {
+            let capacity = chunk_count.next_power_of_two();
+            self.frames[frame].destroy(bindless, allocator);
+            self.frames[frame] = Frame::new(capacity, bindless, allocator, device);
+        }
+
+        let target = &mut self.frames[frame];
+        target.chunk_count = chunk_count;
+        unsafe {
+            let mem = allocator.map_memory(target.table.allocation).unwrap();
+            let table = std::slice::from_raw_parts_mut(mem.cast::<ChunkInfo>(), infos.len());
+            for (entry, info) in table.iter_mut().zip(infos) {
+                *entry = info;
+            }
+            allocator.unmap_memory(target.table.allocation);
+        }
+    }
+
+    pub const fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    /// The camera's view projection the depth pyramid was built with.
+    pub const fn previous_view_proj(&self) -> Option<Mat4> {
+        self.previous_view_proj
+    }
+
+    /// Bring `frame`'s buffers and the depth pyramid into `graph`.
+    pub fn import(&self, graph: &mut Graph, frame: usize) -> Imported {
+        let target = &self.frames[frame];
+        let readback = graph.import_buffer(target.readback.buf, Access::HostRead);
+        graph.export_buffer(readback, Access::HostRead);
+        Imported {
+            commands: graph.import_buffer(target.commands.buf, Access::IndirectRead),
+            counters: graph.import_buffer(target.counters.buf, Access::TransferRead),
+            readback,
+            pyramid: self
+                .pyramid
+                .import(graph, self.previous_view_proj.is_none()),
+        }
+    }
+
+    /// Zero `frame`'s draw counts before culling.
+    pub fn clear_counters(
+        &self,
+        device: &vulkanalia::Device,
+        cmd: vk::CommandBuffer,
+        frame: usize,
+    ) {
+        unsafe {
+            device.cmd_fill_buffer(cmd, self.frames[frame].counters.buf, 0, vk::WHOLE_SIZE, 0);
+        }
+    }
+
+    /// Copy `frame`'s counters to where the host can read them.
+    pub fn read_back(&self, device: &vulkanalia::Device, cmd: vk::CommandBuffer, frame: usize) {
+        let target = &self.frames[frame];
+        unsafe {
+            device.cmd_copy_buffer(
+                cmd,
+                target.counters.buf,
+                target.readback.buf,
+                &[vk::BufferCopy::builder().size((COUNTER_COUNT * size_of::<u32>()) as u64)],
+            );
+        }
+    }
+
+    /// Note that `frame`'s culling and a depth pyramid from the camera's
+    /// `view_proj` have been recorded.
+    pub fn recorded(&mut self, frame: usize, view_proj: Mat4) {
+        self.frames[frame].submitted = true;
+        self.previous_view_proj = Some(view_proj);
+    }
+
+    pub fn camera_draws(&self, frame: usize) -> DrawList {
+        self.frames[frame].draws(0)
+    }
+
+    pub fn cascade_draws(&self, frame: usize, cascade: usize) -> DrawList {
+        self.frames[frame].draws(1 + cascade)
+    }
+
+    /// Bytes of memory held by the chunk tables, draw commands and counters.
+    pub fn buffer_bytes(&self, allocator: &vulkanalia_vma::Allocator) -> u64 {
+        self.frames
+            .iter()
+            .map(|frame| frame.allocated_bytes(allocator))
+            .sum()
+    }
+
+    /// Bytes of device memory held by the depth pyramid.
+    pub fn pyramid_bytes(&self, allocator: &vulkanalia_vma::Allocator) -> u64 {
+        self.pyramid.allocated_bytes(allocator)
+    }
+
+    /// Size the depth pyramid for a new render extent. Expects the device to
+    /// be idle.
+    pub fn resize(
+        &mut self,
+        render_extent: vk::Extent2D,
+        bindless: &mut Bindless,
+        allocator: &vulkanalia_vma::Allocator,
+        device: &vulkanalia::Device,
+    ) {
+        self.pyramid.destroy(bindless, allocator, device);
+        self.pyramid = Pyramid::new(render_extent, bindless, allocator, device);
+        self.previous_view_proj = None;
+    }
+
+    pub fn destroy(
+        &mut self,
+        bindless: &mut Bindless,
+        allocator: &vulkanalia_vma::Allocator,
+        device: &vulkanalia::Device,
+    ) {
+        for frame in &mut self.frames {
+            frame.destroy(bindless, allocator);
+        }
+        self.pyramid.destroy(bindless, allocator, device);
+    }
+}
+
+/// The culling and depth pyramid compute pipelines.
+pub struct Data {
+    cull: Pipeline,
+    pyramid: Pipeline,
+    /// Set 1 of the pyramid pipeline, the level a dispatch writes.
+    level_layout: vk::DescriptorSetLayout,
+}
+
+impl Data {
+    pub fn new(
+        device: &vulkanalia::Device,
+        pipeline_cache: vk::PipelineCache,
+        bindless_layout: vk::DescriptorSetLayout,
+    ) -> Result<Self, Report> {
+        let cull = ComputePipelineBuilder::new(&shaders::CULL, c"cs_main")
+            .set_layout(bindless_layout)
+            .push_constants::<CullPushConstants>()
+            .build(device, pipeline_cache)?;
+
+        let mut level_layout = DescriptorLayoutBuilder::new();
+        level_layout.add_binding(0, vk::DescriptorType::STORAGE_IMAGE);
+        let level_layout = level_layout.build(
+            device,
+            vk::ShaderStageFlags::COMPUTE,
+            vk::DescriptorSetLayoutCreateFlags::empty(),
+        );
+        let pyramid = ComputePipelineBuilder::new(&shaders::HIZ, c"cs_main")
+            .set_layout(bindless_layout)
+            .set_layout(level_layout)
+            .push_constants::<PyramidPushConstants>()
+            .build(device, pipeline_cache);
+        let pyramid = match pyramid {
+            Ok(pyramid) => pyramid,
+            Err(e) => {
+                cull.destroy(device);
+                unsafe { device.destroy_descriptor_set_layout(level_layout, None) };
+                return Err(e);
+            }
+        };
+
+        Ok(Self {
+            cull,
+            pyramid,
+            level_layout,
+        })
+    }
+
+    /// A set for each level of the depth pyramid to write through, only valid
+    /// for the frame `descriptors` belongs to.
+    pub fn pyramid_sets(
+        &self,
+        device: &vulkanalia::Device,
+        descriptors: &mut DescriptorAllocator,
+        culling: &Culling,
+    ) -> Vec<vk::DescriptorSet> {
+        let mut writer = DescriptorWriter::new();
+        culling
+            .pyramid
+            .level_views()
+            .iter()
+            .map(|&view| {
+                let set = descriptors.allocate(device, self.level_layout);
+                writer.clear();
+                writer
+                    .write_image(
+                        0,
+                        view,
+                        vk::Sampler::null(),
+                        vk::ImageLayout::GENERAL,
+                        vk::DescriptorType::STORAGE_IMAGE,
+                    )
+                    .update_set(device, set);
+                set
+            })
+            .collect()
+    }
+
+    /// Fill `frame`'s draw lists with the chunks each view can see. The
+    /// counters must have been cleared.
+    pub fn cull(
+        &self,
+        device: &vulkanalia::Device,
+        cmd: vk::CommandBuffer,
+        bindless: &Bindless,
+        culling: &Culling,
+        frame: usize,
+        scene: bindless::Handle<bindless::StorageBuffer>,
+    ) {
+        let target = &culling.frames[frame];
+        let pyramid = &culling.pyramid;
+        let constants = CullPushConstants {
+            scene: scene.index(),
+            chunks: target.table_handle.index(),
+            chunk_count: target.chunk_count,
+            commands: target.commands_handle.index(),
+            counters: target.counters_handle.index(),
+            capacity: target.capacity,
+            pyramid: pyramid.texture.index(),
+            pyramid_sampler: pyramid.sampler_handle.index(),
+            pyramid_size: Vec2::new(pyramid.extent.width as f32, pyramid.extent.height as f32),
+            pyramid_levels: pyramid.levels(),
+            occlusion: u32::from(culling.previous_view_proj.is_some()),
+        };
+
+        unsafe {
+            device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::COMPUTE, self.cull.pipeline);
+        }
+        bindless.bind(
+            device,
+            cmd,
+            vk::PipelineBindPoint::COMPUTE,
+            self.cull.layout,
+        );
+        unsafe {
+            device.cmd_push_constants(
+                cmd,
+                self.cull.layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                bytemuck::bytes_of(&constants),
+            );
+            device.cmd_dispatch(
+                cmd,
+                target.chunk_count.div_ceil(CULL_GROUP_SIZE),
+                VIEW_COUNT as u32,
+                1,
+            );
+        }
+    }
+
+    /// Reduce the camera's depth into the pyramid the next frame culls
+    /// against. `depth` must be readable by compute shaders and the pyramid
+    /// writable, in `GENERAL`. `level_sets` come from
+    /// [`pyramid_sets`](Self::pyramid_sets).
+    #[allow(clippy::too_many_arguments)]
+    pub fn build_pyramid(
+        &self,
+        device: &vulkanalia::Device,
+        cmd: vk::CommandBuffer,
+        bindless: &Bindless,
+        culling: &Culling,
+        level_sets: &[vk::DescriptorSet],
+        depth: bindless::Handle<bindless::SampledImage>,
+        draw_extent: vk::Extent2D,
+    ) {
+        let pyramid = &culling.pyramid;
+
+        unsafe {
+            device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::COMPUTE, self.pyramid.pipeline);
+        }
+        bindless.bind(
+            device,
+            cmd,
+            vk::PipelineBindPoint::COMPUTE,
+            self.pyramid.layout,
+        );
+
+        let scale = Vec2::new(
+            draw_extent.width as f32 / pyramid.extent.width as f32,
+            draw_extent.height as f32 / pyramid.extent.height as f32,
+        );
+        for level in 0..pyramid.levels() {
+            let constants = PyramidPushConstants {
+                source: if level == 0 {
+                    depth.index()
+                } else {
+                    pyramid.texture.index()
+                },
+                sampler: pyramid.sampler_handle.index(),
+                level,
+                _pad: 0,
+                scale,
+            };
+            let width = (pyramid.extent.width >> level).max(1);
+            let height = (pyramid.extent.height >> level).max(1);
+            unsafe {
+                device.cmd_bind_descriptor_sets(
+                    cmd,
+                    vk::PipelineBindPoint::COMPUTE,
+                    self.pyramid.layout,
+                    1,
+                    &[level_sets[level as usize]],
+                    &[],
+                );
+                device.cmd_push_constants(
+                    cmd,
+                    self.pyramid.layout,
+                    vk::ShaderStageFlags::COMPUTE,
+                    0,
+                    bytemuck::bytes_of(&constants),
+                );
+                device.cmd_dispatch(
+                    cmd,
+                    width.div_ceil(PYRAMID_GROUP_SIZE),
+                    height.div_ceil(PYRAMID_GROUP_SIZE),
+                    1,
+                );
+            }
+            // Each level reads the one before it, which the graph does not
+            // track on its own.
+            if level + 1 < pyramid.levels() {
+                memory_barrier(
+                    cmd,
+                    (
+                        vk::PipelineStageFlags2::COMPUTE_SHADER,
+                        vk::AccessFlags2::SHADER_STORAGE_WRITE,
+                    ),
+                    (
+                        vk::PipelineStageFlags2::COMPUTE_SHADER,
+                        vk::AccessFlags2::SHADER_SAMPLED_READ,
+                    ),
+                    device,
+                );
+            }
+        }
+    }
+
+    pub fn destroy(&mut self, device: &vulkanalia::Device) {
+        self.cull.destroy(device);
+        self.pyramid.destroy(device);
+        unsafe { device.destroy_descriptor_set_layout(self.level_layout, None) };
+    }
+}
@@ -0,0 +1,142 @@
+//! The hierarchical depth pyramid occlusion culling tests against.
+//!
+//! Each texel holds the farthest depth of the texels beneath it. Level 0 is
+//! the largest power of two that fits in the render extent, so every level
+//! halves the one above it exactly.
+
+use vulkanalia::vk::{self, DeviceV1_0, HasBuilder};
+
+use crate::render::allocations::{self, AllocatedImage};
+use crate::render::bindless::{self, Bindless};
+use crate::render::graph::{Access, Graph, ImageId, ImportedImage};
+
+const FORMAT: vk::Format = vk::Format::R32_SFLOAT;
+
+pub struct Pyramid {
+    image: AllocatedImage,
+    /// One view per level for the reduction to write through.
+    level_views: Vec<vk::ImageView>,
+    /// Reads take the maximum of the texels they filter rather than a blend.
+    sampler: vk::Sampler,
+    pub texture: bindless::Handle<bindless::SampledImage>,
+    pub sampler_handle: bindless::Handle<bindless::Sampler>,
+    pub extent: vk::Extent2D,
+}
+
+impl Pyramid {
+    pub fn new(
+        render_extent: vk::Extent2D,
+        bindless: &mut Bindless,
+        allocator: &vulkanalia_vma::Allocator,
+        device: &vulkanalia::Device,
+    ) -> Self {
+        let extent = vk::Extent2D {
+            width: 1 << render_extent.width.max(1).ilog2(),
+            height: 1 << render_extent.height.max(1).ilog2(),
+        };
+        let levels = allocations::mip_levels(extent.width, extent.height);
+        let image = AllocatedImage::with_levels(
+            FORMAT,
+            vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+            vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            },
+            vk::ImageAspectFlags::COLOR,
+            levels,
+            1,
+            vk::ImageViewType::_2D,
+            allocator,
+            device,
+        );
+
+        let level_views = (0..levels)
+            .map(|level| {
+                let info = vk::ImageViewCreateInfo::builder()
+                    .view_type(vk::ImageViewType::_2D)
+                    .image(image.image)
+                    .format(FORMAT)
+                    .subresource_range(
+                        vk::ImageSubresourceRange::builder()
+                            .aspect_mask(vk::ImageAspectFlags::COLOR)
+                            .base_mip_level(level)
+                            .level_count(1)
+                            .base_array_layer(0)
+                            .layer_count(1),
+                    );
+                unsafe { device.create_image_view(&info, None) }.unwrap()
+            })
+            .collect();
+
+        let mut reduction = vk::SamplerReductionModeCreateInfo::builder()
+            .reduction_mode(vk::SamplerReductionMode::MAX);
+        let sampler_info = vk::SamplerCreateInfo::builder()
+            .mag_filter(vk::Filter::LINEAR)
+            .min_filter(vk::Filter::LINEAR)
+            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .min_lod(0.)
+            .max_lod(levels as f32)
+            .push_next(&mut reduction);
+        let sampler = unsafe { device.create_sampler(&sampler_info, None) }.unwrap();
+
+        Self {
+            // The pyramid stays in `GENERAL` so levels can be read while
+            // others are written.
+            texture: bindless.add_sampled_image(device, image.view, vk::ImageLayout::GENERAL),
+            sampler_handle: bindless.add_sampler(device, sampler),
+            image,
+            level_views,
+            sampler,
+            extent,
+        }
+    }
+
+    /// Bring the pyramid into `graph`, as last written by the previous
+    /// frame's reduction. With `discard` it holds nothing worth keeping.
+    pub fn import(&self, graph: &mut Graph, discard: bool) -> ImageId {
+        graph.import_image(ImportedImage {
+            previous: Access::ComputeStorageWrite,
+            discard,
+            general: true,
+            ..ImportedImage::new(
+                self.image.image,
+                self.image.view,
+                self.extent,
+                vk::ImageAspectFlags::COLOR,
+            )
+        })
+    }
+
+    pub const fn levels(&self) -> u32 {
+        self.image.mip_levels
+    }
+
+    pub fn level_views(&self) -> &[vk::ImageView] {
+        &self.level_views
+    }
+
+    pub fn allocated_bytes(&self, allocator: &vulkanalia_vma::Allocator) -> u64 {
+        self.image.size(allocator)
+    }
+
+    pub fn destroy(
+        &mut self,
+        bindless: &mut Bindless,
+        allocator: &vulkanalia_vma::Allocator,
+        device: &vulkanalia::Device,
+    ) {
+        bindless.remove_sampled_image(self.texture);
+        bindless.remove_sampler(self.sampler_handle);
+        unsafe {
+            for view in self.level_views.drain(..) {
+                device.destroy_image_view(view, None);
+            }
+            device.destroy_sampler(self.sampler, None);
+        }
+        self.image.flush(device, allocator);
+    }
+}
@@ -18,10 +18,6 @@ impl<'a> DescriptorLayoutBuilder<'a> {
         );
     }
 
-    pub fn clear(&mut self) {
-        self.bindings.clear();
-    }
-
     pub fn build(
         &mut self,
         device: &vulkanalia::Device,
@@ -39,3 +35,60 @@ impl<'a> DescriptorLayoutBuilder<'a> {
         unsafe { device.create_descriptor_set_layout(&info, None) }.unwrap()
     }
 }
+
+/// Batches descriptor writes so a whole set is updated with one call.
+#[derive(Default)]
+pub struct DescriptorWriter {
+    image_infos: Vec<vk::DescriptorImageInfo>,
+    /// Binding, type and index into `image_infos` of each write.
+    writes: Vec<(u32, vk::DescriptorType, usize)>,
+}
+
+impl DescriptorWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn write_image(
+        &mut self,
+        binding: u32,
+        view: vk::ImageView,
+        sampler: vk::Sampler,
+        layout: vk::ImageLayout,
+        descriptor_type: vk::DescriptorType,
+    ) -> &mut Self {
+        self.image_infos.push(
+            vk::DescriptorImageInfo::builder()
+                .image_view(view)
+                .sampler(sampler)
+                .image_layout(layout)
+                .build(),
+        );
+        self.writes
+            .push((binding, descriptor_type, self.image_infos.len() - 1));
+        self
+    }
+
+    pub fn clear(&mut self) {
+        self.image_infos.clear();
+        self.writes.clear();
+    }
+
+    /// Apply every queued write to `set`. The writer keeps its writes, so the
+    /// same bindings can be applied to several sets.
+    pub fn update_set(&self, device: &vulkanalia::Device, set: vk::DescriptorSet) {
+        let writes: Vec<_> = self
+            .writes
+            .iter()
+            .map(|&(binding, descriptor_type, i)| {
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(set)
+                    .dst_binding(binding)
+                    .descriptor_type(descriptor_type)
+                    .image_info(std::slice::from_ref(&self.image_infos[i]))
+            })
+            .collect();
+
+        unsafe { device.update_descriptor_sets(&writes, &[] as &[vk::CopyDescriptorSet]) };
+    }
+}
@@ -0,0 +1,195 @@
+use rootcause::Report;
+use tracy_client::{GpuContext, GpuContextType, GpuSpan};
+use vulkanalia::vk::{self, DeviceV1_0, DeviceV1_3, HasBuilder, InstanceV1_0};
+
+use super::timeline::Timeline;
+use super::utils::immediate_submit;
+
+/// Timestamp queries available to each frame, two per zone.
+const MAX_QUERIES: u32 = 64;
+
+/// Sends GPU zones to Tracy using timestamp queries written into the frame's
+/// command buffer. Results are read back once the GPU has finished the frame,
+/// so they show up in Tracy two frames late.
+///
+/// Zones recorded into one frame must not overlap.
+pub struct GpuProfiler {
+    context: GpuContext,
+    pools: [vk::QueryPool; 2],
+    spans: [Vec<(GpuSpan, u32)>; 2],
+    next_query: [u32; 2],
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Zone(usize);
+
+impl GpuProfiler {
+    pub fn new(
+        instance: &vulkanalia::Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &vulkanalia::Device,
+        queue: vk::Queue,
+        timeline: &mut Timeline,
+    ) -> Result<Self, Report> {
+        let period = unsafe { instance.get_physical_device_properties(physical_device) }
+            .limits
+            .timestamp_period;
+
+        let info = vk::QueryPoolCreateInfo::builder()
+            .query_type(vk::QueryType::TIMESTAMP)
+            .query_count(MAX_QUERIES);
+        let first = unsafe { device.create_query_pool(&info, None) }?;
+        let pools = match unsafe { device.create_query_pool(&info, None) } {
+            Ok(second) => [first, second],
+            Err(e) => {
+                unsafe { device.destroy_query_pool(first, None) };
+                return Err(e.into());
+            }
+        };
+
+        match Self::context(device, queue, timeline, pools[0], period) {
+            Ok(context) => Ok(Self {
+                context,
+                pools,
+                spans: [vec![], vec![]],
+                next_query: [0; 2],
+            }),
+            Err(e) => {
+                for pool in pools {
+                    unsafe { device.destroy_query_pool(pool, None) };
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Tracy needs one GPU timestamp taken "now" to line the two clocks up,
+    /// which is written into the first query of `pool`.
+    fn context(
+        device: &vulkanalia::Device,
+        queue: vk::Queue,
+        timeline: &mut Timeline,
+        pool: vk::QueryPool,
+        period: f32,
+    ) -> Result<GpuContext, Report> {
+        immediate_submit(device, queue, timeline, |cmd| unsafe {
+            device.cmd_reset_query_pool(cmd, pool, 0, 1);
+            device.cmd_write_timestamp2(cmd, vk::PipelineStageFlags2::BOTTOM_OF_PIPE, pool, 0);
+        });
+
+        let mut timestamp = [0u64];
+        unsafe {
+            device.get_query_pool_results(
+                pool,
+                0,
+                1,
+                bytemuck::cast_slice_mut(&mut timestamp),
+                size_of::<u64>() as u64,
+                vk::QueryResultFlags::_64 | vk::QueryResultFlags::WAIT,
+            )
+        }?;
+
+        Ok(tracy_client::Client::start().new_gpu_context(
+            Some("Graphics Queue"),
+            GpuContextType::Vulkan,
+            timestamp[0] as i64,
+            period,
+        )?)
+    }
+
+    /// Upload the finished zones of the frame that last used this slot, then
+    /// reset its queries. Call once the GPU has finished that frame.
+    pub fn begin_frame(
+        &mut self,
+        device: &vulkanalia::Device,
+        cmd: vk::CommandBuffer,
+        frame: usize,
+    ) {
+        let used = self.next_query[frame];
+        if used > 0 {
+            // Each query's timestamp followed by whether it has been written.
+            let mut results = vec![[0u64; 2]; used as usize];
+            let status = unsafe {
+                device.get_query_pool_results(
+                    self.pools[frame],
+                    0,
+                    used,
+                    bytemuck::cast_slice_mut(&mut results),
+                    size_of::<[u64; 2]>() as u64,
+                    vk::QueryResultFlags::_64 | vk::QueryResultFlags::WITH_AVAILABILITY,
+                )
+            };
+            // Dropping a span without timestamps closes it without timing data.
+            if status.is_ok() {
+                for (span, query) in &self.spans[frame] {
+                    let [start, start_available] = results[*query as usize];
+                    let [end, end_available] = results[*query as usize + 1];
+                    if start_available != 0 && end_available != 0 {
+                        span.upload_timestamp_start(start as i64);
+                        span.upload_timestamp_end(end as i64);
+                    }
+                }
+            }
+        }
+        self.spans[frame].clear();
+        self.next_query[frame] = 0;
+
+        unsafe { device.cmd_reset_query_pool(cmd, self.pools[frame], 0, MAX_QUERIES) };
+    }
+
+    /// Open a zone named `name`. Returns `None` once the frame has run out of
+    /// queries.
+    pub fn begin_zone(
+        &mut self,
+        device: &vulkanalia::Device,
+        cmd: vk::CommandBuffer,
+        frame: usize,
+        name: &str,
+    ) -> Option<Zone> {
+        let query = self.next_query[frame];
+        if query + 2 > MAX_QUERIES {
+            return None;
+        }
+        let span = self.context.span_alloc(name, "", file!(), 0).ok()?;
+        unsafe {
+            device.cmd_write_timestamp2(
+                cmd,
+                vk::PipelineStageFlags2::TOP_OF_PIPE,
+                self.pools[frame],
+                query,
+            );
+        }
+        self.next_query[frame] += 2;
+        self.spans[frame].push((span, query));
+        Some(Zone(self.spans[frame].len() - 1))
+    }
+
+    pub fn end_zone(
+        &mut self,
+        device: &vulkanalia::Device,
+        cmd: vk::CommandBuffer,
+        frame: usize,
+        zone: Option<Zone>,
+    ) {
+        let Some(Zone(index)) = zone else {
+            return;
+        };
+        let (span, query) = &mut self.spans[frame][index];
+        unsafe {
+            device.cmd_write_timestamp2(
+                cmd,
+                vk::PipelineStageFlags2::BOTTOM_OF_PIPE,
+                self.pools[frame],
+                *query + 1,
+            );
+        }
+        span.end_zone();
+    }
+
+    pub fn destroy(&mut self, device: &vulkanalia::Device) {
+        self.spans.iter_mut().for_each(Vec::clear);
+        for pool in self.pools {
+            unsafe { device.destroy_query_pool(pool, None) };
+        }
+    }
+}
@@ -0,0 +1,496 @@
+//! A frame graph: passes declare the images and buffers they use, and the
+//! graph records the barriers and layout transitions between them.
+//!
+//! Passes run in the order they are added. A barrier is only recorded where
+//! one access has to wait for another, and image transitions are folded into
+//! it. Passes with attachments have rendering begun and ended for them, with
+//! the viewport and scissor covering the render area.
+//!
+//! Resources are tracked whole. A pass that writes part of an image and reads
+//! another part of it, like the depth pyramid, orders that itself.
+
+use vulkanalia::vk::{self, DeviceV1_0, DeviceV1_3, HasBuilder};
+
+use super::bindless::{self, Bindless};
+#[cfg(feature = "tracy")]
+use super::gpu_profiler::GpuProfiler;
+
+mod access;
+mod transients;
+
+pub use access::Access;
+use access::State;
+pub use transients::{ImageDesc, Transients};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageId(usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferId(usize);
+
+/// An image owned outside the graph, such as the swapchain or the shadow
+/// map. Start from [`ImportedImage::new`] and change what differs.
+#[derive(Debug, Clone, Copy)]
+pub struct ImportedImage {
+    pub image: vk::Image,
+    /// The view attachments render through.
+    pub view: vk::ImageView,
+    pub extent: vk::Extent2D,
+    pub aspect: vk::ImageAspectFlags,
+    pub base_layer: u32,
+    pub layer_count: u32,
+    /// How the image was last used, which the first pass waits on.
+    pub previous: Access,
+    /// Whether the contents can be thrown away rather than kept in the
+    /// layout `previous` left them in.
+    pub discard: bool,
+    /// Whether the image stays in `GENERAL` for every access.
+    pub general: bool,
+}
+
+impl ImportedImage {
+    /// One layer, unused until now.
+    pub const fn new(
+        image: vk::Image,
+        view: vk::ImageView,
+        extent: vk::Extent2D,
+        aspect: vk::ImageAspectFlags,
+    ) -> Self {
+        Self {
+            image,
+            view,
+            extent,
+            aspect,
+            base_layer: 0,
+            layer_count: 1,
+            previous: Access::None,
+            discard: false,
+            general: false,
+        }
+    }
+}
+
+/// What a pass does with an attachment's contents before rendering.
+#[derive(Debug, Clone, Copy)]
+pub enum Load {
+    Keep,
+    Clear(vk::ClearValue),
+    /// The pass overwrites every pixel it renders.
+    DontCare,
+}
+
+struct Image {
+    imported: ImportedImage,
+    state: State,
+    /// Slot in the transient pool, for images the graph created.
+    transient: Option<usize>,
+    sampled: Option<bindless::Handle<bindless::SampledImage>>,
+    export: Option<Access>,
+}
+
+struct Buffer {
+    buffer: vk::Buffer,
+    state: State,
+    export: Option<Access>,
+}
+
+type Record<'a> = Box<dyn FnOnce(&vulkanalia::Device, vk::CommandBuffer) + 'a>;
+
+struct Pass<'a> {
+    #[cfg(feature = "tracy")]
+    name: &'static str,
+    colors: Vec<(ImageId, Load)>,
+    depth: Option<(ImageId, Load)>,
+    render_area: Option<vk::Extent2D>,
+    images: Vec<(ImageId, Access)>,
+    buffers: Vec<(BufferId, Access)>,
+    record: Record<'a>,
+}
+
+/// One frame's passes and the resources they use. Build it, then
+/// [`Graph::execute`] it into the frame's command buffer.
+pub struct Graph<'a> {
+    transients: &'a mut Transients,
+    images: Vec<Image>,
+    buffers: Vec<Buffer>,
+    passes: Vec<Pass<'a>>,
+    #[cfg(feature = "tracy")]
+    profiler: Option<(&'a mut GpuProfiler, usize)>,
+}
+
+impl<'a> Graph<'a> {
+    pub fn new(transients: &'a mut Transients) -> Self {
+        Self {
+            transients,
+            images: vec![],
+            buffers: vec![],
+            passes: vec![],
+            #[cfg(feature = "tracy")]
+            profiler: None,
+        }
+    }
+
+    /// Time each pass as a GPU zone in `frame`'s slot of `profiler`.
+    #[cfg(feature = "tracy")]
+    pub fn profile(&mut self, profiler: &'a mut GpuProfiler, frame: usize) {
+        self.profiler = Some((profiler, frame));
+    }
+
+    /// A transient image from the pool, whose contents start out undefined
+    /// and are dropped after the frame. Create them all before adding passes
+    /// that borrow `bindless`.
+    pub fn create_image(
+        &mut self,
+        desc: ImageDesc,
+        bindless: &mut Bindless,
+        allocator: &vulkanalia_vma::Allocator,
+        device: &vulkanalia::Device,
+    ) -> ImageId {
+        let (slot, transient) = self.transients.acquire(desc, bindless, allocator, device);
+        let mut state = transient.state;
+        state.discard();
+        self.images.push(Image {
+            imported: ImportedImage::new(
+                transient.image,
+                transient.view,
+                desc.extent,
+                transient.aspect,
+            ),
+            state,
+            transient: Some(slot),
+            sampled: transient.sampled,
+            export: None,
+        });
+        ImageId(self.images.len() - 1)
+    }
+
+    pub fn import_image(&mut self, image: ImportedImage) -> ImageId {
+        let layout = if image.discard {
+            vk::ImageLayout::UNDEFINED
+        } else {
+            image.previous.layout(image.general)
+        };
+        self.images.push(Image {
+            imported: image,
+            state: State::new(image.previous, layout),
+            transient: None,
+            sampled: None,
+            export: None,
+        });
+        ImageId(self.images.len() - 1)
+    }
+
+    /// A buffer owned outside the graph, last used with `previous`.
+    pub fn import_buffer(&mut self, buffer: vk::Buffer, previous: Access) -> BufferId {
+        self.buffers.push(Buffer {
+            buffer,
+            state: State::new(previous, vk::ImageLayout::UNDEFINED),
+            export: None,
+        });
+        BufferId(self.buffers.len() - 1)
+    }
+
+    /// Leave an image ready for `access` once every pass has run.
+    pub fn export_image(&mut self, id: ImageId, access: Access) {
+        self.images[id.0].export = Some(access);
+    }
+
+    /// Make a buffer's last write visible to `access` once every pass has
+    /// run.
+    pub fn export_buffer(&mut self, id: BufferId, access: Access) {
+        self.buffers[id.0].export = Some(access);
+    }
+
+    /// The bindless handle sampling a transient image created with `SAMPLED`
+    /// usage.
+    pub fn sampled(&self, id: ImageId) -> bindless::Handle<bindless::SampledImage> {
+        self.images[id.0]
+            .sampled
+            .expect("transient image with SAMPLED usage")
+    }
+
+    /// Start declaring a pass named `name`, which is added once it is given
+    /// its commands with [`PassBuilder::record`]. The name labels the pass's
+    /// profiler zone.
+    #[cfg_attr(not(feature = "tracy"), allow(unused_variables))]
+    pub fn add_pass(&mut self, name: &'static str) -> PassBuilder<'_, 'a> {
+        PassBuilder {
+            graph: self,
+            colors: vec![],
+            depth: None,
+            render_area: None,
+            images: vec![],
+            buffers: vec![],
+            #[cfg(feature = "tracy")]
+            name,
+        }
+    }
+
+    /// Record every pass into `cmd` with the barriers between them, then the
+    /// barriers for exported resources. Transient images go back to the
+    /// pool.
+    pub fn execute(mut self, device: &vulkanalia::Device, cmd: vk::CommandBuffer) {
+        // The last pass to touch each image, so attachments nothing reads
+        // afterwards need not be stored.
+        let mut last_use = vec![None; self.images.len()];
+        for (index, pass) in self.passes.iter().enumerate() {
+            let attachments = pass.colors.iter().chain(&pass.depth).map(|(id, _)| id);
+            for id in attachments.chain(pass.images.iter().map(|(id, _)| id)) {
+                last_use[id.0] = Some(index);
+            }
+        }
+
+        let passes = std::mem::take(&mut self.passes);
+        for (index, pass) in passes.into_iter().enumerate() {
+            #[cfg(feature = "tracy")]
+            let zone = self
+                .profiler
+                .as_mut()
+                .and_then(|(profiler, frame)| profiler.begin_zone(device, cmd, *frame, pass.name));
+
+            let mut barriers = Barriers::default();
+            for &(id, load) in &pass.colors {
+                if !matches!(load, Load::Keep) {
+                    self.images[id.0].state.discard();
+                }
+                barriers.image(&mut self.images[id.0], Access::ColorAttachment);
+            }
+            if let Some((id, load)) = pass.depth {
+                if !matches!(load, Load::Keep) {
+                    self.images[id.0].state.discard();
+                }
+                barriers.image(&mut self.images[id.0], Access::DepthAttachment);
+            }
+            for &(id, access) in &pass.images {
+                barriers.image(&mut self.images[id.0], access);
+            }
+            for &(id, access) in &pass.buffers {
+                barriers.buffer(&mut self.buffers[id.0], access);
+            }
+            barriers.record(device, cmd);
+
+            let rendering = !pass.colors.is_empty() || pass.depth.is_some();
+            if rendering {
+                let attachment = |(id, load): (ImageId, Load), access: Access| {
+                    let image = &self.images[id.0];
+                    let discard = image.transient.is_some()
+                        && image.export.is_none()
+                        && last_use[id.0] == Some(index);
+                    let info = vk::RenderingAttachmentInfo::builder()
+                        .image_view(image.imported.view)
+                        .image_layout(access.layout(image.imported.general))
+                        .store_op(if discard {
+                            vk::AttachmentStoreOp::DONT_CARE
+                        } else {
+                            vk::AttachmentStoreOp::STORE
+                        });
+                    match load {
+                        Load::Keep => info.load_op(vk::AttachmentLoadOp::LOAD),
+                        Load::Clear(value) => {
+                            info.load_op(vk::AttachmentLoadOp::CLEAR).clear_value(value)
+                        }
+                        Load::DontCare => info.load_op(vk::AttachmentLoadOp::DONT_CARE),
+                    }
+                };
+                let colors: Vec<_> = pass
+                    .colors
+                    .iter()
+                    .map(|&color| attachment(color, Access::ColorAttachment))
+                    .collect();
+                let depth = pass
+                    .depth
+                    .map(|depth| attachment(depth, Access::DepthAttachment));
+
+                let extent = pass.render_area.unwrap_or_else(|| {
+                    let (first, _) = pass
+                        .colors
+                        .first()
+                        .or(pass.depth.as_ref())
+                        .expect("pass with attachments");
+                    self.images[first.0].imported.extent
+                });
+                let area = vk::Rect2D::builder()
+                    .extent(extent)
+                    .offset(vk::Offset2D { x: 0, y: 0 });
+                let mut info = vk::RenderingInfo::builder()
+                    .render_area(area)
+                    .color_attachments(&colors)
+                    .layer_count(1);
+                if let Some(depth) = &depth {
+                    info = info.depth_attachment(depth);
+                }
+                unsafe {
+                    device.cmd_begin_rendering(cmd, &info);
+                    device.cmd_set_viewport(
+                        cmd,
+                        0,
+                        &[vk::Viewport {
+                            width: extent.width as f32,
+                            height: extent.height as f32,
+                            x: 0.,
+                            y: 0.,
+                            min_depth: 0.,
+                            max_depth: 1.,
+                        }],
+                    );
+                    device.cmd_set_scissor(cmd, 0, &[area]);
+                }
+            }
+
+            (pass.record)(device, cmd);
+
+            if rendering {
+                unsafe { device.cmd_end_rendering(cmd) };
+            }
+
+            #[cfg(feature = "tracy")]
+            if let Some((profiler, frame)) = self.profiler.as_mut() {
+                profiler.end_zone(device, cmd, *frame, zone);
+            }
+        }
+
+        let mut barriers = Barriers::default();
+        for image in &mut self.images {
+            if let Some(access) = image.export {
+                barriers.image(image, access);
+            }
+        }
+        for buffer in &mut self.buffers {
+            if let Some(access) = buffer.export {
+                barriers.buffer(buffer, access);
+            }
+        }
+        barriers.record(device, cmd);
+
+        for image in &self.images {
+            if let Some(slot) = image.transient {
+                self.transients.release(slot, image.state);
+            }
+        }
+    }
+}
+
+/// Declares what one pass uses. The pass joins the graph when
+/// [`PassBuilder::record`] is called.
+pub struct PassBuilder<'g, 'a> {
+    graph: &'g mut Graph<'a>,
+    #[cfg(feature = "tracy")]
+    name: &'static str,
+    colors: Vec<(ImageId, Load)>,
+    depth: Option<(ImageId, Load)>,
+    render_area: Option<vk::Extent2D>,
+    images: Vec<(ImageId, Access)>,
+    buffers: Vec<(BufferId, Access)>,
+}
+
+impl<'a> PassBuilder<'_, 'a> {
+    pub fn color_attachment(mut self, id: ImageId, load: Load) -> Self {
+        self.colors.push((id, load));
+        self
+    }
+
+    pub fn depth_attachment(mut self, id: ImageId, load: Load) -> Self {
+        self.depth = Some((id, load));
+        self
+    }
+
+    /// Render to only the top left `extent` of the attachments, rather than
+    /// all of the first one.
+    pub fn render_area(mut self, extent: vk::Extent2D) -> Self {
+        self.render_area = Some(extent);
+        self
+    }
+
+    /// Use an image other than as an attachment.
+    pub fn image(mut self, id: ImageId, access: Access) -> Self {
+        self.images.push((id, access));
+        self
+    }
+
+    pub fn buffer(mut self, id: BufferId, access: Access) -> Self {
+        self.buffers.push((id, access));
+        self
+    }
+
+    /// Add the pass, with `record` recording its commands. Rendering has
+    /// already begun for passes with attachments.
+    pub fn record(self, record: impl FnOnce(&vulkanalia::Device, vk::CommandBuffer) + 'a) {
+        self.graph.passes.push(Pass {
+            #[cfg(feature = "tracy")]
+            name: self.name,
+            colors: self.colors,
+            depth: self.depth,
+            render_area: self.render_area,
+            images: self.images,
+            buffers: self.buffers,
+            record: Box::new(record),
+        });
+    }
+}
+
+/// The barriers recorded together before one pass.
+#[derive(Default)]
+struct Barriers {
+    images: Vec<vk::ImageMemoryBarrier2>,
+    buffers: Vec<vk::BufferMemoryBarrier2>,
+}
+
+impl Barriers {
+    fn image(&mut self, image: &mut Image, access: Access) {
+        let layout = access.layout(image.imported.general);
+        let Some(barrier) = image.state.access(access, layout) else {
+            return;
+        };
+        let imported = &image.imported;
+        self.images.push(
+            vk::ImageMemoryBarrier2::builder()
+                .src_stage_mask(barrier.src_stages)
+                .src_access_mask(barrier.src_access)
+                .dst_stage_mask(barrier.dst_stages)
+                .dst_access_mask(barrier.dst_access)
+                .old_layout(barrier.old_layout)
+                .new_layout(barrier.new_layout)
+                .image(imported.image)
+                .subresource_range(
+                    vk::ImageSubresourceRange::builder()
+                        .aspect_mask(imported.aspect)
+                        .base_mip_level(0)
+                        .level_count(vk::REMAINING_MIP_LEVELS)
+                        .base_array_layer(imported.base_layer)
+                        .layer_count(imported.layer_count),
+                )
+                .build(),
+        );
+    }
+
+    fn buffer(&mut self, buffer: &mut Buffer, access: Access) {
+        let Some(barrier) = buffer.state.access(access, vk::ImageLayout::UNDEFINED) else {
+            return;
+        };
+        self.buffers.push(
+            vk::BufferMemoryBarrier2::builder()
+                .src_stage_mask(barrier.src_stages)
+                .src_access_mask(barrier.src_access)
+                .dst_stage_mask(barrier.dst_stages)
+                .dst_access_mask(barrier.dst_access)
+                .buffer(buffer.buffer)
+                .offset(0)
+                .size(vk::WHOLE_SIZE)
+                .build(),
+        );
+    }
+
+    fn record(self, device: &vulkanalia::Device, cmd: vk::CommandBuffer) {
+        if self.images.is_empty() && self.buffers.is_empty() {
+            return;
+        }
+        unsafe {
+            device.cmd_pipeline_barrier2(
+                cmd,
+                &vk::DependencyInfo::builder()
+                    .buffer_memory_barriers(&self.buffers)
+                    .image_memory_barriers(&self.images),
+            );
+        }
+    }
+}
@@ -0,0 +1,323 @@
+//! How passes use resources, and the barriers between one use and the next.
+
+use vulkanalia::vk;
+
+/// How a pass uses an image or buffer. Each access implies the pipeline
+/// stages involved and, for images, the layout they need.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// No use at all. As the previous access of an imported resource it
+    /// means there is nothing to wait for.
+    None,
+    ColorAttachment,
+    DepthAttachment,
+    FragmentSampled,
+    ComputeSampled,
+    /// Storage writes from compute shaders, which may read as well.
+    ComputeStorageWrite,
+    IndirectRead,
+    TransferRead,
+    TransferWrite,
+    HostRead,
+    /// Presentation, which waits on and signals semaphores at the colour
+    /// attachment output stage.
+    Present,
+}
+
+impl Access {
+    pub const fn is_write(self) -> bool {
+        matches!(
+            self,
+            Self::ColorAttachment
+                | Self::DepthAttachment
+                | Self::ComputeStorageWrite
+                | Self::TransferWrite
+        )
+    }
+
+    fn stages(self) -> vk::PipelineStageFlags2 {
+        use vk::PipelineStageFlags2 as Stage;
+        match self {
+            Self::None => Stage::NONE,
+            Self::ColorAttachment | Self::Present => Stage::COLOR_ATTACHMENT_OUTPUT,
+            Self::DepthAttachment => Stage::EARLY_FRAGMENT_TESTS | Stage::LATE_FRAGMENT_TESTS,
+            Self::FragmentSampled => Stage::FRAGMENT_SHADER,
+            Self::ComputeSampled | Self::ComputeStorageWrite => Stage::COMPUTE_SHADER,
+            Self::IndirectRead => Stage::DRAW_INDIRECT,
+            Self::TransferRead | Self::TransferWrite => Stage::ALL_TRANSFER,
+            Self::HostRead => Stage::HOST,
+        }
+    }
+
+    fn access(self) -> vk::AccessFlags2 {
+        use vk::AccessFlags2 as Mask;
+        match self {
+            Self::None | Self::Present => Mask::NONE,
+            Self::ColorAttachment => Mask::COLOR_ATTACHMENT_READ | Mask::COLOR_ATTACHMENT_WRITE,
+            Self::DepthAttachment => {
+                Mask::DEPTH_STENCIL_ATTACHMENT_READ | Mask::DEPTH_STENCIL_ATTACHMENT_WRITE
+            }
+            Self::FragmentSampled | Self::ComputeSampled => Mask::SHADER_SAMPLED_READ,
+            Self::ComputeStorageWrite => Mask::SHADER_STORAGE_READ | Mask::SHADER_STORAGE_WRITE,
+            Self::IndirectRead => Mask::INDIRECT_COMMAND_READ,
+            Self::TransferRead => Mask::TRANSFER_READ,
+            Self::TransferWrite => Mask::TRANSFER_WRITE,
+            Self::HostRead => Mask::HOST_READ,
+        }
+    }
+
+    /// The layout an image needs for this access. Images kept in `GENERAL`
+    /// use that instead for everything but presentation.
+    pub fn layout(self, general: bool) -> vk::ImageLayout {
+        use vk::ImageLayout as Layout;
+        match self {
+            Self::None => Layout::UNDEFINED,
+            Self::Present => Layout::PRESENT_SRC_KHR,
+            _ if general => Layout::GENERAL,
+            Self::ColorAttachment => Layout::COLOR_ATTACHMENT_OPTIMAL,
+            Self::DepthAttachment => Layout::DEPTH_ATTACHMENT_OPTIMAL,
+            Self::FragmentSampled | Self::ComputeSampled => Layout::SHADER_READ_ONLY_OPTIMAL,
+            Self::TransferRead => Layout::TRANSFER_SRC_OPTIMAL,
+            Self::TransferWrite => Layout::TRANSFER_DST_OPTIMAL,
+            Self::ComputeStorageWrite | Self::IndirectRead | Self::HostRead => Layout::GENERAL,
+        }
+    }
+}
+
+/// A dependency from earlier accesses to the next one, with a layout
+/// transition for images when the layouts differ.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Barrier {
+    pub src_stages: vk::PipelineStageFlags2,
+    pub src_access: vk::AccessFlags2,
+    pub dst_stages: vk::PipelineStageFlags2,
+    pub dst_access: vk::AccessFlags2,
+    pub old_layout: vk::ImageLayout,
+    pub new_layout: vk::ImageLayout,
+}
+
+/// What has happened to a resource since it was last written, which is
+/// enough to tell what the next access has to wait for.
+///
+/// Visibility is tracked as the union of every stage and access the last
+/// write has been made visible to, so a read combining a stage and an access
+/// that were made visible separately does not get a barrier of its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct State {
+    /// Always `UNDEFINED` for buffers.
+    pub layout: vk::ImageLayout,
+    /// The last write, counting layout transitions.
+    write_stages: vk::PipelineStageFlags2,
+    write_access: vk::AccessFlags2,
+    /// Stages that have read since the last write.
+    read_stages: vk::PipelineStageFlags2,
+    /// Where the last write has been made visible.
+    visible_stages: vk::PipelineStageFlags2,
+    visible_access: vk::AccessFlags2,
+}
+
+impl State {
+    /// A resource in `layout` that was last used with `previous`.
+    pub fn new(previous: Access, layout: vk::ImageLayout) -> Self {
+        let none = Self {
+            layout,
+            write_stages: vk::PipelineStageFlags2::NONE,
+            write_access: vk::AccessFlags2::NONE,
+            read_stages: vk::PipelineStageFlags2::NONE,
+            visible_stages: vk::PipelineStageFlags2::NONE,
+            visible_access: vk::AccessFlags2::NONE,
+        };
+        if previous.is_write() {
+            Self {
+                write_stages: previous.stages(),
+                write_access: previous.access(),
+                ..none
+            }
+        } else {
+            Self {
+                read_stages: previous.stages(),
+                ..none
+            }
+        }
+    }
+
+    /// Drop the contents, so the next access transitions from `UNDEFINED`.
+    /// Earlier accesses are still waited on.
+    pub fn discard(&mut self) {
+        self.layout = vk::ImageLayout::UNDEFINED;
+    }
+
+    /// Record `access` with the resource in `layout`, returning the barrier
+    /// that has to come first, if any. Buffers pass their current layout.
+    pub fn access(&mut self, access: Access, layout: vk::ImageLayout) -> Option<Barrier> {
+        let stages = access.stages();
+        let mask = access.access();
+        let transition = layout != self.layout;
+
+        if access.is_write() || transition {
+            // Writes wait for earlier reads and writes, and so does a layout
+            // transition, which is a write of its own.
+            let barrier = Barrier {
+                src_stages: self.write_stages | self.read_stages,
+                src_access: self.write_access,
+                dst_stages: stages,
+                dst_access: mask,
+                old_layout: self.layout,
+                new_layout: layout,
+            };
+            let needed = transition || !barrier.src_stages.is_empty();
+            *self = if access.is_write() {
+                Self {
+                    write_stages: stages,
+                    write_access: mask,
+                    ..Self::new(Access::None, layout)
+                }
+            } else {
+                // The barrier made the transition visible to this read.
+                Self {
+                    layout,
+                    write_stages: stages,
+                    write_access: vk::AccessFlags2::NONE,
+                    read_stages: stages,
+                    visible_stages: stages,
+                    visible_access: mask,
+                }
+            };
+            return needed.then_some(barrier);
+        }
+
+        self.read_stages |= stages;
+        let visible = self.visible_stages.contains(stages) && self.visible_access.contains(mask);
+        if self.write_stages.is_empty() || visible {
+            return None;
+        }
+        self.visible_stages |= stages;
+        self.visible_access |= mask;
+        Some(Barrier {
+            src_stages: self.write_stages,
+            src_access: self.write_access,
+            dst_stages: stages,
+            dst_access: mask,
+            old_layout: layout,
+            new_layout: layout,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use vk::AccessFlags2 as Mask;
+    use vk::ImageLayout as Layout;
+    use vk::PipelineStageFlags2 as Stage;
+
+    /// A buffer, whose layout never changes.
+    fn buffer(previous: Access) -> State {
+        State::new(previous, Layout::UNDEFINED)
+    }
+
+    #[test]
+    fn reads_wait_for_the_last_write() {
+        let mut state = buffer(Access::None);
+        assert_eq!(
+            state.access(Access::ComputeStorageWrite, Layout::UNDEFINED),
+            None
+        );
+        assert_eq!(
+            state.access(Access::IndirectRead, Layout::UNDEFINED),
+            Some(Barrier {
+                src_stages: Stage::COMPUTE_SHADER,
+                src_access: Mask::SHADER_STORAGE_READ | Mask::SHADER_STORAGE_WRITE,
+                dst_stages: Stage::DRAW_INDIRECT,
+                dst_access: Mask::INDIRECT_COMMAND_READ,
+                old_layout: Layout::UNDEFINED,
+                new_layout: Layout::UNDEFINED,
+            })
+        );
+    }
+
+    #[test]
+    fn writes_wait_for_earlier_reads() {
+        let mut state = buffer(Access::IndirectRead);
+        assert_eq!(
+            state.access(Access::TransferWrite, Layout::UNDEFINED),
+            Some(Barrier {
+                src_stages: Stage::DRAW_INDIRECT,
+                src_access: Mask::NONE,
+                dst_stages: Stage::ALL_TRANSFER,
+                dst_access: Mask::TRANSFER_WRITE,
+                old_layout: Layout::UNDEFINED,
+                new_layout: Layout::UNDEFINED,
+            })
+        );
+    }
+
+    #[test]
+    fn layout_changes_transition() {
+        let mut state = State::new(Access::None, Layout::UNDEFINED);
+        let layout = Access::ColorAttachment.layout(false);
+        let barrier = state
+            .access(Access::ColorAttachment, layout)
+            .expect("transition from undefined");
+        assert_eq!(barrier.old_layout, Layout::UNDEFINED);
+        assert_eq!(barrier.new_layout, Layout::COLOR_ATTACHMENT_OPTIMAL);
+        assert_eq!(barrier.src_stages, Stage::NONE);
+
+        let layout = Access::FragmentSampled.layout(false);
+        assert_eq!(
+            state.access(Access::FragmentSampled, layout),
+            Some(Barrier {
+                src_stages: Stage::COLOR_ATTACHMENT_OUTPUT,
+                src_access: Mask::COLOR_ATTACHMENT_READ | Mask::COLOR_ATTACHMENT_WRITE,
+                dst_stages: Stage::FRAGMENT_SHADER,
+                dst_access: Mask::SHADER_SAMPLED_READ,
+                old_layout: Layout::COLOR_ATTACHMENT_OPTIMAL,
+                new_layout: Layout::SHADER_READ_ONLY_OPTIMAL,
+            })
+        );
+        assert_eq!(state.layout, Layout::SHADER_READ_ONLY_OPTIMAL);
+    }
+
+    #[test]
+    fn discarded_images_transition_from_undefined() {
+        let mut state = State::new(Access::FragmentSampled, Layout::SHADER_READ_ONLY_OPTIMAL);
+        state.discard();
+        let barrier = state
+            .access(Access::ColorAttachment, Layout::COLOR_ATTACHMENT_OPTIMAL)
+            .expect("transition");
+        assert_eq!(barrier.old_layout, Layout::UNDEFINED);
+        assert_eq!(barrier.src_stages, Stage::FRAGMENT_SHADER);
+    }
+
+    #[test]
+    fn repeated_reads_need_no_barrier() {
+        let mut state = buffer(Access::TransferWrite);
+        assert!(state.access(Access::HostRead, Layout::UNDEFINED).is_some());
+        assert_eq!(state.access(Access::HostRead, Layout::UNDEFINED), None);
+
+        let layout = Access::FragmentSampled.layout(false);
+        let mut state = State::new(Access::None, Layout::UNDEFINED);
+        state.access(
+            Access::DepthAttachment,
+            Access::DepthAttachment.layout(false),
+        );
+        assert!(state.access(Access::FragmentSampled, layout).is_some());
+        assert_eq!(state.access(Access::FragmentSampled, layout), None);
+    }
+
+    #[test]
+    fn reads_in_new_stages_wait_for_the_write_again() {
+        let mut state = buffer(Access::ComputeStorageWrite);
+        assert!(
+            state
+                .access(Access::IndirectRead, Layout::UNDEFINED)
+                .is_some()
+        );
+        let barrier = state
+            .access(Access::TransferRead, Layout::UNDEFINED)
+            .expect("transfer is not covered by the indirect barrier");
+        assert_eq!(barrier.src_stages, Stage::COMPUTE_SHADER);
+        assert_eq!(barrier.dst_stages, Stage::ALL_TRANSFER);
+    }
+}
@@ -0,0 +1,202 @@
+//! Images whose contents only matter within a frame, kept from one frame to
+//! the next so they are not reallocated every time.
+
+#[cfg(feature = "logging")]
+use piglog::debug;
+#[cfg(feature = "logging")]
+use piglog::prelude::*;
+use vulkanalia::vk;
+
+use super::access::{Access, State};
+use crate::render::allocations::AllocatedImage;
+use crate::render::bindless::{self, Bindless};
+
+/// What a transient image has to be. Images are reused for any request with
+/// an equal description.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageDesc {
+    pub format: vk::Format,
+    pub extent: vk::Extent2D,
+    pub usage: vk::ImageUsageFlags,
+}
+
+impl ImageDesc {
+    fn aspect(&self) -> vk::ImageAspectFlags {
+        match self.format {
+            vk::Format::D16_UNORM | vk::Format::X8_D24_UNORM_PACK32 | vk::Format::D32_SFLOAT => {
+                vk::ImageAspectFlags::DEPTH
+            }
+            vk::Format::D16_UNORM_S8_UINT
+            | vk::Format::D24_UNORM_S8_UINT
+            | vk::Format::D32_SFLOAT_S8_UINT => {
+                vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+            }
+            _ => vk::ImageAspectFlags::COLOR,
+        }
+    }
+}
+
+/// A transient image as a frame graph sees it.
+#[derive(Debug, Clone, Copy)]
+pub struct Transient {
+    pub image: vk::Image,
+    pub view: vk::ImageView,
+    pub aspect: vk::ImageAspectFlags,
+    /// Registered for images with `SAMPLED` usage, reading them in
+    /// `SHADER_READ_ONLY_OPTIMAL`.
+    pub sampled: Option<bindless::Handle<bindless::SampledImage>>,
+    /// How the last frame to use the image left it.
+    pub state: State,
+}
+
+struct Entry {
+    desc: ImageDesc,
+    image: AllocatedImage,
+    transient: Transient,
+    last_used: u64,
+    in_use: bool,
+}
+
+/// The pool transient images come from.
+#[derive(Default)]
+pub struct Transients {
+    entries: Vec<Entry>,
+    frame: u64,
+}
+
+impl Transients {
+    /// Destroy images the previous frame did not ask for once the GPU has
+    /// finished the last frame that did, which is one before `finished`.
+    pub fn begin_frame(
+        &mut self,
+        frame: u64,
+        finished: u64,
+        bindless: &mut Bindless,
+        allocator: &vulkanalia_vma::Allocator,
+        device: &vulkanalia::Device,
+    ) {
+        self.frame = frame;
+        self.entries.retain_mut(|entry| {
+            let stale = entry.last_used + 1 < frame && entry.last_used < finished;
+            if stale {
+                entry.destroy(bindless, allocator, device);
+            }
+            !stale
+        });
+    }
+
+    /// An image matching `desc` that nothing else has this frame, created if
+    /// the pool has none. Returns its slot for [`Transients::release`].
+    pub fn acquire(
+        &mut self,
+        desc: ImageDesc,
+        bindless: &mut Bindless,
+        allocator: &vulkanalia_vma::Allocator,
+        device: &vulkanalia::Device,
+    ) -> (usize, Transient) {
+        let slot = match self
+            .entries
+            .iter()
+            .position(|entry| entry.desc == desc && !entry.in_use)
+        {
+            Some(slot) => slot,
+            None => {
+                self.entries
+                    .push(Entry::new(desc, bindless, allocator, device));
+                self.entries.len() - 1
+            }
+        };
+        let entry = &mut self.entries[slot];
+        entry.in_use = true;
+        entry.last_used = self.frame;
+        (slot, entry.transient)
+    }
+
+    /// Hand back an image from [`Transients::acquire`] in `state` at the end
+    /// of the frame.
+    pub fn release(&mut self, slot: usize, state: State) {
+        let entry = &mut self.entries[slot];
+        entry.in_use = false;
+        entry.transient.state = state;
+    }
+
+    pub fn allocated_bytes(&self, allocator: &vulkanalia_vma::Allocator) -> u64 {
+        self.entries
+            .iter()
+            .map(|entry| entry.image.size(allocator))
+            .sum()
+    }
+
+    /// Expects the device to be idle.
+    pub fn destroy(
+        &mut self,
+        bindless: &mut Bindless,
+        allocator: &vulkanalia_vma::Allocator,
+        device: &vulkanalia::Device,
+    ) {
+        for mut entry in self.entries.drain(..) {
+            entry.destroy(bindless, allocator, device);
+        }
+    }
+}
+
+impl Entry {
+    fn new(
+        desc: ImageDesc,
+        bindless: &mut Bindless,
+        allocator: &vulkanalia_vma::Allocator,
+        device: &vulkanalia::Device,
+    ) -> Self {
+        let aspect = desc.aspect();
+        let image = AllocatedImage::new(
+            desc.format,
+            desc.usage,
+            vk::Extent3D {
+                width: desc.extent.width,
+                height: desc.extent.height,
+                depth: 1,
+            },
+            aspect,
+            allocator,
+            device,
+        );
+
+        #[cfg(feature = "logging")]
+        debug!(
+            "Allocated a {}x{} {:?} transient image",
+            desc.extent.width, desc.extent.height, desc.format
+        );
+
+        Self {
+            transient: Transient {
+                image: image.image,
+                view: image.view,
+                aspect,
+                sampled: desc.usage.contains(vk::ImageUsageFlags::SAMPLED).then(|| {
+                    bindless.add_sampled_image(
+                        device,
+                        image.view,
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    )
+                }),
+                state: State::new(Access::None, vk::ImageLayout::UNDEFINED),
+            },
+            desc,
+            image,
+            last_used: 0,
+            in_use: false,
+        }
+    }
+
+    fn destroy(
+        &mut self,
+        bindless: &mut Bindless,
+        allocator: &vulkanalia_vma::Allocator,
+        device: &vulkanalia::Device,
+    ) {
+        if let Some(handle) = self.transient.sampled {
+            bindless.remove_sampled_image(handle);
+        }
+        self.image.flush(device, allocator);
+    }
+}
@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use piglog::prelude::*;
+use piglog::{error, note};
+
+use super::shaders::{self, Shader};
+
+pub const SOURCE_DIR: &str = "./shaders";
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Polls the shader directories for changes.
+///
+/// Slang sources that change are recompiled with `slangc` if it is on the
+/// `PATH` into the [`shaders::override_dir`], which in turn changes the SPIR-V
+/// and is reported on a later poll.
+pub struct ShaderWatcher {
+    modified: HashMap<PathBuf, SystemTime>,
+    last_poll: Instant,
+}
+
+impl ShaderWatcher {
+    pub fn new() -> Self {
+        let mut watcher = Self {
+            modified: HashMap::new(),
+            last_poll: Instant::now(),
+        };
+        // Record the starting state so nothing counts as changed on the first poll.
+        watcher.scan(&spirv_dir(), "spv");
+        watcher.scan(Path::new(SOURCE_DIR), "slang");
+        watcher
+    }
+
+    /// SPIR-V files that changed since the last call.
+    pub fn poll(&mut self) -> Vec<PathBuf> {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return vec![];
+        }
+        self.last_poll = Instant::now();
+
+        let spirv_dir = spirv_dir();
+        for source in self.scan(Path::new(SOURCE_DIR), "slang") {
+            compile(&source, &spirv_dir);
+        }
+        self.scan(&spirv_dir, "spv")
+    }
+
+    /// Update the stored modification times for files in `dir` with the given
+    /// extension, returning the ones that are new or changed.
+    fn scan(&mut self, dir: &Path, extension: &str) -> Vec<PathBuf> {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return vec![];
+        };
+
+        let mut changed = vec![];
+        for path in entries.filter_map(|entry| entry.ok().map(|entry| entry.path())) {
+            if path.extension().is_none_or(|ext| ext != extension) {
+                continue;
+            }
+            let Ok(modified) = std::fs::metadata(&path).and_then(|meta| meta.modified()) else {
+                continue;
+            };
+            if self.modified.insert(path.clone(), modified) != Some(modified) {
+                changed.push(path);
+            }
+        }
+        changed
+    }
+}
+
+fn spirv_dir() -> PathBuf {
+    shaders::override_dir().unwrap_or_default()
+}
+
+fn compile(source: &Path, spirv_dir: &Path) {
+    let Some(stem) = source.file_stem() else {
+        return;
+    };
+    let output = spirv_dir.join(stem).with_extension("spv");
+    if let Err(e) = std::fs::create_dir_all(spirv_dir) {
+        error!("Could not create {}: {e}", spirv_dir.display());
+        return;
+    }
+
+    note!("Compiling {}", source.display());
+    let status = std::process::Command::new("slangc")
+        .arg(source)
+        .args(["-target", "spirv", "-fvk-use-entrypoint-name", "-o"])
+        .arg(&output)
+        .status();
+
+    match status {
+        Ok(status) if status.success() => {}
+        Ok(status) => error!("slangc failed on {} ({status})", source.display()),
+        Err(e) => error!("Could not run slangc: {e}"),
+    }
+}
+
+/// True if `changed` is the SPIR-V overriding `shader`.
+pub fn affects(changed: &Path, shader: &Shader) -> bool {
+    changed.file_stem().is_some_and(|stem| stem == shader.name)
+}
@@ -0,0 +1,187 @@
+//! How much memory the renderer holds against what the driver will give it.
+//!
+//! With `VK_EXT_memory_budget` the usage and budget of each heap come from
+//! the driver and count memory allocated outside VMA, such as the swapchain
+//! and pipelines. Without it VMA estimates usage from its own blocks and
+//! takes 80% of each heap as the budget.
+
+#[cfg(feature = "logging")]
+use piglog::prelude::*;
+use vulkanalia::vk;
+
+/// Share of a device local heap's budget past which chunk streaming should
+/// pull in the view distance.
+pub const SOFT_LIMIT: f64 = 0.85;
+/// The view distance, in chunks, memory pressure never pushes below.
+pub const MIN_VIEW_DISTANCE: u32 = 2;
+
+/// What an allocation is for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Category {
+    /// Chunk vertices, the shared quad index buffer and the culling buffers.
+    Chunks,
+    /// Block textures and lookup tables.
+    Textures,
+    /// Images sized by the render or display extent, and the shadow map.
+    RenderTargets,
+    /// Everything else VMA holds, such as uniform and staging buffers.
+    Other,
+}
+
+impl Category {
+    pub const ALL: [Self; 4] = [
+        Self::Chunks,
+        Self::Textures,
+        Self::RenderTargets,
+        Self::Other,
+    ];
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Chunks => "chunks",
+            Self::Textures => "textures",
+            Self::RenderTargets => "render targets",
+            Self::Other => "other",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Heap {
+    pub device_local: bool,
+    pub size: u64,
+    /// Bytes of the heap the process is using.
+    pub usage: u64,
+    /// Bytes of the heap the process can use before allocations start to
+    /// fail or get moved elsewhere.
+    pub budget: u64,
+    /// Bytes inside VMA allocations, which is part of `usage`.
+    pub allocated: u64,
+}
+
+/// Memory use as of the latest frame, for the debug overlay and chunk
+/// streaming.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Report {
+    /// Whether the heap budgets come from the driver rather than VMA's
+    /// estimate.
+    pub driver_budget: bool,
+    pub heaps: Vec<Heap>,
+    /// Bytes allocated for each of [`Category::ALL`].
+    pub categories: [u64; Category::ALL.len()],
+}
+
+impl Report {
+    pub const fn category(&self, category: Category) -> u64 {
+        self.categories[category as usize]
+    }
+
+    fn device_local(&self) -> impl Iterator<Item = &Heap> {
+        self.heaps
+            .iter()
+            .filter(|heap| heap.device_local && heap.budget > 0)
+    }
+
+    /// The highest usage of any device local heap as a share of its budget.
+    /// Past 1 the driver may start paging memory out.
+    pub fn pressure(&self) -> f32 {
+        self.device_local()
+            .map(|heap| heap.usage as f32 / heap.budget as f32)
+            .fold(0., f32::max)
+    }
+
+    /// The view distance chunk streaming should keep to, given that chunks
+    /// within `current` are loaded and it wants `max`.
+    ///
+    /// While every device local heap is under [`SOFT_LIMIT`] of its budget
+    /// the distance is `max`. Past that it is scaled so that the chunks'
+    /// memory would bring usage back to the limit, never below
+    /// [`MIN_VIEW_DISTANCE`].
+    pub fn view_distance(&self, current: u32, max: u32) -> u32 {
+        let chunks = self.category(Category::Chunks);
+        let Some(headroom) = self
+            .device_local()
+            .map(|heap| (heap.budget as f64 * SOFT_LIMIT) as i64 - heap.usage as i64)
+            .min()
+        else {
+            return max;
+        };
+        if headroom >= 0 || chunks == 0 {
+            return max;
+        }
+
+        // Loaded chunks grow with at least the square of the distance, so the
+        // square root of the memory ratio errs on the side of shrinking.
+        let ratio = (chunks as f64 + headroom as f64).max(0.) / chunks as f64;
+        let distance = (f64::from(current) * ratio.sqrt()).floor() as u32;
+        distance.clamp(MIN_VIEW_DISTANCE.min(max), max)
+    }
+}
+
+/// Queries the heap budgets each frame and keeps the latest [`Report`].
+pub struct Tracker {
+    report: Report,
+}
+
+impl Tracker {
+    /// `driver_budget` says whether the allocator was created with
+    /// `VK_EXT_memory_budget`.
+    pub fn new(driver_budget: bool) -> Self {
+        Self {
+            report: Report {
+                driver_budget,
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Refresh the report at the start of `frame`. `tagged` gives the bytes
+    /// known to belong to each category; whatever else VMA holds counts as
+    /// [`Category::Other`].
+    pub fn update(
+        &mut self,
+        frame: u64,
+        tagged: impl IntoIterator<Item = (Category, u64)>,
+        allocator: &vulkanalia_vma::Allocator,
+    ) {
+        // VMA refetches the driver's numbers when the frame index changes.
+        unsafe { allocator.set_current_frame_index(frame as u32) };
+        let budgets = allocator.get_heap_budgets().unwrap();
+        let heaps = &allocator.get_memory_properties().memory_heaps;
+
+        #[cfg(feature = "logging")]
+        let was_over = self.report.pressure() > 1.;
+        self.report.heaps = budgets
+            .iter()
+            .zip(heaps)
+            .map(|(budget, heap)| Heap {
+                device_local: heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL),
+                size: heap.size,
+                usage: budget.usage,
+                budget: budget.budget,
+                allocated: budget.statistics.allocationBytes,
+            })
+            .collect();
+
+        let mut categories = [0; Category::ALL.len()];
+        for (category, bytes) in tagged {
+            categories[category as usize] += bytes;
+        }
+        let allocated: u64 = self.report.heaps.iter().map(|heap| heap.allocated).sum();
+        categories[Category::Other as usize] =
+            allocated.saturating_sub(categories.iter().sum::<u64>());
+        self.report.categories = categories;
+
+        #[cfg(feature = "logging")]
+        if !was_over && self.report.pressure() > 1. {
+            piglog::warning!(
+                "Device memory over budget: {:.0}% in use",
+                self.report.pressure() * 100.
+            );
+        }
+    }
+
+    pub const fn report(&self) -> &Report {
+        &self.report
+    }
+}
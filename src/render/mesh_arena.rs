@@ -0,0 +1,346 @@
+//! Sub-allocation of mesh data out of a few large device local buffers.
+//!
+//! Each block is one buffer with a [`FreeList`] over it, in units of one
+//! element. Meshes get a [`Range`] in some block, so a draw needs only that
+//! block's buffer device address and the range's first element. Chunk meshes
+//! share one index buffer, so only their vertices live here.
+
+use bytemuck::NoUninit;
+#[cfg(feature = "logging")]
+use piglog::prelude::*;
+use vulkanalia::vk::{self, DeviceV1_0, DeviceV1_2, HasBuilder};
+
+use super::allocations::AllocatedBuffer;
+use super::timeline::Timeline;
+use super::utils::immediate_submit;
+use crate::profiling::zone;
+
+mod free_list;
+
+use free_list::FreeList;
+
+/// Part of a block given out by [`MeshArena::allocate`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Range {
+    pub block: u32,
+    /// First element of the range within the block.
+    pub offset: u32,
+    pub len: u32,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub blocks: u32,
+    /// Bytes of device memory held by the blocks.
+    pub capacity: u64,
+    /// Bytes inside allocated ranges.
+    pub used: u64,
+    pub allocations: u32,
+    /// Separate free ranges across all blocks. Many of them for the space
+    /// left means the arena is fragmented.
+    pub free_ranges: u32,
+    /// Bytes in the longest range that can still be allocated without a new
+    /// block.
+    pub largest_free: u64,
+    /// Bytes moved by [`MeshArena::defragment`] so far.
+    pub defragmented: u64,
+}
+
+struct Block {
+    buffer: AllocatedBuffer,
+    address: vk::DeviceAddress,
+    free: FreeList,
+    allocations: u32,
+}
+
+pub struct MeshArena {
+    blocks: Vec<Option<Block>>,
+    /// Bytes per element.
+    stride: u64,
+    /// Elements per block.
+    block_len: u32,
+    defragmented: u64,
+}
+
+impl MeshArena {
+    /// An arena of `T`s, growing `block_len` elements at a time. No memory is
+    /// allocated until the first range is.
+    pub const fn new<T>(block_len: u32) -> Self {
+        Self {
+            blocks: Vec::new(),
+            stride: size_of::<T>() as u64,
+            block_len,
+            defragmented: 0,
+        }
+    }
+
+    /// A range of `len` elements, in the first block with room for it. Adds a
+    /// block if none has.
+    pub fn allocate(
+        &mut self,
+        len: u32,
+        allocator: &vulkanalia_vma::Allocator,
+        device: &vulkanalia::Device,
+    ) -> Range {
+        assert!(len <= self.block_len, "mesh larger than an arena block");
+        if let Some(range) = self.allocate_except(len, None) {
+            return range;
+        }
+
+        let buffer = AllocatedBuffer::new(
+            allocator,
+            u64::from(self.block_len) * self.stride,
+            vk::BufferUsageFlags::STORAGE_BUFFER
+                | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
+                | vk::BufferUsageFlags::TRANSFER_SRC
+                | vk::BufferUsageFlags::TRANSFER_DST,
+            vulkanalia_vma::MemoryUsage::AutoPreferDevice,
+        );
+        let info = vk::BufferDeviceAddressInfo::builder().buffer(buffer.buf);
+        let block = Block {
+            address: unsafe { device.get_buffer_device_address(&info) },
+            buffer,
+            free: FreeList::new(self.block_len),
+            allocations: 0,
+        };
+        match self.blocks.iter().position(Option::is_none) {
+            Some(index) => self.blocks[index] = Some(block),
+            None => self.blocks.push(Some(block)),
+        }
+
+        #[cfg(feature = "logging")]
+        piglog::note!("Mesh arena grew to {} blocks", self.stats().blocks);
+
+        self.allocate_except(len, None)
+            .expect("a new block fits any mesh")
+    }
+
+    fn allocate_except(&mut self, len: u32, except: Option<u32>) -> Option<Range> {
+        self.blocks
+            .iter_mut()
+            .enumerate()
+            .filter(|&(index, _)| Some(index as u32) != except)
+            .find_map(|(index, block)| {
+                let block = block.as_mut()?;
+                let offset = block.free.allocate(len)?;
+                block.allocations += 1;
+                Some(Range {
+                    block: index as u32,
+                    offset,
+                    len,
+                })
+            })
+    }
+
+    /// Return `range` to its block. The GPU must be done reading it.
+    pub fn free(&mut self, range: Range) {
+        let block = self.blocks[range.block as usize]
+            .as_mut()
+            .expect("range from a live block");
+        block.free.free(range.offset, range.len);
+        block.allocations -= 1;
+    }
+
+    /// Destroy blocks holding nothing, keeping at least one. Anything freed
+    /// from them must have been freed after the GPU finished with it.
+    pub fn release_empty_blocks(&mut self, allocator: &vulkanalia_vma::Allocator) {
+        let mut live = self.blocks.iter().flatten().count();
+        for slot in &mut self.blocks {
+            if live <= 1 {
+                break;
+            }
+            if let Some(block) = slot.as_mut().filter(|block| block.allocations == 0) {
+                block.buffer.flush(allocator);
+                *slot = None;
+                live -= 1;
+            }
+        }
+    }
+
+    /// Buffer device address of `range`'s block.
+    pub fn address(&self, range: Range) -> vk::DeviceAddress {
+        self.blocks[range.block as usize]
+            .as_ref()
+            .expect("range from a live block")
+            .address
+    }
+
+    /// Copy `data` into `range` through a staging buffer, blocking until the
+    /// copy has finished.
+    pub fn write<T: NoUninit>(
+        &self,
+        range: Range,
+        data: &[T],
+        allocator: &vulkanalia_vma::Allocator,
+        device: &vulkanalia::Device,
+        queue: vk::Queue,
+        timeline: &mut Timeline,
+    ) {
+        zone!("mesh arena write");
+        debug_assert_eq!(size_of::<T>() as u64, self.stride);
+        debug_assert!(data.len() <= range.len as usize, "data overflows its range");
+
+        let bytes: &[u8] = bytemuck::cast_slice(data);
+        let mut staging = AllocatedBuffer::new(
+            allocator,
+            bytes.len() as u64,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vulkanalia_vma::MemoryUsage::AutoPreferHost,
+        );
+        unsafe {
+            let mem = allocator.map_memory(staging.allocation).unwrap();
+            std::slice::from_raw_parts_mut(mem, bytes.len()).copy_from_slice(bytes);
+            allocator.unmap_memory(staging.allocation);
+        }
+
+        let dst = self.blocks[range.block as usize]
+            .as_ref()
+            .expect("range from a live block")
+            .buffer
+            .buf;
+        immediate_submit(device, queue, timeline, |cmd| unsafe {
+            device.cmd_copy_buffer(
+                cmd,
+                staging.buf,
+                dst,
+                &[vk::BufferCopy::builder()
+                    .dst_offset(u64::from(range.offset) * self.stride)
+                    .size(bytes.len() as u64)],
+            );
+        });
+
+        staging.flush(allocator);
+    }
+
+    /// Move up to `budget` elements out of the emptiest block into the
+    /// others, so it can be released once they are freed. `ranges` are the
+    /// live ranges, updated in place; each one moved is passed to `retire`
+    /// and must be freed once the GPU is done with it. Blocks until the copies
+    /// have finished.
+    pub fn defragment<'a>(
+        &mut self,
+        budget: u32,
+        ranges: impl IntoIterator<Item = &'a mut Range>,
+        mut retire: impl FnMut(Range),
+        device: &vulkanalia::Device,
+        queue: vk::Queue,
+        timeline: &mut Timeline,
+    ) {
+        let Some(source) = self.evacuation_candidate() else {
+            return;
+        };
+
+        let mut moves = vec![];
+        let mut moved = 0;
+        for range in ranges {
+            if range.block != source {
+                continue;
+            }
+            if moved + range.len > budget {
+                break;
+            }
+            let Some(new) = self.allocate_except(range.len, Some(source)) else {
+                break;
+            };
+            moves.push((*range, new));
+            moved += range.len;
+            retire(*range);
+            *range = new;
+        }
+        if moves.is_empty() {
+            return;
+        }
+
+        zone!("mesh arena defragment");
+        let buffer = |block: u32| {
+            self.blocks[block as usize]
+                .as_ref()
+                .expect("range from a live block")
+                .buffer
+                .buf
+        };
+        immediate_submit(device, queue, timeline, |cmd| {
+            for (from, to) in &moves {
+                unsafe {
+                    device.cmd_copy_buffer(
+                        cmd,
+                        buffer(from.block),
+                        buffer(to.block),
+                        &[vk::BufferCopy::builder()
+                            .src_offset(u64::from(from.offset) * self.stride)
+                            .dst_offset(u64::from(to.offset) * self.stride)
+                            .size(u64::from(from.len) * self.stride)],
+                    );
+                }
+            }
+        });
+        self.defragmented += u64::from(moved) * self.stride;
+    }
+
+    /// The least used block, if it is under half full and the other blocks
+    /// have room for everything in it.
+    fn evacuation_candidate(&self) -> Option<u32> {
+        let live = self.blocks.iter().flatten();
+        if live.clone().count() < 2 {
+            return None;
+        }
+        let (index, block) = self
+            .blocks
+            .iter()
+            .enumerate()
+            .filter_map(|(index, block)| Some((index, block.as_ref()?)))
+            .filter(|(_, block)| block.allocations > 0)
+            .min_by_key(|(_, block)| block.free.used())?;
+        let room: u32 = live
+            .filter(|other| !std::ptr::eq(*other, block))
+            .map(|other| other.free.available())
+            .sum();
+        (block.free.used() < self.block_len / 2 && room >= block.free.used())
+            .then_some(index as u32)
+    }
+
+    pub fn stats(&self) -> Stats {
+        let blocks = self.blocks.iter().flatten();
+        Stats {
+            blocks: blocks.clone().count() as u32,
+            capacity: blocks
+                .clone()
+                .map(|block| block.free.size())
+                .map(u64::from)
+                .sum::<u64>()
+                * self.stride,
+            used: blocks
+                .clone()
+                .map(|block| u64::from(block.free.used()))
+                .sum::<u64>()
+                * self.stride,
+            allocations: blocks.clone().map(|block| block.allocations).sum(),
+            free_ranges: blocks
+                .clone()
+                .map(|block| block.free.free_ranges() as u32)
+                .sum(),
+            largest_free: blocks
+                .map(|block| u64::from(block.free.largest_free()))
+                .max()
+                .unwrap_or(0)
+                * self.stride,
+            defragmented: self.defragmented,
+        }
+    }
+
+    /// Bytes of device memory held by the blocks, as allocated.
+    pub fn allocated_bytes(&self, allocator: &vulkanalia_vma::Allocator) -> u64 {
+        self.blocks
+            .iter()
+            .flatten()
+            .map(|block| block.buffer.size(allocator))
+            .sum()
+    }
+
+    /// Expects the device to be idle.
+    pub fn destroy(&mut self, allocator: &vulkanalia_vma::Allocator) {
+        for mut block in self.blocks.drain(..).flatten() {
+            block.buffer.flush(allocator);
+        }
+    }
+}
@@ -0,0 +1,174 @@
+//! Best fit allocation of ranges out of a fixed size space.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Free ranges of a space `size` units long. Freed ranges merge with free
+/// neighbours, so the space never holds two adjacent free ranges.
+#[derive(Debug, Clone)]
+pub struct FreeList {
+    size: u32,
+    /// Length of each free range, by offset.
+    by_offset: BTreeMap<u32, u32>,
+    /// `(length, offset)` of each free range, for finding the best fit.
+    by_length: BTreeSet<(u32, u32)>,
+    used: u32,
+}
+
+impl FreeList {
+    pub fn new(size: u32) -> Self {
+        let mut list = Self {
+            size,
+            by_offset: BTreeMap::new(),
+            by_length: BTreeSet::new(),
+            used: 0,
+        };
+        if size > 0 {
+            list.insert(0, size);
+        }
+        list
+    }
+
+    /// The offset of a new range `len` units long, taken from the smallest
+    /// free range it fits in, or `None` if none is large enough.
+    pub fn allocate(&mut self, len: u32) -> Option<u32> {
+        if len == 0 {
+            return None;
+        }
+        let &(free_len, offset) = self.by_length.range((len, 0)..).next()?;
+        self.remove(offset, free_len);
+        if free_len > len {
+            self.insert(offset + len, free_len - len);
+        }
+        self.used += len;
+        Some(offset)
+    }
+
+    /// Return a range given out by [`FreeList::allocate`].
+    pub fn free(&mut self, offset: u32, len: u32) {
+        debug_assert!(offset + len <= self.size, "range outside the list");
+        let mut start = offset;
+        let mut end = offset + len;
+
+        if let Some((&before, &before_len)) = self.by_offset.range(..offset).next_back() {
+            debug_assert!(before + before_len <= offset, "range freed twice");
+            if before + before_len == offset {
+                self.remove(before, before_len);
+                start = before;
+            }
+        }
+        if let Some(&after_len) = self.by_offset.get(&end) {
+            self.remove(end, after_len);
+            end += after_len;
+        }
+
+        self.insert(start, end - start);
+        self.used -= len;
+    }
+
+    pub const fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// Units inside allocated ranges.
+    pub const fn used(&self) -> u32 {
+        self.used
+    }
+
+    pub const fn available(&self) -> u32 {
+        self.size - self.used
+    }
+
+    /// The longest range [`FreeList::allocate`] can currently give out.
+    pub fn largest_free(&self) -> u32 {
+        self.by_length.last().map_or(0, |&(len, _)| len)
+    }
+
+    /// Number of separate free ranges. Many of them for the space available
+    /// means the list is fragmented.
+    pub fn free_ranges(&self) -> usize {
+        self.by_offset.len()
+    }
+
+    fn insert(&mut self, offset: u32, len: u32) {
+        self.by_offset.insert(offset, len);
+        self.by_length.insert((len, offset));
+    }
+
+    fn remove(&mut self, offset: u32, len: u32) {
+        self.by_offset.remove(&offset);
+        self.by_length.remove(&(len, offset));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocating_splits_the_free_range() {
+        let mut list = FreeList::new(100);
+        assert_eq!(list.allocate(30), Some(0));
+        assert_eq!(list.allocate(20), Some(30));
+        assert_eq!(list.used(), 50);
+        assert_eq!(list.free_ranges(), 1);
+        assert_eq!(list.largest_free(), 50);
+        assert_eq!(list.allocate(51), None);
+        assert_eq!(list.allocate(50), Some(50));
+        assert_eq!(list.available(), 0);
+        assert_eq!(list.free_ranges(), 0);
+    }
+
+    #[test]
+    fn freeing_merges_with_the_range_before() {
+        let mut list = FreeList::new(30);
+        let a = list.allocate(10).expect("room for a");
+        let b = list.allocate(10).expect("room for b");
+        list.allocate(10).expect("room for c");
+        list.free(a, 10);
+        list.free(b, 10);
+        assert_eq!(list.free_ranges(), 1);
+        assert_eq!(list.largest_free(), 20);
+    }
+
+    #[test]
+    fn freeing_merges_with_the_range_after() {
+        let mut list = FreeList::new(30);
+        list.allocate(10).expect("room for a");
+        let b = list.allocate(10).expect("room for b");
+        assert_eq!(list.free_ranges(), 1);
+        list.free(b, 10);
+        assert_eq!(list.free_ranges(), 1);
+        assert_eq!(list.largest_free(), 20);
+    }
+
+    #[test]
+    fn freeing_between_two_free_ranges_merges_all_three() {
+        let mut list = FreeList::new(30);
+        let a = list.allocate(10).expect("room for a");
+        let b = list.allocate(10).expect("room for b");
+        let c = list.allocate(10).expect("room for c");
+        list.free(a, 10);
+        list.free(c, 10);
+        assert_eq!(list.free_ranges(), 2);
+        list.free(b, 10);
+        assert_eq!(list.free_ranges(), 1);
+        assert_eq!(list.largest_free(), list.size());
+        assert_eq!(list.used(), 0);
+    }
+
+    #[test]
+    fn allocation_takes_the_smallest_range_that_fits() {
+        let mut list = FreeList::new(100);
+        let big = list.allocate(40).expect("room for big");
+        list.allocate(10).expect("room for a separator");
+        let small = list.allocate(15).expect("room for small");
+        list.allocate(35).expect("room for the rest");
+        list.free(big, 40);
+        list.free(small, 15);
+
+        assert_eq!(list.allocate(12), Some(small));
+        assert_eq!(list.allocate(12), Some(big));
+        assert_eq!(list.free_ranges(), 2);
+        assert_eq!(list.largest_free(), 28);
+    }
+}
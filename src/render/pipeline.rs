@@ -0,0 +1,402 @@
+use std::ffi::CStr;
+
+use rootcause::{Report, prelude::ResultExt};
+use vulkanalia::vk::{self, DeviceV1_0, DeviceV1_3, HasBuilder};
+
+use super::shaders::Shader;
+use super::utils::load_shader_module;
+
+pub struct Pipeline {
+    pub pipeline: vk::Pipeline,
+    pub layout: vk::PipelineLayout,
+}
+
+impl Pipeline {
+    /// Begin rendering to the whole of `target` and bind this pipeline, for a
+    /// pass that covers every pixel. The caller ends rendering.
+    pub fn begin_fullscreen(
+        &self,
+        device: &vulkanalia::Device,
+        cmd: vk::CommandBuffer,
+        target: vk::ImageView,
+        extent: vk::Extent2D,
+    ) {
+        let color = [vk::RenderingAttachmentInfo::builder()
+            .image_view(target)
+            .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .store_op(vk::AttachmentStoreOp::STORE)];
+        let area = vk::Rect2D::builder()
+            .extent(extent)
+            .offset(vk::Offset2D { x: 0, y: 0 });
+
+        unsafe {
+            device.cmd_begin_rendering(
+                cmd,
+                &vk::RenderingInfo::builder()
+                    .render_area(area)
+                    .color_attachments(&color)
+                    .layer_count(1),
+            );
+            device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+            device.cmd_set_viewport(
+                cmd,
+                0,
+                &[vk::Viewport {
+                    width: extent.width as f32,
+                    height: extent.height as f32,
+                    x: 0.,
+                    y: 0.,
+                    min_depth: 0.,
+                    max_depth: 1.,
+                }],
+            );
+            device.cmd_set_scissor(cmd, 0, &[area]);
+        }
+    }
+
+    pub fn destroy(&self, device: &vulkanalia::Device) {
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.layout, None);
+        }
+    }
+}
+
+/// Graphics pipeline state for dynamic rendering.
+///
+/// Draws triangle lists with opaque output and a dynamic viewport and
+/// scissor. Defaults to no culling, a single `R16G16B16A16_SFLOAT` attachment
+/// and no depth.
+pub struct PipelineBuilder {
+    shader: &'static Shader,
+    vertex_entry: &'static CStr,
+    fragment_entry: &'static CStr,
+    cull_mode: vk::CullModeFlags,
+    front_face: vk::FrontFace,
+    depth_test: bool,
+    depth_write: bool,
+    depth_compare: vk::CompareOp,
+    /// Constant and slope scaled depth bias, if any.
+    depth_bias: Option<(f32, f32)>,
+    color_formats: Vec<vk::Format>,
+    depth_format: vk::Format,
+    push_constant_ranges: Vec<vk::PushConstantRange>,
+    set_layouts: Vec<vk::DescriptorSetLayout>,
+}
+
+impl PipelineBuilder {
+    pub fn new(
+        shader: &'static Shader,
+        vertex_entry: &'static CStr,
+        fragment_entry: &'static CStr,
+    ) -> Self {
+        Self {
+            shader,
+            vertex_entry,
+            fragment_entry,
+            cull_mode: vk::CullModeFlags::NONE,
+            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+            depth_test: false,
+            depth_write: false,
+            depth_compare: vk::CompareOp::NEVER,
+            depth_bias: None,
+            color_formats: vec![vk::Format::R16G16B16A16_SFLOAT],
+            depth_format: vk::Format::UNDEFINED,
+            push_constant_ranges: vec![],
+            set_layouts: vec![],
+        }
+    }
+
+    pub const fn cull(mut self, cull_mode: vk::CullModeFlags, front_face: vk::FrontFace) -> Self {
+        self.cull_mode = cull_mode;
+        self.front_face = front_face;
+        self
+    }
+
+    pub const fn depth(mut self, test: bool, write: bool, compare: vk::CompareOp) -> Self {
+        self.depth_test = test;
+        self.depth_write = write;
+        self.depth_compare = compare;
+        self
+    }
+
+    /// Push depth away from the light source by `constant` units plus
+    /// `slope` times the polygon's depth slope.
+    pub const fn depth_bias(mut self, constant: f32, slope: f32) -> Self {
+        self.depth_bias = Some((constant, slope));
+        self
+    }
+
+    pub fn color_formats(mut self, formats: &[vk::Format]) -> Self {
+        self.color_formats = formats.to_vec();
+        self
+    }
+
+    pub const fn depth_format(mut self, format: vk::Format) -> Self {
+        self.depth_format = format;
+        self
+    }
+
+    /// Add a push constant range the size of `T` at offset 0.
+    pub fn push_constants<T>(mut self, stages: vk::ShaderStageFlags) -> Self {
+        self.push_constant_ranges.push(
+            vk::PushConstantRange::builder()
+                .stage_flags(stages)
+                .size(size_of::<T>() as u32)
+                .build(),
+        );
+        self
+    }
+
+    pub fn set_layout(mut self, layout: vk::DescriptorSetLayout) -> Self {
+        self.set_layouts.push(layout);
+        self
+    }
+
+    /// Bytes of push constants visible to `stage`.
+    fn push_constant_size(&self, stage: vk::ShaderStageFlags) -> u32 {
+        self.push_constant_ranges
+            .iter()
+            .filter(|range| range.stage_flags.contains(stage))
+            .map(|range| range.offset + range.size)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Fails without touching the device if the shader lacks the entry points
+    /// or their push constant blocks differ in size from the declared ranges.
+    pub fn build(
+        &self,
+        device: &vulkanalia::Device,
+        cache: vk::PipelineCache,
+    ) -> Result<Pipeline, Report> {
+        let shader = self.shader.load()?;
+        for (entry, stage) in [
+            (self.vertex_entry, vk::ShaderStageFlags::VERTEX),
+            (self.fragment_entry, vk::ShaderStageFlags::FRAGMENT),
+        ] {
+            shader.check_entry_point(entry, stage, self.push_constant_size(stage))?;
+        }
+
+        let layout = unsafe {
+            device.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo::builder()
+                    .set_layouts(&self.set_layouts)
+                    .push_constant_ranges(&self.push_constant_ranges),
+                None,
+            )
+        }?;
+
+        let shader = match load_shader_module(&shader.code, device)
+            .context(format!("Issue Loading Shader {}", self.shader.name))
+        {
+            Ok(shader) => shader,
+            Err(e) => {
+                unsafe { device.destroy_pipeline_layout(layout, None) };
+                return Err(e.into());
+            }
+        };
+
+        let dynamic_state_info = vk::PipelineDynamicStateCreateInfo::builder()
+            .dynamic_states(&[vk::DynamicState::SCISSOR, vk::DynamicState::VIEWPORT]);
+
+        let input_assembly_info = vk::PipelineInputAssemblyStateCreateInfo::builder()
+            .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
+
+        let (depth_bias_constant, depth_bias_slope) = self.depth_bias.unwrap_or_default();
+        let rasterization_state_info = vk::PipelineRasterizationStateCreateInfo::builder()
+            .cull_mode(self.cull_mode)
+            .front_face(self.front_face)
+            .line_width(1.)
+            .polygon_mode(vk::PolygonMode::FILL)
+            .depth_bias_enable(self.depth_bias.is_some())
+            .depth_bias_constant_factor(depth_bias_constant)
+            .depth_bias_slope_factor(depth_bias_slope);
+
+        let viewport_state_info = vk::PipelineViewportStateCreateInfo::builder()
+            .viewport_count(1)
+            .scissor_count(1);
+
+        let color_blend_attachments: Vec<_> = self
+            .color_formats
+            .iter()
+            .map(|_| {
+                vk::PipelineColorBlendAttachmentState::builder()
+                    .color_write_mask(
+                        vk::ColorComponentFlags::R
+                            | vk::ColorComponentFlags::G
+                            | vk::ColorComponentFlags::B
+                            | vk::ColorComponentFlags::A,
+                    )
+                    .blend_enable(false)
+                    .build()
+            })
+            .collect();
+        let color_blend_state_info = vk::PipelineColorBlendStateCreateInfo::builder()
+            .logic_op_enable(false)
+            .logic_op(vk::LogicOp::COPY)
+            .attachments(&color_blend_attachments);
+
+        let shader_stages = [
+            vk::PipelineShaderStageCreateInfo::builder()
+                .stage(vk::ShaderStageFlags::VERTEX)
+                .module(shader)
+                .name(self.vertex_entry.to_bytes_with_nul()),
+            vk::PipelineShaderStageCreateInfo::builder()
+                .stage(vk::ShaderStageFlags::FRAGMENT)
+                .module(shader)
+                .name(self.fragment_entry.to_bytes_with_nul()),
+        ];
+
+        let multisample_state_info = vk::PipelineMultisampleStateCreateInfo::builder()
+            .sample_shading_enable(false)
+            .rasterization_samples(vk::SampleCountFlags::_1)
+            .min_sample_shading(1.)
+            .alpha_to_coverage_enable(false)
+            .alpha_to_one_enable(false);
+
+        let depth_stencil_state_info = vk::PipelineDepthStencilStateCreateInfo::builder()
+            .depth_test_enable(self.depth_test)
+            .depth_write_enable(self.depth_write)
+            .depth_compare_op(self.depth_compare)
+            .min_depth_bounds(0.)
+            .max_depth_bounds(1.)
+            .depth_bounds_test_enable(false)
+            .stencil_test_enable(false);
+
+        let mut rendering_create_info = vk::PipelineRenderingCreateInfo::builder()
+            .color_attachment_formats(&self.color_formats)
+            .depth_attachment_format(self.depth_format);
+
+        let vert_info = vk::PipelineVertexInputStateCreateInfo::builder();
+
+        let info = vk::GraphicsPipelineCreateInfo::builder()
+            .dynamic_state(&dynamic_state_info)
+            .input_assembly_state(&input_assembly_info)
+            .rasterization_state(&rasterization_state_info)
+            .viewport_state(&viewport_state_info)
+            .color_blend_state(&color_blend_state_info)
+            .stages(&shader_stages)
+            .vertex_input_state(&vert_info)
+            .depth_stencil_state(&depth_stencil_state_info)
+            .multisample_state(&multisample_state_info)
+            .push_next(&mut rendering_create_info)
+            .layout(layout);
+
+        let pipeline = unsafe { device.create_graphics_pipelines(cache, &[info], None) };
+
+        unsafe { device.destroy_shader_module(shader, None) };
+
+        match pipeline {
+            Ok((pipelines, _)) => Ok(Pipeline {
+                pipeline: pipelines[0],
+                layout,
+            }),
+            Err(e) => {
+                unsafe { device.destroy_pipeline_layout(layout, None) };
+                Err(e.into())
+            }
+        }
+    }
+}
+
+/// Compute pipeline state: one entry point, its push constants and the
+/// descriptor set layouts.
+pub struct ComputePipelineBuilder {
+    shader: &'static Shader,
+    entry: &'static CStr,
+    push_constant_ranges: Vec<vk::PushConstantRange>,
+    set_layouts: Vec<vk::DescriptorSetLayout>,
+}
+
+impl ComputePipelineBuilder {
+    pub const fn new(shader: &'static Shader, entry: &'static CStr) -> Self {
+        Self {
+            shader,
+            entry,
+            push_constant_ranges: vec![],
+            set_layouts: vec![],
+        }
+    }
+
+    /// Add a push constant range the size of `T` at offset 0.
+    pub fn push_constants<T>(mut self) -> Self {
+        self.push_constant_ranges.push(
+            vk::PushConstantRange::builder()
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                .size(size_of::<T>() as u32)
+                .build(),
+        );
+        self
+    }
+
+    pub fn set_layout(mut self, layout: vk::DescriptorSetLayout) -> Self {
+        self.set_layouts.push(layout);
+        self
+    }
+
+    /// Fails without touching the device if the shader lacks the entry point
+    /// or its push constant block differs in size from the declared range.
+    pub fn build(
+        &self,
+        device: &vulkanalia::Device,
+        cache: vk::PipelineCache,
+    ) -> Result<Pipeline, Report> {
+        let shader = self.shader.load()?;
+        let push_constant_size = self
+            .push_constant_ranges
+            .iter()
+            .map(|range| range.offset + range.size)
+            .max()
+            .unwrap_or(0);
+        shader.check_entry_point(
+            self.entry,
+            vk::ShaderStageFlags::COMPUTE,
+            push_constant_size,
+        )?;
+
+        let layout = unsafe {
+            device.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo::builder()
+                    .set_layouts(&self.set_layouts)
+                    .push_constant_ranges(&self.push_constant_ranges),
+                None,
+            )
+        }?;
+
+        let shader = match load_shader_module(&shader.code, device)
+            .context(format!("Issue Loading Shader {}", self.shader.name))
+        {
+            Ok(shader) => shader,
+            Err(e) => {
+                unsafe { device.destroy_pipeline_layout(layout, None) };
+                return Err(e.into());
+            }
+        };
+
+        let info = vk::ComputePipelineCreateInfo::builder()
+            .stage(
+                vk::PipelineShaderStageCreateInfo::builder()
+                    .stage(vk::ShaderStageFlags::COMPUTE)
+                    .module(shader)
+                    .name(self.entry.to_bytes_with_nul()),
+            )
+            .layout(layout);
+
+        let pipeline = unsafe { device.create_compute_pipelines(cache, &[info], None) };
+
+        unsafe { device.destroy_shader_module(shader, None) };
+
+        match pipeline {
+            Ok((pipelines, _)) => Ok(Pipeline {
+                pipeline: pipelines[0],
+                layout,
+            }),
+            Err(e) => {
+                unsafe { device.destroy_pipeline_layout(layout, None) };
+                Err(e.into())
+            }
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use std::path::PathBuf;
+
+use vulkanalia::vk::{self, DeviceV1_0, HasBuilder, InstanceV1_0};
+
+#[cfg(feature = "logging")]
+use piglog::prelude::*;
+#[cfg(feature = "logging")]
+use piglog::warning;
+
+/// Size of `VkPipelineCacheHeaderVersionOne`.
+const HEADER_SIZE: usize = 32;
+/// `VK_PIPELINE_CACHE_HEADER_VERSION_ONE`.
+const HEADER_VERSION_ONE: u32 = 1;
+
+/// A `VkPipelineCache` persisted to disk between runs.
+pub struct PipelineCache {
+    pub cache: vk::PipelineCache,
+    path: PathBuf,
+}
+
+impl PipelineCache {
+    /// Seed the cache from `path` if it was written by this exact driver and
+    /// device. Anything else is ignored and the cache starts empty.
+    pub fn new(
+        instance: &vulkanalia::Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &vulkanalia::Device,
+        path: PathBuf,
+    ) -> Self {
+        let props = unsafe { instance.get_physical_device_properties(physical_device) };
+
+        let data = std::fs::read(&path)
+            .ok()
+            .filter(|data| header_matches(data, &props));
+
+        let create = |data: &[u8]| unsafe {
+            device.create_pipeline_cache(
+                &vk::PipelineCacheCreateInfo::builder().initial_data(data),
+                None,
+            )
+        };
+
+        // The driver is still free to reject data whose header looked fine.
+        let cache = match data.as_deref().map(create) {
+            Some(Ok(cache)) => cache,
+            _ => create(&[]).unwrap(),
+        };
+
+        Self { cache, path }
+    }
+
+    /// Write the cache back to disk. Failing to save only costs compile time on
+    /// the next launch, so errors are logged rather than returned.
+    pub fn save(&self, device: &vulkanalia::Device) {
+        let result = unsafe { device.get_pipeline_cache_data(self.cache) }
+            .map_err(std::io::Error::other)
+            .and_then(|data| {
+                if let Some(dir) = self.path.parent() {
+                    std::fs::create_dir_all(dir)?;
+                }
+                // Write then rename so a crash mid-write cannot leave a torn file.
+                let tmp = self.path.with_extension("tmp");
+                std::fs::write(&tmp, data)?;
+                std::fs::rename(&tmp, &self.path)
+            });
+
+        if let Err(_e) = result {
+            #[cfg(feature = "logging")]
+            warning!(
+                "Failed to save pipeline cache {}: {_e}",
+                self.path.display()
+            );
+        }
+    }
+
+    pub fn destroy(&self, device: &vulkanalia::Device) {
+        unsafe { device.destroy_pipeline_cache(self.cache, None) };
+    }
+}
+
+fn header_matches(data: &[u8], props: &vk::PhysicalDeviceProperties) -> bool {
+    let Some(header) = data.get(..HEADER_SIZE) else {
+        return false;
+    };
+    let word =
+        |i: usize| u32::from_le_bytes([header[i], header[i + 1], header[i + 2], header[i + 3]]);
+
+    word(0) as usize >= HEADER_SIZE
+        && word(4) == HEADER_VERSION_ONE
+        && word(8) == props.vendor_id
+        && word(12) == props.device_id
+        && header[16..32] == props.pipeline_cache_uuid[..]
+}
@@ -0,0 +1,109 @@
+//! Per-frame data shared by the chunk and shadow passes, read from a storage
+//! buffer in the bindless set.
+
+use bytemuck::{Pod, Zeroable};
+use glam::{Mat4, Vec3, Vec4};
+use vulkanalia::vk;
+
+use super::allocations::AllocatedBuffer;
+use super::bindless::{self, Bindless};
+use super::shadows::{CASCADE_COUNT, Cascade};
+use crate::time_of_day::TimeOfDay;
+
+/// Sunlight with the sun overhead, matching `SUN_ILLUMINANCE` in
+/// `skybox.slang` so lit blocks sit right against the sky.
+pub const SUN_ILLUMINANCE: f32 = 20.;
+
+/// Skylight from a clear sky at noon and on a moonless night.
+const DAY_AMBIENT: Vec3 = Vec3::new(1.2, 1.6, 2.2);
+const NIGHT_AMBIENT: Vec3 = Vec3::new(0.01, 0.012, 0.02);
+
+/// Must match `SceneData` in `chunk.slang`, `shadow.slang` and `cull.slang`.
+#[repr(C)]
+#[derive(Debug, Pod, Zeroable, Copy, Clone)]
+pub struct SceneData {
+    pub view_proj: Mat4,
+    pub view: Mat4,
+    pub cascade_view_proj: [Mat4; CASCADE_COUNT],
+    /// View space distance where each cascade ends.
+    pub cascade_splits: Vec4,
+    /// Width of a shadow map texel in blocks, per cascade.
+    pub cascade_texel_sizes: Vec4,
+    /// Points from the world towards the light.
+    pub light_direction: Vec3,
+    pub shadow_map: u32,
+    pub light_color: Vec3,
+    pub shadow_sampler: u32,
+    pub ambient: Vec3,
+    pub block_textures: u32,
+    pub camera: Vec3,
+    pub block_sampler: u32,
+    /// The camera's view projection the depth pyramid was built with.
+    pub previous_view_proj: Mat4,
+}
+
+impl SceneData {
+    pub fn set_cascades(&mut self, cascades: &[Cascade; CASCADE_COUNT]) {
+        self.cascade_view_proj = cascades.map(|cascade| cascade.view_proj);
+        self.cascade_splits = Vec4::from_array(cascades.map(|cascade| cascade.split));
+        self.cascade_texel_sizes = Vec4::from_array(cascades.map(|cascade| cascade.texel_size));
+    }
+}
+
+/// Light scattered down from the whole sky, which fills in shadows.
+pub fn ambient(time: &TimeOfDay) -> Vec3 {
+    NIGHT_AMBIENT + DAY_AMBIENT * time.sun_intensity().sqrt()
+}
+
+/// One host visible [`SceneData`] buffer per frame in flight.
+pub struct SceneBuffers {
+    buffers: [AllocatedBuffer; 2],
+    handles: [bindless::Handle<bindless::StorageBuffer>; 2],
+}
+
+impl SceneBuffers {
+    pub fn new(
+        bindless: &mut Bindless,
+        allocator: &vulkanalia_vma::Allocator,
+        device: &vulkanalia::Device,
+    ) -> Self {
+        let size = size_of::<SceneData>() as u64;
+        let buffers = [(); 2].map(|()| {
+            AllocatedBuffer::new(
+                allocator,
+                size,
+                vk::BufferUsageFlags::STORAGE_BUFFER,
+                vulkanalia_vma::MemoryUsage::AutoPreferDevice,
+            )
+        });
+        let handles = [0, 1].map(|i| bindless.add_storage_buffer(device, buffers[i].buf, 0, size));
+        Self { buffers, handles }
+    }
+
+    /// Write `data` for `frame` and return the handle shaders read it from.
+    /// The frame's previous submission must have finished.
+    pub fn write(
+        &self,
+        frame: usize,
+        data: &SceneData,
+        allocator: &vulkanalia_vma::Allocator,
+    ) -> bindless::Handle<bindless::StorageBuffer> {
+        let buffer = &self.buffers[frame];
+        unsafe {
+            let mem = allocator.map_memory(buffer.allocation).unwrap();
+            std::slice::from_raw_parts_mut(mem, size_of::<SceneData>())
+                .copy_from_slice(bytemuck::bytes_of(data));
+            allocator.unmap_memory(buffer.allocation);
+        }
+        self.handles[frame]
+    }
+
+    pub fn destroy(&mut self, bindless: &mut Bindless, allocator: &vulkanalia_vma::Allocator) {
+        for handle in self.handles {
+            bindless.remove_storage_buffer(handle);
+        }
+        for buffer in &mut self.buffers {
+            buffer.flush(allocator);
+        }
+    }
+}
@@ -0,0 +1,179 @@
+//! Shaders compiled by `build.rs` and embedded in the binary.
+//!
+//! Setting `GLACIAN_SHADER_DIR` makes `<name>.spv` in that directory take
+//! precedence over the embedded copy. Builds with `hot-reload` default it to
+//! `./assets/shaders`, where the watcher writes recompiled shaders.
+
+use std::borrow::Cow;
+use std::ffi::CStr;
+use std::fmt;
+use std::path::PathBuf;
+
+use vulkanalia::vk;
+
+mod reflect;
+
+pub use reflect::Reflection;
+
+macro_rules! embed {
+    ($name:literal) => {
+        Shader {
+            name: $name,
+            embedded: include_bytes!(concat!(env!("OUT_DIR"), "/", $name, ".spv")),
+        }
+    };
+}
+
+pub static SKYBOX: Shader = embed!("skybox");
+pub static TONEMAP: Shader = embed!("tonemap");
+pub static EASU: Shader = embed!("easu");
+pub static RCAS: Shader = embed!("rcas");
+pub static CHUNK: Shader = embed!("chunk");
+pub static SHADOW: Shader = embed!("shadow");
+pub static CULL: Shader = embed!("cull");
+pub static HIZ: Shader = embed!("hiz");
+
+#[derive(Debug)]
+pub enum ShaderError {
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    InvalidSpirv {
+        shader: &'static str,
+        reason: &'static str,
+    },
+    MissingEntryPoint {
+        shader: &'static str,
+        entry: String,
+        stage: vk::ShaderStageFlags,
+    },
+    PushConstantSize {
+        shader: &'static str,
+        entry: String,
+        shader_size: u32,
+        rust_size: u32,
+    },
+}
+
+impl fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io { path, error } => write!(f, "{}: {error}", path.display()),
+            Self::InvalidSpirv { shader, reason } => {
+                write!(f, "shader {shader} is not valid SPIR-V: {reason}")
+            }
+            Self::MissingEntryPoint {
+                shader,
+                entry,
+                stage,
+            } => write!(f, "shader {shader} has no {stage:?} entry point {entry}"),
+            Self::PushConstantSize {
+                shader,
+                entry,
+                shader_size,
+                rust_size,
+            } => write!(
+                f,
+                "shader {shader} entry point {entry} has a {shader_size} byte push constant block, \
+                 but the pipeline provides {rust_size} bytes"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ShaderError {}
+
+pub struct Shader {
+    pub name: &'static str,
+    embedded: &'static [u8],
+}
+
+impl Shader {
+    /// Read and reflect the SPIR-V, preferring the override directory.
+    pub fn load(&'static self) -> Result<LoadedShader, ShaderError> {
+        let code = match override_dir() {
+            Some(dir) => {
+                let path = dir.join(self.name).with_extension("spv");
+                match std::fs::read(&path) {
+                    Ok(code) => Cow::Owned(code),
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                        Cow::Borrowed(self.embedded)
+                    }
+                    Err(error) => return Err(ShaderError::Io { path, error }),
+                }
+            }
+            None => Cow::Borrowed(self.embedded),
+        };
+
+        let reflection = reflect::reflect(&code).map_err(|reason| ShaderError::InvalidSpirv {
+            shader: self.name,
+            reason,
+        })?;
+
+        Ok(LoadedShader {
+            shader: self,
+            code,
+            reflection,
+        })
+    }
+}
+
+pub struct LoadedShader {
+    pub shader: &'static Shader,
+    pub code: Cow<'static, [u8]>,
+    pub reflection: Reflection,
+}
+
+impl LoadedShader {
+    /// Check that `entry` exists for `stage` and that its push constant block
+    /// is exactly `push_constant_size` bytes.
+    pub fn check_entry_point(
+        &self,
+        entry: &CStr,
+        stage: vk::ShaderStageFlags,
+        push_constant_size: u32,
+    ) -> Result<(), ShaderError> {
+        let name = entry.to_string_lossy();
+
+        let Some(entry_point) = self
+            .reflection
+            .entry_points
+            .iter()
+            .find(|entry_point| entry_point.name == name && entry_point.stage == stage)
+        else {
+            return Err(ShaderError::MissingEntryPoint {
+                shader: self.shader.name,
+                entry: name.into_owned(),
+                stage,
+            });
+        };
+
+        // Without exact interfaces, a stage given no push constants may simply
+        // not use the module's block.
+        let unknown = !self.reflection.exact_interfaces && push_constant_size == 0;
+
+        match entry_point.push_constant_size {
+            Some(shader_size) if shader_size != push_constant_size && !unknown => {
+                Err(ShaderError::PushConstantSize {
+                    shader: self.shader.name,
+                    entry: name.into_owned(),
+                    shader_size,
+                    rust_size: push_constant_size,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The directory whose SPIR-V overrides the embedded shaders, if any.
+pub fn override_dir() -> Option<PathBuf> {
+    match std::env::var_os("GLACIAN_SHADER_DIR") {
+        Some(dir) => Some(dir.into()),
+        #[cfg(feature = "hot-reload")]
+        None => Some(PathBuf::from("./assets/shaders")),
+        #[cfg(not(feature = "hot-reload"))]
+        None => None,
+    }
+}
@@ -0,0 +1,535 @@
+//! Just enough SPIR-V parsing to check a module against the pipeline built
+//! from it: entry points and the size of their push constant blocks.
+
+use std::collections::HashMap;
+
+use vulkanalia::vk;
+
+const MAGIC: u32 = 0x0723_0203;
+const HEADER_WORDS: usize = 5;
+/// From 1.4 on, entry point interfaces list every global they use rather than
+/// only inputs and outputs.
+const VERSION_1_4: u32 = 0x0001_0400;
+/// Deeper than any real push constant block, shallow enough not to overflow
+/// the stack on a malformed module whose types refer to themselves.
+const MAX_TYPE_DEPTH: u32 = 64;
+
+const OP_ENTRY_POINT: u32 = 15;
+const OP_TYPE_INT: u32 = 21;
+const OP_TYPE_FLOAT: u32 = 22;
+const OP_TYPE_VECTOR: u32 = 23;
+const OP_TYPE_MATRIX: u32 = 24;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_STRUCT: u32 = 30;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_CONSTANT: u32 = 43;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const OP_MEMBER_DECORATE: u32 = 72;
+
+const DECORATION_ARRAY_STRIDE: u32 = 6;
+const DECORATION_OFFSET: u32 = 35;
+
+const STORAGE_CLASS_PUSH_CONSTANT: u32 = 9;
+
+#[derive(Debug, Clone)]
+pub struct EntryPoint {
+    pub name: String,
+    pub stage: vk::ShaderStageFlags,
+    /// Size of the push constant block including trailing padding, if the
+    /// entry point uses one.
+    pub push_constant_size: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Reflection {
+    pub entry_points: Vec<EntryPoint>,
+    /// False for SPIR-V older than 1.4, where every entry point is assumed to
+    /// use the module's push constant block whether it does or not.
+    pub exact_interfaces: bool,
+}
+
+enum Type {
+    Scalar { bytes: u32 },
+    Vector { component: u32, count: u32 },
+    Matrix { column: u32, count: u32 },
+    Array { element: u32, length: u32 },
+    Struct { members: Vec<u32> },
+}
+
+#[derive(Default)]
+struct Module {
+    types: HashMap<u32, Type>,
+    pointers: HashMap<u32, u32>,
+    constants: HashMap<u32, u32>,
+    array_strides: HashMap<u32, u32>,
+    member_offsets: HashMap<(u32, u32), u32>,
+    push_constants: HashMap<u32, u32>,
+}
+
+impl Module {
+    /// Size and alignment of a type under the std430 rules used for push
+    /// constants, honouring any explicit offsets and strides.
+    fn layout(&self, id: u32, depth: u32) -> Result<(u32, u32), &'static str> {
+        // Valid modules cannot nest types in a cycle, but a malformed one could.
+        if depth > MAX_TYPE_DEPTH {
+            return Err("types nest too deeply");
+        }
+        let ty = self.types.get(&id).ok_or("reference to an unknown type")?;
+        let round_up = |size: u32, align: u32| size.checked_next_multiple_of(align);
+        let layout = match ty {
+            Type::Scalar { bytes: 0 } => return Err("scalar type has no width"),
+            Type::Scalar { bytes } => Some((*bytes, *bytes)),
+            Type::Vector { component, count } => {
+                let (size, _) = self.layout(*component, depth + 1)?;
+                let align = size.checked_mul(if *count == 2 { 2 } else { 4 });
+                size.checked_mul(*count).zip(align)
+            }
+            Type::Matrix { column, count } => {
+                let (size, align) = self.layout(*column, depth + 1)?;
+                round_up(size, align)
+                    .and_then(|stride| stride.checked_mul(*count))
+                    .map(|size| (size, align))
+            }
+            Type::Array { element, length } => {
+                let (size, align) = self.layout(*element, depth + 1)?;
+                let length = *self
+                    .constants
+                    .get(length)
+                    .ok_or("array length is not a constant")?;
+                let stride = match self.array_strides.get(&id) {
+                    Some(&stride) => Some(stride),
+                    None => round_up(size, align),
+                };
+                stride
+                    .and_then(|stride| stride.checked_mul(length))
+                    .map(|size| (size, align))
+            }
+            Type::Struct { members } => {
+                let mut end = 0u32;
+                let mut align = 1;
+                for (index, member) in members.iter().enumerate() {
+                    let (size, member_align) = self.layout(*member, depth + 1)?;
+                    let offset = match self.member_offsets.get(&(id, index as u32)) {
+                        Some(&offset) => Some(offset),
+                        None => round_up(end, member_align),
+                    };
+                    end = end.max(
+                        offset
+                            .and_then(|offset| offset.checked_add(size))
+                            .ok_or("type is too large")?,
+                    );
+                    align = align.max(member_align);
+                }
+                round_up(end, align).map(|size| (size, align))
+            }
+        };
+        layout.ok_or("type is too large")
+    }
+}
+
+/// Reflect the entry points of a SPIR-V module.
+pub fn reflect(code: &[u8]) -> Result<Reflection, &'static str> {
+    if !code.len().is_multiple_of(4) {
+        return Err("length is not a multiple of four bytes");
+    }
+    let words: Vec<u32> = code
+        .chunks_exact(4)
+        .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+        .collect();
+
+    if words.len() < HEADER_WORDS || words[0] != MAGIC {
+        return Err("missing SPIR-V header");
+    }
+    let version = words[1];
+
+    let mut module = Module::default();
+    // (model, name, interface)
+    let mut entry_points = vec![];
+
+    let mut rest = &words[HEADER_WORDS..];
+    while let Some(&first) = rest.first() {
+        let count = (first >> 16) as usize;
+        let opcode = first & 0xffff;
+        if count == 0 || count > rest.len() {
+            return Err("truncated instruction");
+        }
+        let operands = &rest[1..count];
+        rest = &rest[count..];
+
+        let operand = |i: usize| operands.get(i).copied().ok_or("truncated instruction");
+
+        match opcode {
+            OP_ENTRY_POINT => {
+                let model = operand(0)?;
+                let (name, name_words) = literal_string(operands.get(2..).unwrap_or_default())?;
+                let interface = operands[2 + name_words..].to_vec();
+                entry_points.push((model, name, interface));
+            }
+            OP_TYPE_INT | OP_TYPE_FLOAT => {
+                let bytes = operand(1)? / 8;
+                module.types.insert(operand(0)?, Type::Scalar { bytes });
+            }
+            OP_TYPE_VECTOR => {
+                let (component, count) = (operand(1)?, operand(2)?);
+                module
+                    .types
+                    .insert(operand(0)?, Type::Vector { component, count });
+            }
+            OP_TYPE_MATRIX => {
+                let (column, count) = (operand(1)?, operand(2)?);
+                module
+                    .types
+                    .insert(operand(0)?, Type::Matrix { column, count });
+            }
+            OP_TYPE_ARRAY => {
+                let (element, length) = (operand(1)?, operand(2)?);
+                module
+                    .types
+                    .insert(operand(0)?, Type::Array { element, length });
+            }
+            OP_TYPE_STRUCT => {
+                let members = operands.get(1..).unwrap_or_default().to_vec();
+                module.types.insert(operand(0)?, Type::Struct { members });
+            }
+            OP_TYPE_POINTER => {
+                module.pointers.insert(operand(0)?, operand(2)?);
+            }
+            OP_CONSTANT => {
+                module.constants.insert(operand(1)?, operand(2)?);
+            }
+            OP_VARIABLE if operand(2)? == STORAGE_CLASS_PUSH_CONSTANT => {
+                let pointee = *module
+                    .pointers
+                    .get(&operand(0)?)
+                    .ok_or("push constant variable is not a pointer")?;
+                module.push_constants.insert(operand(1)?, pointee);
+            }
+            OP_DECORATE if operand(1)? == DECORATION_ARRAY_STRIDE => {
+                module.array_strides.insert(operand(0)?, operand(2)?);
+            }
+            OP_MEMBER_DECORATE if operand(2)? == DECORATION_OFFSET => {
+                module
+                    .member_offsets
+                    .insert((operand(0)?, operand(1)?), operand(3)?);
+            }
+            _ => {}
+        }
+    }
+
+    let mut reflection = Reflection {
+        entry_points: vec![],
+        exact_interfaces: version >= VERSION_1_4,
+    };
+    for (model, name, interface) in entry_points {
+        let Some(stage) = stage(model) else {
+            continue;
+        };
+
+        let block = module
+            .push_constants
+            .iter()
+            .find(|(variable, _)| !reflection.exact_interfaces || interface.contains(variable));
+        let push_constant_size = match block {
+            Some((_, ty)) => Some(module.layout(*ty, 0)?.0),
+            None => None,
+        };
+
+        reflection.entry_points.push(EntryPoint {
+            name,
+            stage,
+            push_constant_size,
+        });
+    }
+    Ok(reflection)
+}
+
+fn stage(execution_model: u32) -> Option<vk::ShaderStageFlags> {
+    Some(match execution_model {
+        0 => vk::ShaderStageFlags::VERTEX,
+        1 => vk::ShaderStageFlags::TESSELLATION_CONTROL,
+        2 => vk::ShaderStageFlags::TESSELLATION_EVALUATION,
+        3 => vk::ShaderStageFlags::GEOMETRY,
+        4 => vk::ShaderStageFlags::FRAGMENT,
+        5 => vk::ShaderStageFlags::COMPUTE,
+        _ => return None,
+    })
+}
+
+/// Decode a nul-terminated literal string, returning it with the number of
+/// words it occupies.
+fn literal_string(words: &[u32]) -> Result<(String, usize), &'static str> {
+    let mut bytes = vec![];
+    for (i, word) in words.iter().enumerate() {
+        for byte in word.to_le_bytes() {
+            if byte == 0 {
+                let name = String::from_utf8(bytes).map_err(|_| "entry point name is not UTF-8")?;
+                return Ok((name, i + 1));
+            }
+            bytes.push(byte);
+        }
+    }
+    Err("unterminated string")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VERSION_1_3: u32 = 0x0001_0300;
+
+    const FLOAT: u32 = 1;
+    const VEC3: u32 = 2;
+    const UINT: u32 = 3;
+    const THREE: u32 = 4;
+    const ARRAY: u32 = 5;
+    const BLOCK: u32 = 6;
+    const POINTER: u32 = 7;
+    const PUSH_CONSTANTS: u32 = 8;
+
+    fn op(opcode: u32, operands: &[u32]) -> Vec<u32> {
+        let mut words = vec![((operands.len() as u32 + 1) << 16) | opcode];
+        words.extend(operands);
+        words
+    }
+
+    fn entry_point(model: u32, name: &str, interface: &[u32]) -> Vec<u32> {
+        let mut bytes = name.as_bytes().to_vec();
+        bytes.resize((bytes.len() + 1).next_multiple_of(4), 0);
+
+        let mut operands = vec![model, 100];
+        operands.extend(
+            bytes
+                .chunks_exact(4)
+                .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]])),
+        );
+        operands.extend(interface);
+        op(OP_ENTRY_POINT, &operands)
+    }
+
+    fn assemble(version: u32, instructions: &[Vec<u32>]) -> Vec<u8> {
+        [MAGIC, version, 0, 101, 0]
+            .into_iter()
+            .chain(instructions.iter().flatten().copied())
+            .flat_map(u32::to_le_bytes)
+            .collect()
+    }
+
+    /// A push constant block of a float, a vec3 at 16 and a float[3] with a
+    /// 16 byte stride at 32, 80 bytes in all.
+    fn explicit_block() -> Vec<Vec<u32>> {
+        vec![
+            op(OP_TYPE_FLOAT, &[FLOAT, 32]),
+            op(OP_TYPE_VECTOR, &[VEC3, FLOAT, 3]),
+            op(OP_TYPE_INT, &[UINT, 32, 0]),
+            op(OP_CONSTANT, &[UINT, THREE, 3]),
+            op(OP_TYPE_ARRAY, &[ARRAY, FLOAT, THREE]),
+            op(OP_DECORATE, &[ARRAY, DECORATION_ARRAY_STRIDE, 16]),
+            op(OP_TYPE_STRUCT, &[BLOCK, FLOAT, VEC3, ARRAY]),
+            op(OP_MEMBER_DECORATE, &[BLOCK, 0, DECORATION_OFFSET, 0]),
+            op(OP_MEMBER_DECORATE, &[BLOCK, 1, DECORATION_OFFSET, 16]),
+            op(OP_MEMBER_DECORATE, &[BLOCK, 2, DECORATION_OFFSET, 32]),
+            op(
+                OP_TYPE_POINTER,
+                &[POINTER, STORAGE_CLASS_PUSH_CONSTANT, BLOCK],
+            ),
+            op(
+                OP_VARIABLE,
+                &[POINTER, PUSH_CONSTANTS, STORAGE_CLASS_PUSH_CONSTANT],
+            ),
+        ]
+    }
+
+    #[test]
+    fn finds_entry_points_by_stage() {
+        let code = assemble(
+            VERSION_1_4,
+            &[
+                entry_point(0, "vertex_main", &[]),
+                entry_point(4, "fragment_main", &[]),
+                entry_point(5, "main", &[]),
+                // Mesh shaders are not used and are skipped.
+                entry_point(5364, "mesh_main", &[]),
+            ],
+        );
+        let reflection = reflect(&code).expect("valid module");
+
+        let found: Vec<_> = reflection
+            .entry_points
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry.stage, entry.push_constant_size))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("vertex_main", vk::ShaderStageFlags::VERTEX, None),
+                ("fragment_main", vk::ShaderStageFlags::FRAGMENT, None),
+                ("main", vk::ShaderStageFlags::COMPUTE, None),
+            ]
+        );
+        assert!(reflection.exact_interfaces);
+    }
+
+    #[test]
+    fn push_constant_size_honours_offsets_and_strides() {
+        let mut instructions = vec![entry_point(5, "main", &[PUSH_CONSTANTS])];
+        instructions.extend(explicit_block());
+        let reflection = reflect(&assemble(VERSION_1_4, &instructions)).expect("valid module");
+
+        assert_eq!(reflection.entry_points[0].push_constant_size, Some(80));
+    }
+
+    #[test]
+    fn push_constant_size_defaults_to_std430() {
+        const VEC2: u32 = 9;
+        const PLAIN_ARRAY: u32 = 10;
+        const PLAIN_BLOCK: u32 = 11;
+        const PLAIN_POINTER: u32 = 12;
+        const VARIABLE: u32 = 13;
+
+        let code = assemble(
+            VERSION_1_4,
+            &[
+                entry_point(5, "main", &[VARIABLE]),
+                op(OP_TYPE_FLOAT, &[FLOAT, 32]),
+                op(OP_TYPE_VECTOR, &[VEC2, FLOAT, 2]),
+                op(OP_TYPE_VECTOR, &[VEC3, FLOAT, 3]),
+                op(OP_TYPE_INT, &[UINT, 32, 0]),
+                op(OP_CONSTANT, &[UINT, THREE, 3]),
+                op(OP_TYPE_ARRAY, &[PLAIN_ARRAY, FLOAT, THREE]),
+                // float at 0, vec3 at 16, vec2 at 32 and float[3] at 40, padded
+                // to the vec3 alignment.
+                op(
+                    OP_TYPE_STRUCT,
+                    &[PLAIN_BLOCK, FLOAT, VEC3, VEC2, PLAIN_ARRAY],
+                ),
+                op(
+                    OP_TYPE_POINTER,
+                    &[PLAIN_POINTER, STORAGE_CLASS_PUSH_CONSTANT, PLAIN_BLOCK],
+                ),
+                op(
+                    OP_VARIABLE,
+                    &[PLAIN_POINTER, VARIABLE, STORAGE_CLASS_PUSH_CONSTANT],
+                ),
+            ],
+        );
+        let reflection = reflect(&code).expect("valid module");
+
+        assert_eq!(reflection.entry_points[0].push_constant_size, Some(64));
+    }
+
+    #[test]
+    fn interfaces_decide_push_constant_use_from_1_4() {
+        let mut instructions = vec![
+            entry_point(0, "uses_block", &[PUSH_CONSTANTS]),
+            entry_point(4, "ignores_block", &[]),
+        ];
+        instructions.extend(explicit_block());
+
+        let exact = reflect(&assemble(VERSION_1_4, &instructions)).expect("valid module");
+        let sizes: Vec<_> = exact
+            .entry_points
+            .iter()
+            .map(|entry| entry.push_constant_size)
+            .collect();
+        assert_eq!(sizes, [Some(80), None]);
+
+        let older = reflect(&assemble(VERSION_1_3, &instructions)).expect("valid module");
+        assert!(!older.exact_interfaces);
+        let sizes: Vec<_> = older
+            .entry_points
+            .iter()
+            .map(|entry| entry.push_constant_size)
+            .collect();
+        assert_eq!(sizes, [Some(80), Some(80)]);
+    }
+
+    #[test]
+    fn rejects_malformed_modules() {
+        let with_block = |types: &[Vec<u32>]| {
+            let mut instructions = vec![entry_point(5, "main", &[PUSH_CONSTANTS])];
+            instructions.extend_from_slice(types);
+            instructions.push(op(
+                OP_TYPE_POINTER,
+                &[POINTER, STORAGE_CLASS_PUSH_CONSTANT, BLOCK],
+            ));
+            instructions.push(op(
+                OP_VARIABLE,
+                &[POINTER, PUSH_CONSTANTS, STORAGE_CLASS_PUSH_CONSTANT],
+            ));
+            assemble(VERSION_1_4, &instructions)
+        };
+
+        let mut bad_magic = assemble(VERSION_1_4, &[]);
+        bad_magic[0] ^= 1;
+        let cases = [
+            ("empty", vec![]),
+            ("ragged length", vec![0; 21]),
+            ("bad magic", bad_magic),
+            ("zero word count", assemble(VERSION_1_4, &[vec![0]])),
+            (
+                "word count past the end",
+                assemble(VERSION_1_4, &[vec![(3 << 16) | OP_TYPE_INT, 1]]),
+            ),
+            (
+                "unterminated name",
+                assemble(
+                    VERSION_1_4,
+                    &[op(OP_ENTRY_POINT, &[5, 100, u32::from_le_bytes(*b"main")])],
+                ),
+            ),
+            (
+                "missing operands",
+                assemble(VERSION_1_4, &[op(OP_TYPE_VECTOR, &[2])]),
+            ),
+            (
+                "unknown member type",
+                with_block(&[op(OP_TYPE_STRUCT, &[BLOCK, FLOAT])]),
+            ),
+            (
+                "array length not a constant",
+                with_block(&[
+                    op(OP_TYPE_FLOAT, &[FLOAT, 32]),
+                    op(OP_TYPE_ARRAY, &[ARRAY, FLOAT, THREE]),
+                    op(OP_TYPE_STRUCT, &[BLOCK, ARRAY]),
+                ]),
+            ),
+            (
+                "zero width scalar",
+                with_block(&[
+                    op(OP_TYPE_INT, &[UINT, 0, 0]),
+                    op(OP_TYPE_STRUCT, &[BLOCK, UINT]),
+                ]),
+            ),
+            (
+                "struct containing itself",
+                with_block(&[op(OP_TYPE_STRUCT, &[BLOCK, BLOCK])]),
+            ),
+            (
+                "oversized array",
+                with_block(&[
+                    op(OP_TYPE_FLOAT, &[FLOAT, 32]),
+                    op(OP_TYPE_INT, &[UINT, 32, 0]),
+                    op(OP_CONSTANT, &[UINT, THREE, u32::MAX]),
+                    op(OP_TYPE_ARRAY, &[ARRAY, FLOAT, THREE]),
+                    op(OP_TYPE_STRUCT, &[BLOCK, ARRAY]),
+                ]),
+            ),
+        ];
+        for (case, code) in cases {
+            assert!(reflect(&code).is_err(), "{case} was accepted");
+        }
+    }
+
+    #[test]
+    fn truncated_modules_never_panic() {
+        let mut instructions = vec![entry_point(5, "main", &[PUSH_CONSTANTS])];
+        instructions.extend(explicit_block());
+        let code = assemble(VERSION_1_4, &instructions);
+
+        for len in 0..code.len() {
+            // Cuts on an instruction boundary leave a valid module.
+            let _ = reflect(&code[..len]);
+        }
+    }
+}
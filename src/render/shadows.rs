@@ -0,0 +1,232 @@
+//! Cascaded shadow maps for the sun and moon.
+//!
+//! Each cascade is a layer of one depth array, drawn from the light with the
+//! chunks inside that cascade and sampled with PCF by `chunk.slang`.
+
+use rootcause::Report;
+use vulkanalia::vk::{self, DeviceV1_0, HasBuilder};
+
+use super::allocations::AllocatedImage;
+use super::bindless::{self, Bindless};
+use super::chunks::{self, Chunks};
+use super::culling::DrawList;
+use super::graph::{Access, Graph, ImageId, ImportedImage};
+use super::pipeline::{Pipeline, PipelineBuilder};
+use super::shaders;
+
+mod cascades;
+
+pub use cascades::{CASCADE_COUNT, Cascade, fit};
+
+pub const FORMAT: vk::Format = vk::Format::D32_SFLOAT;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Settings {
+    /// How far from the camera shadows reach, in blocks.
+    pub distance: f32,
+    /// Width and height of each cascade in texels.
+    pub resolution: u32,
+    /// Blend from uniform (0) to logarithmic (1) cascade splits.
+    pub split_lambda: f32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            distance: 128.,
+            resolution: 2048,
+            split_lambda: 0.75,
+        }
+    }
+}
+
+/// The depth only pipeline drawing chunks into the cascades.
+pub struct Data {
+    pipeline: Pipeline,
+}
+
+impl Data {
+    pub fn new(
+        device: &vulkanalia::Device,
+        pipeline_cache: vk::PipelineCache,
+        bindless_layout: vk::DescriptorSetLayout,
+    ) -> Result<Self, Report> {
+        let pipeline = PipelineBuilder::new(&shaders::SHADOW, c"vs_main", c"fs_main")
+            .color_formats(&[])
+            .depth_format(FORMAT)
+            .depth(true, true, vk::CompareOp::LESS)
+            .depth_bias(1.25, 1.75)
+            .set_layout(bindless_layout)
+            .push_constants::<chunks::PushConstants>(
+                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+            )
+            .build(device, pipeline_cache)?;
+
+        Ok(Self { pipeline })
+    }
+
+    /// Draw the chunks the culling pass kept for `cascade` into the pass's
+    /// depth attachment, which is that cascade's layer of the map.
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw(
+        &self,
+        device: &vulkanalia::Device,
+        cmd: vk::CommandBuffer,
+        bindless: &Bindless,
+        chunks: &Chunks,
+        draws: &DrawList,
+        scene: bindless::Handle<bindless::StorageBuffer>,
+        cascade: usize,
+    ) {
+        unsafe {
+            device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, self.pipeline.pipeline);
+        }
+        bindless.bind(
+            device,
+            cmd,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline.layout,
+        );
+
+        chunks.draw(
+            device,
+            cmd,
+            self.pipeline.layout,
+            draws,
+            scene.index(),
+            cascade as u32,
+        );
+    }
+
+    pub fn destroy(&mut self, device: &vulkanalia::Device) {
+        self.pipeline.destroy(device);
+    }
+}
+
+/// The cascades' depth array and the comparison sampler reading it.
+pub struct Map {
+    image: AllocatedImage,
+    /// One view per cascade to render into.
+    layer_views: [vk::ImageView; CASCADE_COUNT],
+    sampler: vk::Sampler,
+    resolution: u32,
+    pub texture: bindless::Handle<bindless::SampledImage>,
+    pub sampler_handle: bindless::Handle<bindless::Sampler>,
+}
+
+impl Map {
+    pub fn new(
+        resolution: u32,
+        bindless: &mut Bindless,
+        allocator: &vulkanalia_vma::Allocator,
+        device: &vulkanalia::Device,
+    ) -> Self {
+        let resolution = resolution.max(1);
+        let image = AllocatedImage::with_levels(
+            FORMAT,
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+            vk::Extent3D {
+                width: resolution,
+                height: resolution,
+                depth: 1,
+            },
+            vk::ImageAspectFlags::DEPTH,
+            1,
+            CASCADE_COUNT as u32,
+            vk::ImageViewType::_2D_ARRAY,
+            allocator,
+            device,
+        );
+
+        let layer_views = std::array::from_fn(|layer| {
+            let info = vk::ImageViewCreateInfo::builder()
+                .view_type(vk::ImageViewType::_2D)
+                .image(image.image)
+                .format(FORMAT)
+                .subresource_range(
+                    vk::ImageSubresourceRange::builder()
+                        .aspect_mask(vk::ImageAspectFlags::DEPTH)
+                        .base_mip_level(0)
+                        .level_count(1)
+                        .base_array_layer(layer as u32)
+                        .layer_count(1),
+                );
+            unsafe { device.create_image_view(&info, None) }.unwrap()
+        });
+
+        // Outside the map counts as lit.
+        let sampler_info = vk::SamplerCreateInfo::builder()
+            .mag_filter(vk::Filter::LINEAR)
+            .min_filter(vk::Filter::LINEAR)
+            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+            .border_color(vk::BorderColor::FLOAT_OPAQUE_WHITE)
+            .compare_enable(true)
+            .compare_op(vk::CompareOp::LESS_OR_EQUAL)
+            .min_lod(0.)
+            .max_lod(0.);
+        let sampler = unsafe { device.create_sampler(&sampler_info, None) }.unwrap();
+
+        Self {
+            texture: bindless.add_sampled_image(
+                device,
+                image.view,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            ),
+            sampler_handle: bindless.add_sampler(device, sampler),
+            image,
+            layer_views,
+            sampler,
+            resolution,
+        }
+    }
+
+    pub const fn resolution(&self) -> u32 {
+        self.resolution
+    }
+
+    /// Bring each cascade's layer into `graph`. Every layer is redrawn each
+    /// frame, so last frame's contents are dropped.
+    pub fn import(&self, graph: &mut Graph) -> [ImageId; CASCADE_COUNT] {
+        let extent = vk::Extent2D {
+            width: self.resolution,
+            height: self.resolution,
+        };
+        std::array::from_fn(|layer| {
+            graph.import_image(ImportedImage {
+                base_layer: layer as u32,
+                previous: Access::FragmentSampled,
+                discard: true,
+                ..ImportedImage::new(
+                    self.image.image,
+                    self.layer_views[layer],
+                    extent,
+                    vk::ImageAspectFlags::DEPTH,
+                )
+            })
+        })
+    }
+
+    pub fn allocated_bytes(&self, allocator: &vulkanalia_vma::Allocator) -> u64 {
+        self.image.size(allocator)
+    }
+
+    pub fn destroy(
+        &mut self,
+        bindless: &mut Bindless,
+        allocator: &vulkanalia_vma::Allocator,
+        device: &vulkanalia::Device,
+    ) {
+        bindless.remove_sampled_image(self.texture);
+        bindless.remove_sampler(self.sampler_handle);
+        unsafe {
+            for view in self.layer_views {
+                device.destroy_image_view(view, None);
+            }
+            device.destroy_sampler(self.sampler, None);
+        }
+        self.image.flush(device, allocator);
+    }
+}
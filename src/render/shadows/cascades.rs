@@ -0,0 +1,105 @@
+//! Fitting shadow cascades around slices of the camera frustum.
+
+use glam::{Mat4, Vec3, Vec4};
+
+use super::Settings;
+
+pub const CASCADE_COUNT: usize = 4;
+
+/// Room behind each cascade for casters outside the view, in blocks.
+const CASTER_MARGIN: f32 = 64.;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cascade {
+    /// World to shadow map clip space.
+    pub view_proj: Mat4,
+    /// View space distance at which the next cascade takes over.
+    pub split: f32,
+    /// Width of one shadow map texel in blocks.
+    pub texel_size: f32,
+}
+
+/// Far end of each cascade, blending logarithmic splits, which spend texels
+/// where they are needed up close, with uniform ones by `lambda`.
+pub fn split_distances(near: f32, far: f32, lambda: f32) -> [f32; CASCADE_COUNT] {
+    std::array::from_fn(|i| {
+        let p = (i + 1) as f32 / CASCADE_COUNT as f32;
+        let log = near * (far / near).powf(p);
+        let uniform = near + (far - near) * p;
+        uniform + (log - uniform) * lambda
+    })
+}
+
+/// Cascades covering the view out to the shadow distance. `light_direction`
+/// points from the world towards the light.
+pub fn fit(
+    view: Mat4,
+    fov_y: f32,
+    aspect_ratio: f32,
+    near: f32,
+    light_direction: Vec3,
+    settings: &Settings,
+) -> [Cascade; CASCADE_COUNT] {
+    let inv_view = view.inverse();
+    let tan_y = (fov_y * 0.5).tan();
+    let tan_x = tan_y * aspect_ratio;
+    let splits = split_distances(
+        near,
+        settings.distance.max(near * 2.),
+        settings.split_lambda,
+    );
+    let resolution = settings.resolution.max(1) as f32;
+
+    let light_direction = light_direction.normalize_or(Vec3::NEG_Z);
+    let up = if light_direction.y.abs() > 0.99 {
+        Vec3::Z
+    } else {
+        Vec3::Y
+    };
+
+    std::array::from_fn(|i| {
+        let slice_near = if i == 0 { near } else { splits[i - 1] };
+        let slice_far = splits[i];
+        let corners: [Vec3; 8] = std::array::from_fn(|c| {
+            let d = if c < 4 { slice_near } else { slice_far };
+            let x = if c & 1 == 0 { -tan_x } else { tan_x };
+            let y = if c & 2 == 0 { -tan_y } else { tan_y };
+            inv_view.transform_point3(Vec3::new(x * d, y * d, -d))
+        });
+
+        // A bounding sphere keeps the cascade the same size as the camera
+        // turns, and rounding its radius keeps float error from resizing it.
+        let centre = corners.iter().sum::<Vec3>() / 8.;
+        let radius = corners
+            .iter()
+            .map(|corner| corner.distance(centre))
+            .fold(0., f32::max);
+        let radius = (radius * 16.).ceil() / 16.;
+
+        let eye = centre + light_direction * (radius + CASTER_MARGIN);
+        let light_view = Mat4::look_to_rh(eye, -light_direction, up);
+        let mut proj = Mat4::orthographic_rh(
+            -radius,
+            radius,
+            -radius,
+            radius,
+            0.,
+            2. * radius + CASTER_MARGIN,
+        );
+
+        // Move the projection by less than a texel so the world origin lands
+        // on a texel corner, which stops shadow edges crawling as the camera
+        // moves.
+        let origin = (proj * light_view) * Vec4::W;
+        let texels = origin.truncate().truncate() * (resolution * 0.5);
+        let offset = (texels.round() - texels) * (2. / resolution);
+        proj.w_axis.x += offset.x;
+        proj.w_axis.y += offset.y;
+
+        Cascade {
+            view_proj: proj * light_view,
+            split: slice_far,
+            texel_size: 2. * radius / resolution,
+        }
+    })
+}
@@ -1,183 +1,260 @@
-use rootcause::{Report, prelude::ResultExt};
+use rootcause::Report;
 
-use super::utils::load_shader_module;
+use super::allocations::AllocatedImage;
+use super::bindless::{self, Bindless};
+use super::pipeline::{Pipeline, PipelineBuilder};
+use super::shaders;
+use super::timeline::Timeline;
+use super::utils::{immediate_submit, transition_image};
 use bytemuck::{Pod, Zeroable};
-use glam::Vec3A;
-use vulkanalia::vk::{self, DeviceV1_0, DeviceV1_3, Handle, HasBuilder};
+use glam::{Mat4, Vec3};
+use vulkanalia::vk::{self, DeviceV1_0, DeviceV1_3};
 
+/// Size of the transmittance LUT, which must match `skybox.slang`.
+pub const TRANSMITTANCE_SIZE: vk::Extent2D = vk::Extent2D {
+    width: 256,
+    height: 64,
+};
+
+/// Which cheaper paths the sky shader takes. The values match the constants
+/// in `skybox.slang`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[repr(u32)]
+pub enum Quality {
+    /// Fewer scattering and aurora samples and two octaves of noise.
+    Low = 0,
+    Medium = 1,
+    #[default]
+    High = 2,
+}
+
+impl Quality {
+    /// Integrated GPUs start on the low preset.
+    pub fn for_device(device_type: vk::PhysicalDeviceType) -> Self {
+        match device_type {
+            vk::PhysicalDeviceType::DISCRETE_GPU => Self::High,
+            vk::PhysicalDeviceType::INTEGRATED_GPU => Self::Low,
+            _ => Self::Medium,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aurora {
+    /// Colour at the lower edge of the curtains. Components are clamped to
+    /// 0..=1; use `intensity` for brightness.
+    pub color: Vec3,
+    /// 0 turns the aurora off.
+    pub intensity: f32,
+    /// How fast the curtains drift and ripple.
+    pub speed: f32,
+}
+
+impl Default for Aurora {
+    fn default() -> Self {
+        Self {
+            color: Vec3::new(0.1, 1., 0.45),
+            intensity: 0.6,
+            speed: 1.,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Clouds {
+    /// Fraction of the sky covered, from 0 (clear) to 1 (overcast).
+    pub coverage: f32,
+    /// Wind speed in kilometres per second.
+    pub speed: f32,
+    /// Size of the cloud pattern in kilometres.
+    pub scale: f32,
+}
+
+impl Default for Clouds {
+    fn default() -> Self {
+        Self {
+            coverage: 0.4,
+            speed: 0.01,
+            scale: 3.,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Settings {
+    pub quality: Quality,
+    pub aurora: Aurora,
+    pub clouds: Clouds,
+}
+
+/// The atmosphere, sun, moon, stars, aurora and clouds, drawn behind
+/// everything else.
 pub struct Data {
-    pipeline: vk::Pipeline,
-    layout: vk::PipelineLayout,
+    pipeline: Pipeline,
+    transmittance: AllocatedImage,
+    pub transmittance_texture: bindless::Handle<bindless::SampledImage>,
 }
 
-#[repr(C, packed)]
+#[repr(C)]
 #[derive(Debug, Pod, Zeroable, Copy, Clone)]
 pub struct PushConstants {
-    sky_color: Vec3A,
+    inv_view_proj: Mat4,
+    sun_direction: Vec3,
+    transmittance: u32,
+    moon_direction: Vec3,
+    sampler: u32,
+    time: f32,
+    quality: u32,
+    aurora_color: u32,
+    aurora_intensity: f32,
+    aurora_speed: f32,
+    cloud_coverage: f32,
+    cloud_speed: f32,
+    cloud_scale: f32,
 }
 
 impl PushConstants {
-    pub const fn new(sky_color: Vec3A) -> Self {
-        Self { sky_color }
+    /// Directions point from the observer towards the sun and moon in world
+    /// space, where `+Z` is down. `time` is in seconds and drives the aurora
+    /// and clouds.
+    pub fn new(
+        view_proj: Mat4,
+        sun_direction: Vec3,
+        moon_direction: Vec3,
+        transmittance: bindless::Handle<bindless::SampledImage>,
+        sampler: bindless::Handle<bindless::Sampler>,
+        settings: &Settings,
+        time: f32,
+    ) -> Self {
+        let [r, g, b] = settings
+            .aurora
+            .color
+            .clamp(Vec3::ZERO, Vec3::ONE)
+            .to_array()
+            .map(|c| (c * 255.).round() as u32);
+
+        // The shader works with `+Z` up.
+        let to_sky = Vec3::new(1., 1., -1.);
+
+        Self {
+            inv_view_proj: Mat4::from_scale(to_sky) * view_proj.inverse(),
+            sun_direction: (sun_direction * to_sky).normalize_or(Vec3::Z),
+            transmittance: transmittance.index(),
+            moon_direction: (moon_direction * to_sky).normalize_or(Vec3::NEG_Z),
+            sampler: sampler.index(),
+            time,
+            quality: settings.quality as u32,
+            aurora_color: r | (g << 8) | (b << 16),
+            aurora_intensity: settings.aurora.intensity.max(0.),
+            aurora_speed: settings.aurora.speed,
+            cloud_coverage: settings.clouds.coverage.clamp(0., 1.),
+            cloud_speed: settings.clouds.speed,
+            cloud_scale: settings.clouds.scale.max(0.01),
+        }
     }
 }
 
 impl Data {
-    pub fn new(device: &vulkanalia::Device) -> Result<Self, Report> {
-        let layout = {
-            let push_range_contants = [vk::PushConstantRange::builder()
-                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
-                .size(size_of::<PushConstants>() as u32)];
-            let info =
-                vk::PipelineLayoutCreateInfo::builder().push_constant_ranges(&push_range_contants);
-            unsafe { device.create_pipeline_layout(&info, None) }?
+    /// Builds the pipelines and renders the transmittance LUT, blocking until
+    /// it is done.
+    pub fn new(
+        device: &vulkanalia::Device,
+        pipeline_cache: vk::PipelineCache,
+        bindless: &mut Bindless,
+        allocator: &vulkanalia_vma::Allocator,
+        queue: vk::Queue,
+        timeline: &mut Timeline,
+    ) -> Result<Self, Report> {
+        let pipeline = PipelineBuilder::new(&shaders::SKYBOX, c"vs_main", c"fs_main")
+            .set_layout(bindless.layout)
+            .push_constants::<PushConstants>(vk::ShaderStageFlags::FRAGMENT)
+            .build(device, pipeline_cache)?;
+
+        let transmittance_pipeline =
+            PipelineBuilder::new(&shaders::SKYBOX, c"vs_main", c"fs_transmittance")
+                .build(device, pipeline_cache);
+        let transmittance_pipeline = match transmittance_pipeline {
+            Ok(transmittance_pipeline) => transmittance_pipeline,
+            Err(e) => {
+                pipeline.destroy(device);
+                return Err(e);
+            }
         };
-        let pipeline = {
-            let shader = load_shader_module("./assets/shaders/skybox.spv", device)
-                .context("Issue Loading Skybox Shader")?;
-
-            let dynamic_state_info = vk::PipelineDynamicStateCreateInfo::builder()
-                .dynamic_states(&[vk::DynamicState::SCISSOR, vk::DynamicState::VIEWPORT]);
-
-            let input_assembly_info = vk::PipelineInputAssemblyStateCreateInfo::builder()
-                .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
-
-            let rasterization_state_info = vk::PipelineRasterizationStateCreateInfo::builder()
-                .cull_mode(vk::CullModeFlags::BACK)
-                .front_face(vk::FrontFace::CLOCKWISE)
-                .line_width(1.)
-                .polygon_mode(vk::PolygonMode::FILL);
-
-            let viewport_state_info = vk::PipelineViewportStateCreateInfo::builder()
-                .viewport_count(1)
-                .scissor_count(1);
-
-            let color_blend_attachment = vk::PipelineColorBlendAttachmentState::builder()
-                .blend_enable(false)
-                .color_write_mask(
-                    vk::ColorComponentFlags::R
-                        | vk::ColorComponentFlags::G
-                        | vk::ColorComponentFlags::B
-                        | vk::ColorComponentFlags::A,
-                );
-            let color_blend_state_info = vk::PipelineColorBlendStateCreateInfo::builder()
-                .logic_op_enable(false)
-                .logic_op(vk::LogicOp::COPY)
-                .attachments(std::slice::from_ref(&color_blend_attachment));
-
-            let shader_stages = [
-                vk::PipelineShaderStageCreateInfo::builder()
-                    .stage(vk::ShaderStageFlags::VERTEX)
-                    .module(shader)
-                    .name(b"vs_main\0"),
-                vk::PipelineShaderStageCreateInfo::builder()
-                    .stage(vk::ShaderStageFlags::FRAGMENT)
-                    .module(shader)
-                    .name(b"fs_main\0"),
-            ];
-
-            let multisample_state_info = vk::PipelineMultisampleStateCreateInfo::builder()
-                .sample_shading_enable(false)
-                .rasterization_samples(vk::SampleCountFlags::_1)
-                .min_sample_shading(1.)
-                .alpha_to_coverage_enable(false)
-                .alpha_to_one_enable(false);
-
-            let depth_stencil_state_info = vk::PipelineDepthStencilStateCreateInfo::builder()
-                .depth_test_enable(false)
-                .depth_write_enable(false)
-                .depth_compare_op(vk::CompareOp::NEVER)
-                .min_depth_bounds(1.)
-                .max_depth_bounds(1.)
-                .depth_bounds_test_enable(false)
-                .stencil_test_enable(false);
-
-            let mut rendering_create_info = vk::PipelineRenderingCreateInfo::builder()
-                .color_attachment_formats(&[vk::Format::R16G16B16A16_SFLOAT]);
-
-            let vert_info = vk::PipelineVertexInputStateCreateInfo::builder();
-
-            let info = vk::GraphicsPipelineCreateInfo::builder()
-                .dynamic_state(&dynamic_state_info)
-                .input_assembly_state(&input_assembly_info)
-                .rasterization_state(&rasterization_state_info)
-                .viewport_state(&viewport_state_info)
-                .color_blend_state(&color_blend_state_info)
-                .stages(&shader_stages)
-                .vertex_input_state(&vert_info)
-                .depth_stencil_state(&depth_stencil_state_info)
-                .multisample_state(&multisample_state_info)
-                .push_next(&mut rendering_create_info)
-                .layout(layout);
-
-            let create_infos = [info];
-            let pipeline = unsafe {
-                device
-                    .create_graphics_pipelines(vk::PipelineCache::null(), &create_infos, None)
-                    .expect("Failed to create graphics pipeline")
-                    .0[0]
-            };
 
+        let transmittance = AllocatedImage::new(
+            vk::Format::R16G16B16A16_SFLOAT,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+            vk::Extent3D {
+                width: TRANSMITTANCE_SIZE.width,
+                height: TRANSMITTANCE_SIZE.height,
+                depth: 1,
+            },
+            vk::ImageAspectFlags::COLOR,
+            allocator,
+            device,
+        );
+
+        immediate_submit(device, queue, timeline, |cmd| {
+            transition_image(
+                cmd,
+                transmittance.image,
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                device,
+            );
+            transmittance_pipeline.begin_fullscreen(
+                device,
+                cmd,
+                transmittance.view,
+                TRANSMITTANCE_SIZE,
+            );
             unsafe {
-                device.destroy_shader_module(shader, None);
+                device.cmd_draw(cmd, 3, 1, 0, 0);
+                device.cmd_end_rendering(cmd);
             }
+            transition_image(
+                cmd,
+                transmittance.image,
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                device,
+            );
+        });
+        transmittance_pipeline.destroy(device);
 
-            pipeline
-        };
+        let transmittance_texture = bindless.add_sampled_image(
+            device,
+            transmittance.view,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        );
 
-        Ok(Self { layout, pipeline })
+        Ok(Self {
+            pipeline,
+            transmittance,
+            transmittance_texture,
+        })
     }
 
     pub fn draw(
         &self,
         device: &vulkanalia::Device,
         cmd: vk::CommandBuffer,
-        view: vk::ImageView,
-        draw_extent: vk::Extent2D,
+        bindless: &Bindless,
         constants: PushConstants,
     ) {
-        let color = vk::RenderingAttachmentInfo::builder()
-            .image_view(view)
-            .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-            .load_op(vk::AttachmentLoadOp::CLEAR)
-            .store_op(vk::AttachmentStoreOp::STORE);
-        let color = [color];
-        let rend_info = vk::RenderingInfo::builder()
-            .render_area(
-                vk::Rect2D::builder()
-                    .extent(draw_extent)
-                    .offset(vk::Offset2D { x: 0, y: 0 }),
-            )
-            .color_attachments(&color)
-            .layer_count(1);
-
         unsafe {
-            device.cmd_begin_rendering(cmd, &rend_info);
-            device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
-
-            device.cmd_set_viewport(
-                cmd,
-                0,
-                &[vk::Viewport {
-                    width: draw_extent.width as f32,
-                    height: draw_extent.height as f32,
-                    x: 0.,
-                    y: 0.,
-                    min_depth: 0.,
-                    max_depth: 1.,
-                }],
-            );
-            device.cmd_set_scissor(
-                cmd,
-                0,
-                &[vk::Rect2D::builder()
-                    .extent(draw_extent)
-                    .offset(vk::Offset2D { x: 0, y: 0 })],
-            );
-
+            device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, self.pipeline.pipeline);
+        }
+        bindless.bind(
+            device,
+            cmd,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline.layout,
+        );
+        unsafe {
             device.cmd_push_constants(
                 cmd,
-                self.layout,
+                self.pipeline.layout,
                 vk::ShaderStageFlags::FRAGMENT,
                 0,
                 bytemuck::bytes_of(&constants),
@@ -186,10 +263,19 @@ impl Data {
         }
     }
 
-    pub fn destroy(&mut self, device: &vulkanalia::Device) {
-        unsafe {
-            device.destroy_pipeline(self.pipeline, None);
-            device.destroy_pipeline_layout(self.layout, None);
-        }
+    /// Bytes of device memory held by the transmittance LUT.
+    pub fn allocated_bytes(&self, allocator: &vulkanalia_vma::Allocator) -> u64 {
+        self.transmittance.size(allocator)
+    }
+
+    pub fn destroy(
+        &mut self,
+        bindless: &mut Bindless,
+        allocator: &vulkanalia_vma::Allocator,
+        device: &vulkanalia::Device,
+    ) {
+        bindless.remove_sampled_image(self.transmittance_texture);
+        self.transmittance.flush(device, allocator);
+        self.pipeline.destroy(device);
     }
 }
@@ -3,6 +3,19 @@ use vulkanalia::vk::{
 };
 use vulkanalia::vk::{DeviceV1_0, HasBuilder};
 
+use super::allocations::DescriptorAllocator;
+
+/// Swapchain images are UNORM, so the final pass encodes sRGB itself.
+pub const FORMAT: vk::Format = vk::Format::B8G8R8A8_UNORM;
+
+/// Descriptors of each type per set for the per-frame descriptor allocators.
+/// Only the depth pyramid levels are bound outside the bindless set.
+const FRAME_DESCRIPTOR_RATIOS: [(vk::DescriptorType, f32); 1] =
+    [(vk::DescriptorType::STORAGE_IMAGE, 1.)];
+/// Sets in the first pool of each frame, enough for the depth pyramid of a
+/// 4K render extent.
+const FRAME_DESCRIPTOR_SETS: u32 = 16;
+
 pub struct SwapchainData {
     pub image_views: Vec<vk::ImageView>,
     pub swapchain: vk::SwapchainKHR,
@@ -33,7 +46,7 @@ impl SwapchainData {
         let image_format = &surface_formats
             .unwrap()
             .into_iter()
-            .find(|format| format.format == vk::Format::B8G8R8A8_UNORM)
+            .find(|format| format.format == FORMAT)
             .unwrap();
 
         let extent = vk::Extent2D {
@@ -78,7 +91,7 @@ impl SwapchainData {
             let imageview_create_info = vk::ImageViewCreateInfo::builder()
                 .image(*image)
                 .view_type(vk::ImageViewType::_2D)
-                .format(vk::Format::B8G8R8A8_UNORM)
+                .format(FORMAT)
                 .subresource_range(subresource_range);
             let imageview =
                 unsafe { device.create_image_view(&imageview_create_info, None) }.unwrap();
@@ -112,12 +125,14 @@ impl SwapchainData {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Debug)]
 pub struct FrameData {
-    pub render_fence: vk::Fence,
     pub swapchain_semaphore: vk::Semaphore,
     pub pool: vk::CommandPool,
     pub buf: vk::CommandBuffer,
+    /// Sets that only live for one frame. Reset once the GPU has finished the
+    /// frame.
+    pub descriptors: DescriptorAllocator,
 }
 
 impl FrameData {
@@ -144,21 +159,17 @@ impl FrameData {
             .first()
             .map_or_else(|| panic!("Unable to allocate command buffers"), |s| *s);
 
-        let render_fence = unsafe {
-            device.create_fence(
-                &vk::FenceCreateInfo::builder().flags(vk::FenceCreateFlags::SIGNALED),
-                None,
-            )
-        }
-        .unwrap();
         let swapchain_semaphore =
             unsafe { device.create_semaphore(&vk::SemaphoreCreateInfo::default(), None) }.unwrap();
 
+        let descriptors =
+            DescriptorAllocator::new(device, FRAME_DESCRIPTOR_SETS, &FRAME_DESCRIPTOR_RATIOS);
+
         Self {
-            render_fence,
             swapchain_semaphore,
             pool,
             buf,
+            descriptors,
         }
     }
 }
@@ -0,0 +1,60 @@
+//! The images a frame is drawn through on its way to the swapchain, created
+//! from the frame graph's transient pool each frame.
+//!
+//! `draw`, `depth` and `ldr` are sized by the render scale, and dynamic
+//! resolution may only use the top left part of them. `upscaled` matches the
+//! swapchain.
+
+use vulkanalia::vk;
+
+use super::graph::ImageDesc;
+
+pub const DRAW_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+/// Tonemapped images hold sRGB encoded values in a UNORM format, which is
+/// what the upscaler expects to filter.
+pub const LDR_FORMAT: vk::Format = vk::Format::R8G8B8A8_UNORM;
+pub const DEPTH_FORMAT: vk::Format = vk::Format::D32_SFLOAT;
+
+/// HDR scene colour.
+pub fn draw(render_extent: vk::Extent2D) -> ImageDesc {
+    ImageDesc {
+        format: DRAW_FORMAT,
+        extent: render_extent,
+        usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+    }
+}
+
+/// Scene depth, cleared by the chunk pass.
+pub fn depth(render_extent: vk::Extent2D) -> ImageDesc {
+    ImageDesc {
+        format: DEPTH_FORMAT,
+        extent: render_extent,
+        usage: vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+    }
+}
+
+/// Tonemapped scene at render resolution.
+pub fn ldr(render_extent: vk::Extent2D) -> ImageDesc {
+    ImageDesc {
+        format: LDR_FORMAT,
+        extent: render_extent,
+        usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+    }
+}
+
+/// Upscaled scene at display resolution, before sharpening.
+pub fn upscaled(display_extent: vk::Extent2D) -> ImageDesc {
+    ImageDesc {
+        format: LDR_FORMAT,
+        extent: display_extent,
+        usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+    }
+}
+
+/// `extent` scaled by `scale`, never smaller than one pixel.
+pub fn scale_extent(extent: vk::Extent2D, scale: f32) -> vk::Extent2D {
+    vk::Extent2D {
+        width: ((extent.width as f32 * scale).round() as u32).max(1),
+        height: ((extent.height as f32 * scale).round() as u32).max(1),
+    }
+}
@@ -0,0 +1,131 @@
+//! GPU progress tracked on one timeline semaphore.
+//!
+//! Every submission to the queue signals the next value of the semaphore,
+//! whether it is a frame or a blocking upload. Signals complete in submission
+//! order, so reaching a value means everything submitted before it has
+//! finished as well. Frames remember the value they signal, which is what
+//! "the GPU has finished frame N" is answered from.
+
+use vulkanalia::vk::{self, DeviceV1_0, DeviceV1_2, HasBuilder};
+
+/// Frames whose values are kept. Older frames must have finished before a
+/// new one is submitted.
+const FRAMES_IN_FLIGHT: u64 = 2;
+
+pub struct Timeline {
+    semaphore: vk::Semaphore,
+    /// The last value handed out.
+    value: u64,
+    /// Frames submitted so far.
+    frames: u64,
+    /// The value each of the last [`FRAMES_IN_FLIGHT`] frames signals, by
+    /// frame slot.
+    frame_values: [u64; FRAMES_IN_FLIGHT as usize],
+}
+
+impl Timeline {
+    pub fn new(device: &vulkanalia::Device) -> Self {
+        let mut type_info = vk::SemaphoreTypeCreateInfo::builder()
+            .semaphore_type(vk::SemaphoreType::TIMELINE)
+            .initial_value(0);
+        let semaphore = unsafe {
+            device.create_semaphore(
+                &vk::SemaphoreCreateInfo::builder().push_next(&mut type_info),
+                None,
+            )
+        }
+        .unwrap();
+        Self {
+            semaphore,
+            value: 0,
+            frames: 0,
+            frame_values: [0; FRAMES_IN_FLIGHT as usize],
+        }
+    }
+
+    /// A value for the next submission to signal. Submissions have to signal
+    /// their values in the order they were handed out.
+    pub const fn next_value(&mut self) -> u64 {
+        self.value += 1;
+        self.value
+    }
+
+    /// [`Timeline::next_value`] for frame `frame`, which has to be the one
+    /// after the last frame submitted.
+    pub fn next_frame_value(&mut self, frame: u64) -> u64 {
+        debug_assert_eq!(frame, self.frames, "frames are submitted in order");
+        let value = self.next_value();
+        self.frame_values[(frame % FRAMES_IN_FLIGHT) as usize] = value;
+        self.frames = frame + 1;
+        value
+    }
+
+    /// Signals `value` once the commands before `stages` have finished.
+    pub fn signal_info(
+        &self,
+        value: u64,
+        stages: vk::PipelineStageFlags2,
+    ) -> vk::SemaphoreSubmitInfo {
+        vk::SemaphoreSubmitInfo::builder()
+            .semaphore(self.semaphore)
+            .value(value)
+            .stage_mask(stages)
+            .build()
+    }
+
+    /// The last value the GPU has signalled.
+    pub fn completed(&self, device: &vulkanalia::Device) -> u64 {
+        unsafe { device.get_semaphore_counter_value(self.semaphore) }.unwrap()
+    }
+
+    /// Block until the GPU has signalled `value` or `timeout` nanoseconds
+    /// have passed, returning whether it got there.
+    pub fn wait(&self, device: &vulkanalia::Device, value: u64, timeout: u64) -> bool {
+        let semaphores = [self.semaphore];
+        let values = [value];
+        let info = vk::SemaphoreWaitInfo::builder()
+            .semaphores(&semaphores)
+            .values(&values);
+        unsafe { device.wait_semaphores(&info, timeout) }.unwrap() == vk::SuccessCode::SUCCESS
+    }
+
+    /// The value frame `frame` signals, or `None` if it has not been
+    /// submitted. Frames too old to be kept have finished, so any value
+    /// will do for them.
+    const fn frame_value(&self, frame: u64) -> Option<u64> {
+        if frame >= self.frames {
+            None
+        } else if frame + FRAMES_IN_FLIGHT < self.frames {
+            Some(0)
+        } else {
+            Some(self.frame_values[(frame % FRAMES_IN_FLIGHT) as usize])
+        }
+    }
+
+    /// Block until the GPU has finished frame `frame` or `timeout`
+    /// nanoseconds have passed, returning whether it finished. Frames that
+    /// have not been submitted never finish.
+    pub fn wait_for_frame(&self, device: &vulkanalia::Device, frame: u64, timeout: u64) -> bool {
+        self.frame_value(frame)
+            .is_some_and(|value| self.wait(device, value, timeout))
+    }
+
+    /// How many frames the GPU has finished, which are all the frames before
+    /// the returned one.
+    pub fn frames_finished(&self, device: &vulkanalia::Device) -> u64 {
+        let completed = self.completed(device);
+        let oldest = self.frames.saturating_sub(FRAMES_IN_FLIGHT);
+        (oldest..self.frames)
+            .rev()
+            .find(|&frame| {
+                self.frame_value(frame)
+                    .is_some_and(|value| value <= completed)
+            })
+            .map_or(oldest, |frame| frame + 1)
+    }
+
+    /// Expects the device to be idle.
+    pub fn destroy(&self, device: &vulkanalia::Device) {
+        unsafe { device.destroy_semaphore(self.semaphore, None) };
+    }
+}
@@ -0,0 +1,118 @@
+use rootcause::Report;
+
+use super::bindless::{self, Bindless};
+use super::pipeline::{Pipeline, PipelineBuilder};
+use super::shaders;
+use bytemuck::{Pod, Zeroable};
+use vulkanalia::vk::{self, DeviceV1_0};
+
+/// Curve used to fit HDR colour into the displayable range. The values match
+/// the constants in `tonemap.slang`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[repr(u32)]
+pub enum Tonemapper {
+    Reinhard = 0,
+    #[default]
+    Aces = 1,
+    AgX = 2,
+}
+
+impl Tonemapper {
+    /// The tonemapper after this one, wrapping around.
+    pub const fn next(self) -> Self {
+        match self {
+            Self::Reinhard => Self::Aces,
+            Self::Aces => Self::AgX,
+            Self::AgX => Self::Reinhard,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Settings {
+    pub tonemapper: Tonemapper,
+    /// Linear multiplier applied before tonemapping.
+    pub exposure: f32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            tonemapper: Tonemapper::default(),
+            exposure: 1.,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Pod, Zeroable, Copy, Clone)]
+pub struct PushConstants {
+    exposure: f32,
+    tonemapper: u32,
+    image: u32,
+}
+
+impl PushConstants {
+    pub const fn new(settings: Settings, image: bindless::Handle<bindless::SampledImage>) -> Self {
+        Self {
+            exposure: settings.exposure,
+            tonemapper: settings.tonemapper as u32,
+            image: image.index(),
+        }
+    }
+}
+
+/// Fullscreen pass that tonemaps the HDR draw image into an sRGB encoded image
+/// of the same size, ready for upscaling.
+pub struct Data {
+    pipeline: Pipeline,
+}
+
+impl Data {
+    pub fn new(
+        device: &vulkanalia::Device,
+        pipeline_cache: vk::PipelineCache,
+        bindless_layout: vk::DescriptorSetLayout,
+        format: vk::Format,
+    ) -> Result<Self, Report> {
+        let pipeline = PipelineBuilder::new(&shaders::TONEMAP, c"vs_main", c"fs_main")
+            .color_formats(&[format])
+            .set_layout(bindless_layout)
+            .push_constants::<PushConstants>(vk::ShaderStageFlags::FRAGMENT)
+            .build(device, pipeline_cache)?;
+
+        Ok(Self { pipeline })
+    }
+
+    pub fn draw(
+        &self,
+        device: &vulkanalia::Device,
+        cmd: vk::CommandBuffer,
+        bindless: &Bindless,
+        constants: PushConstants,
+    ) {
+        unsafe {
+            device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, self.pipeline.pipeline);
+        }
+        bindless.bind(
+            device,
+            cmd,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline.layout,
+        );
+        unsafe {
+            device.cmd_push_constants(
+                cmd,
+                self.pipeline.layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                bytemuck::bytes_of(&constants),
+            );
+            device.cmd_draw(cmd, 3, 1, 0, 0);
+        }
+    }
+
+    pub fn destroy(&mut self, device: &vulkanalia::Device) {
+        self.pipeline.destroy(device);
+    }
+}
@@ -0,0 +1,176 @@
+//! Spatial upscaling from render resolution to display resolution, in the
+//! style of FSR 1: an edge adaptive upsample (EASU) followed by contrast
+//! adaptive sharpening (RCAS) straight into the swapchain.
+
+use rootcause::Report;
+
+use super::bindless::{self, Bindless};
+use super::pipeline::{Pipeline, PipelineBuilder};
+use super::shaders;
+use bytemuck::{Pod, Zeroable};
+use vulkanalia::vk::{self, DeviceV1_0};
+
+mod dynamic_resolution;
+
+pub use dynamic_resolution::DynamicResolution;
+
+pub const MIN_RENDER_SCALE: f32 = 0.5;
+pub const MAX_RENDER_SCALE: f32 = 2.;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Settings {
+    /// Sharpening strength from 0 (off) to 1.
+    pub sharpness: f32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self { sharpness: 0.8 }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Pod, Zeroable, Copy, Clone)]
+pub struct EasuPushConstants {
+    image: u32,
+    sampler: u32,
+    input_size: [f32; 2],
+    output_size: [f32; 2],
+    image_size: [f32; 2],
+}
+
+impl EasuPushConstants {
+    /// `input` is the part of `image_size` that holds this frame.
+    pub const fn new(
+        image: bindless::Handle<bindless::SampledImage>,
+        sampler: bindless::Handle<bindless::Sampler>,
+        input: vk::Extent2D,
+        output: vk::Extent2D,
+        image_size: vk::Extent2D,
+    ) -> Self {
+        Self {
+            image: image.index(),
+            sampler: sampler.index(),
+            input_size: [input.width as f32, input.height as f32],
+            output_size: [output.width as f32, output.height as f32],
+            image_size: [image_size.width as f32, image_size.height as f32],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Pod, Zeroable, Copy, Clone)]
+pub struct RcasPushConstants {
+    image: u32,
+    sharpness: f32,
+}
+
+impl RcasPushConstants {
+    pub const fn new(image: bindless::Handle<bindless::SampledImage>, settings: Settings) -> Self {
+        Self {
+            image: image.index(),
+            sharpness: settings.sharpness.clamp(0., 1.),
+        }
+    }
+}
+
+pub struct Data {
+    easu: Pipeline,
+    rcas: Pipeline,
+}
+
+impl Data {
+    /// `upscaled_format` is the format of the intermediate display resolution
+    /// image and `output_format` that of the swapchain.
+    pub fn new(
+        device: &vulkanalia::Device,
+        pipeline_cache: vk::PipelineCache,
+        bindless_layout: vk::DescriptorSetLayout,
+        upscaled_format: vk::Format,
+        output_format: vk::Format,
+    ) -> Result<Self, Report> {
+        let easu = PipelineBuilder::new(&shaders::EASU, c"vs_main", c"fs_main")
+            .color_formats(&[upscaled_format])
+            .set_layout(bindless_layout)
+            .push_constants::<EasuPushConstants>(vk::ShaderStageFlags::FRAGMENT)
+            .build(device, pipeline_cache)?;
+
+        let rcas = PipelineBuilder::new(&shaders::RCAS, c"vs_main", c"fs_main")
+            .color_formats(&[output_format])
+            .set_layout(bindless_layout)
+            .push_constants::<RcasPushConstants>(vk::ShaderStageFlags::FRAGMENT)
+            .build(device, pipeline_cache);
+        let rcas = match rcas {
+            Ok(rcas) => rcas,
+            Err(e) => {
+                easu.destroy(device);
+                return Err(e);
+            }
+        };
+
+        Ok(Self { easu, rcas })
+    }
+
+    pub fn draw_easu(
+        &self,
+        device: &vulkanalia::Device,
+        cmd: vk::CommandBuffer,
+        bindless: &Bindless,
+        constants: EasuPushConstants,
+    ) {
+        draw(
+            &self.easu,
+            device,
+            cmd,
+            bindless,
+            bytemuck::bytes_of(&constants),
+        );
+    }
+
+    pub fn draw_rcas(
+        &self,
+        device: &vulkanalia::Device,
+        cmd: vk::CommandBuffer,
+        bindless: &Bindless,
+        constants: RcasPushConstants,
+    ) {
+        draw(
+            &self.rcas,
+            device,
+            cmd,
+            bindless,
+            bytemuck::bytes_of(&constants),
+        );
+    }
+
+    pub fn destroy(&mut self, device: &vulkanalia::Device) {
+        self.easu.destroy(device);
+        self.rcas.destroy(device);
+    }
+}
+
+fn draw(
+    pipeline: &Pipeline,
+    device: &vulkanalia::Device,
+    cmd: vk::CommandBuffer,
+    bindless: &Bindless,
+    constants: &[u8],
+) {
+    unsafe { device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, pipeline.pipeline) };
+    bindless.bind(
+        device,
+        cmd,
+        vk::PipelineBindPoint::GRAPHICS,
+        pipeline.layout,
+    );
+    unsafe {
+        device.cmd_push_constants(
+            cmd,
+            pipeline.layout,
+            vk::ShaderStageFlags::FRAGMENT,
+            0,
+            constants,
+        );
+        device.cmd_draw(cmd, 3, 1, 0, 0);
+    }
+}
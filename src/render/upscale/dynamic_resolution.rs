@@ -0,0 +1,58 @@
+use std::time::Duration;
+
+/// Scales are snapped to multiples of this to stop the resolution jittering
+/// between nearly identical sizes.
+const STEP: f32 = 1. / 32.;
+/// Largest change in scale per frame.
+const MAX_CHANGE: f32 = 0.05;
+/// Weight of the newest frame in the moving average of frame times.
+const SMOOTHING: f32 = 0.1;
+
+/// Lowers the share of the draw image that is rendered to when frames take
+/// longer than `target`, and raises it again when there is headroom.
+///
+/// Frame times include waiting on present, so with FIFO presentation a
+/// target at or below the refresh interval only ever lowers the scale once
+/// frames are being missed.
+#[derive(Debug, Clone)]
+pub struct DynamicResolution {
+    pub target: Duration,
+    pub min_scale: f32,
+    scale: f32,
+    /// Moving average of the frame time scaled up to a full size frame.
+    full_frame_time: Option<f32>,
+}
+
+impl DynamicResolution {
+    pub const fn new(target: Duration) -> Self {
+        Self {
+            target,
+            min_scale: 0.5,
+            scale: 1.,
+            full_frame_time: None,
+        }
+    }
+
+    /// Fraction of the draw image to render along each axis.
+    pub const fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    /// Feed the duration of the last frame and get the scale for the next.
+    pub fn update(&mut self, frame_time: Duration) -> f32 {
+        // Cost goes with pixel count, which goes with the square of the scale.
+        let full = frame_time.as_secs_f32() / (self.scale * self.scale);
+        let average = match self.full_frame_time {
+            Some(average) => average + (full - average) * SMOOTHING,
+            None => full,
+        };
+        self.full_frame_time = Some(average);
+
+        let ideal = (self.target.as_secs_f32() / average).sqrt();
+        if (ideal - self.scale).abs() >= STEP {
+            let next = self.scale + (ideal - self.scale).clamp(-MAX_CHANGE, MAX_CHANGE);
+            self.scale = ((next / STEP).round() * STEP).clamp(self.min_scale, 1.);
+        }
+        self.scale
+    }
+}
@@ -1,54 +1,7 @@
 use rootcause::Report;
-use vulkanalia::vk::{self, DeviceV1_0, DeviceV1_3, HasBuilder};
+use vulkanalia::vk::{self, DeviceV1_0, DeviceV1_3, Handle, HasBuilder};
 
-pub fn copy_image_to_image(
-    cmd: vk::CommandBuffer,
-    source: vk::Image,
-    destination: vk::Image,
-    src_size: vk::Extent2D,
-    dst_size: vk::Extent2D,
-    device: &vulkanalia::Device,
-) {
-    // TODO: change to device.cmd_copy_image2
-    let mut blit_region = vk::ImageBlit2::builder()
-        .src_subresource(
-            vk::ImageSubresourceLayers::builder()
-                .aspect_mask(vk::ImageAspectFlags::COLOR)
-                .base_array_layer(0)
-                .layer_count(1)
-                .mip_level(0),
-        )
-        .dst_subresource(
-            vk::ImageSubresourceLayers::builder()
-                .aspect_mask(vk::ImageAspectFlags::COLOR)
-                .base_array_layer(0)
-                .layer_count(1)
-                .mip_level(0),
-        );
-
-    blit_region.src_offsets[1] = vk::Offset3D::builder()
-        .x(src_size.width as i32)
-        .y(src_size.height as i32)
-        .z(1)
-        .build();
-    blit_region.dst_offsets[1] = vk::Offset3D::builder()
-        .x(dst_size.width as i32)
-        .y(dst_size.height as i32)
-        .z(1)
-        .build();
-
-    let binding = [blit_region];
-
-    let blit_info = vk::BlitImageInfo2::builder()
-        .dst_image(destination)
-        .dst_image_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
-        .src_image(source)
-        .src_image_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
-        .filter(vk::Filter::LINEAR)
-        .regions(&binding);
-
-    unsafe { device.cmd_blit_image2(cmd, &blit_info) };
-}
+use super::timeline::Timeline;
 
 pub fn transition_image(
     cmd: vk::CommandBuffer,
@@ -63,7 +16,8 @@ pub fn transition_image(
         .level_count(vk::REMAINING_MIP_LEVELS)
         .layer_count(vk::REMAINING_ARRAY_LAYERS);
 
-    let subresource_range = if new_layout == vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL {
+    let depth = vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL;
+    let subresource_range = if new_layout == depth || curr_layout == depth {
         subresource_range.aspect_mask(vk::ImageAspectFlags::DEPTH)
     } else {
         subresource_range.aspect_mask(vk::ImageAspectFlags::COLOR)
@@ -86,19 +40,85 @@ pub fn transition_image(
     };
 }
 
+/// A global memory barrier, for buffers and images whose layout stays put.
+pub fn memory_barrier(
+    cmd: vk::CommandBuffer,
+    src: (vk::PipelineStageFlags2, vk::AccessFlags2),
+    dst: (vk::PipelineStageFlags2, vk::AccessFlags2),
+    device: &vulkanalia::Device,
+) {
+    let barrier = vk::MemoryBarrier2::builder()
+        .src_stage_mask(src.0)
+        .src_access_mask(src.1)
+        .dst_stage_mask(dst.0)
+        .dst_access_mask(dst.1);
+    unsafe {
+        device.cmd_pipeline_barrier2(
+            cmd,
+            &vk::DependencyInfo::builder().memory_barriers(&[barrier]),
+        );
+    };
+}
+
 pub fn load_shader_module(
-    path: &str,
+    code: &[u8],
     device: &vulkanalia::Device,
 ) -> Result<vk::ShaderModule, Report> {
-    use std::io::Read;
-    let mut file = std::fs::File::open(path)?;
-    let mut buf = Vec::new();
-    _ = file.read_to_end(&mut buf);
-
-    let code = vulkanalia::bytecode::Bytecode::new(&buf).expect(&format!("Invalid Shader: {path}"));
+    let code = vulkanalia::bytecode::Bytecode::new(code)?;
 
     let create_info = vk::ShaderModuleCreateInfo::builder()
         .code(code.code())
         .code_size(code.code_size());
     unsafe { device.create_shader_module(&create_info, None) }.map_err(|x| x.into())
 }
+
+/// Record commands with `record`, submit them to `queue` and block until
+/// `timeline` reaches the value they signal.
+pub fn immediate_submit(
+    device: &vulkanalia::Device,
+    queue: vk::Queue,
+    timeline: &mut Timeline,
+    record: impl FnOnce(vk::CommandBuffer),
+) {
+    unsafe {
+        let cmd_pool = device
+            .create_command_pool(&vk::CommandPoolCreateInfo::default(), None)
+            .unwrap();
+        let cmd = device
+            .allocate_command_buffers(
+                &vk::CommandBufferAllocateInfo::builder()
+                    .command_pool(cmd_pool)
+                    .level(vk::CommandBufferLevel::PRIMARY)
+                    .command_buffer_count(1),
+            )
+            .unwrap()[0];
+        device
+            .begin_command_buffer(
+                cmd,
+                &vk::CommandBufferBeginInfo::builder()
+                    .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+            )
+            .unwrap();
+
+        record(cmd);
+
+        device.end_command_buffer(cmd).unwrap();
+        let value = timeline.next_value();
+        device
+            .queue_submit2(
+                queue,
+                &[vk::SubmitInfo2::builder()
+                    .command_buffer_infos(&[vk::CommandBufferSubmitInfo::builder()
+                        .command_buffer(cmd)
+                        .device_mask(0)])
+                    .signal_semaphore_infos(&[
+                        timeline.signal_info(value, vk::PipelineStageFlags2::ALL_COMMANDS)
+                    ])],
+                vk::Fence::null(),
+            )
+            .unwrap();
+        timeline.wait(device, value, u64::MAX);
+
+        device.destroy_command_pool(cmd_pool, None);
+    }
+}
@@ -0,0 +1,244 @@
+//! Clock and calendar for the world: where the sun and moon are, which season
+//! it is and how cold it gets.
+//!
+//! Directions are in world space, where `+X` is east, `+Y` is north and `+Z`
+//! is down, and point from the world towards the body.
+
+use std::f32::consts::TAU;
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+use glam::Vec3;
+
+const SAVE_MAGIC: [u8; 4] = *b"TIME";
+const SAVE_VERSION: u32 = 1;
+
+/// Tilt of the planet's axis, which sets how far the sun moves between
+/// solstices.
+const AXIAL_TILT: f32 = 23.44_f32.to_radians();
+/// Days from one new moon to the next.
+const LUNAR_MONTH: f64 = 8.;
+/// Yearly mean of the fallout-darkened climate, in degrees Celsius.
+const MEAN_TEMPERATURE: f32 = -28.;
+/// Difference between the yearly mean and midsummer at the poles.
+const SEASONAL_SWING: f32 = 18.;
+/// Difference between the daily mean and the afternoon peak.
+const DAILY_SWING: f32 = 6.;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Season {
+    Spring,
+    Summer,
+    Autumn,
+    Winter,
+}
+
+/// The light that casts shadows: the sun by day and the moon by night.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DirectionalLight {
+    pub direction: Vec3,
+    /// Linear colour scaled by intensity, 1 for the sun at its highest.
+    pub color: Vec3,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimeOfDay {
+    /// Real time one day takes at a `scale` of 1.
+    pub day_length: Duration,
+    /// Days in a year, which cycles through the four seasons.
+    pub year_length: u32,
+    /// Degrees north of the equator. Negative values are in the south.
+    pub latitude: f32,
+    /// Multiplier on how fast time passes.
+    pub scale: f32,
+    pub paused: bool,
+    /// Days since the world began. The fraction is the time of day, with 0
+    /// at midnight.
+    days: f64,
+}
+
+impl TimeOfDay {
+    /// Starts on the first morning of spring.
+    pub const fn new(day_length: Duration, latitude: f32) -> Self {
+        Self {
+            day_length,
+            year_length: 48,
+            latitude,
+            scale: 1.,
+            paused: false,
+            days: 0.3,
+        }
+    }
+
+    /// Advance by `dt` seconds of real time, unless paused.
+    pub fn advance(&mut self, dt: f32) {
+        if self.paused || self.day_length.is_zero() {
+            return;
+        }
+        self.days += f64::from(dt * self.scale) / self.day_length.as_secs_f64();
+    }
+
+    /// Whole days since the world began.
+    pub fn day(&self) -> u64 {
+        self.days.max(0.) as u64
+    }
+
+    /// Fraction of the current day, 0 at midnight and 0.5 at noon.
+    pub fn time(&self) -> f32 {
+        self.days.rem_euclid(1.) as f32
+    }
+
+    /// Jump to `time` on the current day, keeping the date.
+    pub fn set_time(&mut self, time: f32) {
+        self.days = self.days.floor() + f64::from(time.rem_euclid(1.));
+    }
+
+    /// Fraction of the year, 0 at the spring equinox of the northern
+    /// hemisphere.
+    pub fn year_fraction(&self) -> f32 {
+        let year_length = f64::from(self.year_length.max(1));
+        (self.days.rem_euclid(year_length) / year_length) as f32
+    }
+
+    /// Season at the observer's latitude.
+    pub fn season(&self) -> Season {
+        let mut quarter = (self.year_fraction() * 4.) as u32;
+        if self.latitude < 0. {
+            quarter += 2;
+        }
+        match quarter % 4 {
+            0 => Season::Spring,
+            1 => Season::Summer,
+            2 => Season::Autumn,
+            _ => Season::Winter,
+        }
+    }
+
+    /// Fraction of the lunar month, 0 at new moon and 0.5 at full moon.
+    pub fn moon_phase(&self) -> f32 {
+        (self.days / LUNAR_MONTH).rem_euclid(1.) as f32
+    }
+
+    pub fn sun_direction(&self) -> Vec3 {
+        let declination = AXIAL_TILT * (self.year_fraction() * TAU).sin();
+        let hour_angle = (self.time() - 0.5) * TAU;
+        self.direction(declination, hour_angle)
+    }
+
+    /// The moon trails the sun by its phase and sits on the opposite side of
+    /// the ecliptic when full.
+    pub fn moon_direction(&self) -> Vec3 {
+        let phase = self.moon_phase();
+        let declination = AXIAL_TILT * ((self.year_fraction() + phase) * TAU).sin();
+        let hour_angle = (self.time() - 0.5 - phase) * TAU;
+        self.direction(declination, hour_angle)
+    }
+
+    /// Sunlight reaching a flat panel, 0 at night and 1 with the sun overhead.
+    pub fn sun_intensity(&self) -> f32 {
+        (-self.sun_direction().z).max(0.)
+    }
+
+    /// The sun while it is up, otherwise the moon dimmed by its phase.
+    pub fn light(&self) -> DirectionalLight {
+        let sun = self.sun_direction();
+        let elevation = -sun.z;
+        if elevation > 0. {
+            // Redder and dimmer through the thicker air near the horizon.
+            let warmth = smoothstep(0., 0.3, elevation);
+            let color = Vec3::new(1., 0.45, 0.2).lerp(Vec3::ONE, warmth);
+            return DirectionalLight {
+                direction: sun,
+                color: color * smoothstep(0., 0.1, elevation),
+            };
+        }
+
+        let moon = self.moon_direction();
+        let lit = (1. - (self.moon_phase() * TAU).cos()) * 0.5;
+        DirectionalLight {
+            direction: moon,
+            color: Vec3::new(0.6, 0.7, 1.) * 0.03 * lit * smoothstep(0., 0.1, -moon.z),
+        }
+    }
+
+    /// Air temperature in degrees Celsius, coldest before dawn and in the
+    /// depths of winter.
+    pub fn temperature(&self) -> f32 {
+        // Seasons lag the sun by about a month and grow with latitude.
+        let season = ((self.year_fraction() - 0.125) * TAU).sin() * self.latitude.signum();
+        let seasonal = season * SEASONAL_SWING * (self.latitude.abs() / 90.).min(1.);
+        // The warmest part of the day is mid afternoon.
+        let daily = ((self.time() - 0.625) * TAU).cos() * DAILY_SWING;
+        MEAN_TEMPERATURE + seasonal + daily
+    }
+
+    /// Direction to a body at `declination` and `hour_angle` (0 when it is
+    /// due south at its highest).
+    fn direction(&self, declination: f32, hour_angle: f32) -> Vec3 {
+        let latitude = self.latitude.to_radians();
+        let (sin_lat, cos_lat) = latitude.sin_cos();
+        let (sin_dec, cos_dec) = declination.sin_cos();
+        let (sin_hour, cos_hour) = hour_angle.sin_cos();
+
+        let up = sin_lat * sin_dec + cos_lat * cos_dec * cos_hour;
+        let east = -cos_dec * sin_hour;
+        let north = cos_lat * sin_dec - sin_lat * cos_dec * cos_hour;
+        Vec3::new(east, north, -up).normalize()
+    }
+
+    pub fn save(&self, w: &mut impl Write) -> io::Result<()> {
+        w.write_all(&SAVE_MAGIC)?;
+        w.write_all(&SAVE_VERSION.to_le_bytes())?;
+        w.write_all(&self.days.to_le_bytes())?;
+        w.write_all(&self.day_length.as_secs_f64().to_le_bytes())?;
+        w.write_all(&self.year_length.to_le_bytes())?;
+        w.write_all(&self.latitude.to_le_bytes())?;
+        w.write_all(&self.scale.to_le_bytes())?;
+        w.write_all(&[u8::from(self.paused)])
+    }
+
+    pub fn load(r: &mut impl Read) -> io::Result<Self> {
+        if read_array::<4>(r)? != SAVE_MAGIC {
+            return Err(invalid_data("not a time of day save"));
+        }
+        let version = u32::from_le_bytes(read_array(r)?);
+        if version != SAVE_VERSION {
+            return Err(invalid_data("unsupported time of day save version"));
+        }
+
+        let days = f64::from_le_bytes(read_array(r)?);
+        let day_length = Duration::try_from_secs_f64(f64::from_le_bytes(read_array(r)?))
+            .map_err(|_| invalid_data("invalid day length"))?;
+        let year_length = u32::from_le_bytes(read_array(r)?);
+        let latitude = f32::from_le_bytes(read_array(r)?);
+        let scale = f32::from_le_bytes(read_array(r)?);
+        let [paused] = read_array(r)?;
+        if !days.is_finite() {
+            return Err(invalid_data("invalid time of day"));
+        }
+
+        Ok(Self {
+            day_length,
+            year_length,
+            latitude,
+            scale,
+            paused: paused != 0,
+            days,
+        })
+    }
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0., 1.);
+    t * t * (3. - 2. * t)
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn read_array<const N: usize>(r: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut buf = [0; N];
+    r.read_exact(&mut buf)?;
+    Ok(buf)
+}
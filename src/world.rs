@@ -0,0 +1,231 @@
+//! Terrain generated from noise and streamed in around the player as chunk
+//! meshes.
+//!
+//! Positions are in world space, where `+Z` is down, so the ground starts at
+//! the surface height and runs to larger `z`.
+
+use std::collections::HashSet;
+use std::ops::Range;
+
+use glam::{IVec3, Vec3};
+use noise::{Fbm, MultiFractal, NoiseFn, Perlin};
+
+#[cfg(feature = "logging")]
+use piglog::prelude::*;
+
+use crate::profiling::zone;
+use crate::render::Renderer;
+use crate::render::block_textures::Face;
+use crate::render::chunks::mesher::{self, CHUNK_SIZE, Voxels};
+
+const SEED: u32 = 0x61ac;
+
+/// Names of the block ids the terrain is made of, as their textures are
+/// named. Id 0 is air.
+const BLOCK_NAMES: [&str; 4] = ["air", "stone", "dirt", "snow"];
+const STONE: u16 = 1;
+const DIRT: u16 = 2;
+const SNOW: u16 = 3;
+
+/// Height of the ground where the hills average out.
+const GROUND_LEVEL: f64 = 32.;
+/// Furthest the surface rises above or sinks below the ground level.
+const HILL_HEIGHT: f64 = 20.;
+/// Hills per block along each axis.
+const HILL_FREQUENCY: f64 = 1. / 256.;
+/// Blocks of dirt between the snow and the stone.
+const DIRT_DEPTH: i32 = 3;
+/// Chunk layers along `z` the surface can reach. Everything above is air
+/// and everything below solid stone, which has no faces to draw.
+const CHUNK_LAYERS: Range<i32> = 0..2;
+
+/// Chunks meshed and uploaded each frame, so streaming in does not stall.
+const CHUNKS_PER_FRAME: usize = 4;
+
+pub struct World {
+    hills: Fbm<Perlin>,
+    /// Texture layer of each face of each block id, in [`Face::ALL`] order.
+    layers: Vec<[u32; 6]>,
+    /// Chunks uploaded to the renderer, including empty ones.
+    loaded: HashSet<IVec3>,
+    /// The view distance of the last call to [`World::stream`].
+    view_distance: u32,
+}
+
+impl World {
+    pub fn new(r: &Renderer) -> Self {
+        let textures = r.block_textures();
+        Self {
+            hills: hills(),
+            layers: BLOCK_NAMES
+                .iter()
+                .map(|name| Face::ALL.map(|face| textures.layer(name, face)))
+                .collect(),
+            loaded: HashSet::new(),
+            view_distance: 0,
+        }
+    }
+
+    /// Unload chunks more than the view distance from `player` along `x` or
+    /// `y`, then load the nearest missing ones within it, a few per call. The
+    /// view distance is `max_view_distance` unless device memory is running
+    /// short.
+    pub fn stream(&mut self, player: Vec3, max_view_distance: u32, r: &mut Renderer) {
+        zone!("chunk streaming");
+        let view_distance = r
+            .memory_report()
+            .view_distance(self.view_distance, max_view_distance);
+        #[cfg(feature = "logging")]
+        if view_distance < self.view_distance {
+            piglog::warning!(
+                "Short on device memory, view distance down to {view_distance} chunks"
+            );
+        }
+        self.view_distance = view_distance;
+        let center = chunk_containing(player);
+        let in_view = |pos: IVec3| {
+            let offset = (pos - center).truncate().abs();
+            offset.max_element() <= view_distance as i32
+        };
+
+        self.loaded.retain(|&pos| {
+            let keep = in_view(pos);
+            if !keep {
+                r.remove_chunk(pos);
+            }
+            keep
+        });
+
+        let reach = view_distance as i32;
+        let mut missing: Vec<IVec3> = (-reach..=reach)
+            .flat_map(|y| (-reach..=reach).map(move |x| center.truncate() + glam::ivec2(x, y)))
+            .flat_map(|column| CHUNK_LAYERS.map(move |z| column.extend(z)))
+            .filter(|pos| !self.loaded.contains(pos))
+            .collect();
+        missing.sort_by_key(|pos| (*pos - center).truncate().length_squared());
+
+        for pos in missing.into_iter().take(CHUNKS_PER_FRAME) {
+            let voxels = self.generate(pos);
+            let origin = (pos * CHUNK_SIZE as i32).as_vec3();
+            let mesh = mesher::mesh(&voxels, origin, |block, face| {
+                self.layers[usize::from(block)][face as usize]
+            });
+            r.upload_chunk(pos, &mesh.vertices);
+            self.loaded.insert(pos);
+        }
+    }
+
+    /// World `z` of the topmost solid block in the column at `x`, `y`.
+    fn surface(&self, x: i32, y: i32) -> i32 {
+        let hills = self.hills.get([f64::from(x), f64::from(y)]);
+        (GROUND_LEVEL + HILL_HEIGHT * hills).round() as i32
+    }
+
+    /// The blocks of the chunk at `pos` and the border around it.
+    fn generate(&self, pos: IVec3) -> Voxels {
+        let origin = pos * CHUNK_SIZE as i32;
+        let mut voxels = Voxels::default();
+        let border = -1..=CHUNK_SIZE as i32;
+        for y in border.clone() {
+            for x in border.clone() {
+                let surface = self.surface(origin.x + x, origin.y + y);
+                for z in border.clone() {
+                    let block = block_at(origin.z + z, surface);
+                    voxels.set(IVec3::new(x, y, z), block);
+                }
+            }
+        }
+        voxels
+    }
+}
+
+/// The block at world height `z` in a column whose surface is at `surface`.
+const fn block_at(z: i32, surface: i32) -> u16 {
+    if z < surface {
+        0
+    } else if z == surface {
+        SNOW
+    } else if z <= surface + DIRT_DEPTH {
+        DIRT
+    } else {
+        STONE
+    }
+}
+
+fn hills() -> Fbm<Perlin> {
+    Fbm::new(SEED).set_octaves(5).set_frequency(HILL_FREQUENCY)
+}
+
+fn chunk_containing(pos: Vec3) -> IVec3 {
+    pos.floor()
+        .as_ivec3()
+        .div_euclid(IVec3::splat(CHUNK_SIZE as i32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn world() -> World {
+        World {
+            hills: hills(),
+            layers: vec![],
+            loaded: HashSet::new(),
+            view_distance: 0,
+        }
+    }
+
+    #[test]
+    fn columns_are_snow_over_dirt_over_stone() {
+        assert_eq!(block_at(9, 10), 0);
+        assert_eq!(block_at(10, 10), SNOW);
+        assert_eq!(block_at(11, 10), DIRT);
+        assert_eq!(block_at(10 + DIRT_DEPTH, 10), DIRT);
+        assert_eq!(block_at(11 + DIRT_DEPTH, 10), STONE);
+    }
+
+    #[test]
+    fn chunks_contain_negative_positions() {
+        assert_eq!(
+            chunk_containing(Vec3::new(0., 31.9, 32.)),
+            IVec3::new(0, 0, 1)
+        );
+        assert_eq!(
+            chunk_containing(Vec3::new(-0.5, -32., -33.)),
+            IVec3::new(-1, -1, -2)
+        );
+    }
+
+    #[test]
+    fn the_surface_stays_within_the_streamed_layers() {
+        let world = world();
+        let top = CHUNK_LAYERS.start * CHUNK_SIZE as i32;
+        let bottom = CHUNK_LAYERS.end * CHUNK_SIZE as i32;
+        for y in (-4096..4096).step_by(37) {
+            for x in (-4096..4096).step_by(41) {
+                let surface = world.surface(x, y);
+                assert!((top..bottom).contains(&surface), "surface at {surface}");
+            }
+        }
+    }
+
+    #[test]
+    fn borders_match_the_neighbouring_chunk() {
+        let world = world();
+        let here = world.generate(IVec3::ZERO);
+        let east = world.generate(IVec3::X);
+        let last = CHUNK_SIZE as i32 - 1;
+        for z in 0..CHUNK_SIZE as i32 {
+            for y in 0..CHUNK_SIZE as i32 {
+                assert_eq!(
+                    here.get(IVec3::new(last + 1, y, z)),
+                    east.get(IVec3::new(0, y, z))
+                );
+                assert_eq!(
+                    east.get(IVec3::new(-1, y, z)),
+                    here.get(IVec3::new(last, y, z))
+                );
+            }
+        }
+    }
+}